@@ -0,0 +1,56 @@
+// Times a distortion chain fed one sample at a time against the same chain
+// fed whole blocks, to show what the per-sample virtual calls cost.  Run with
+// `cargo bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use g2::effects::{DistortFilter, DistortMode, Filter, FilterChain};
+
+// A typical callback buffer, and enough of them for about ten seconds of
+// 48kHz audio.
+const BLOCK: usize = 512;
+const BLOCKS: usize = 1000;
+
+fn chain() -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(DistortFilter::new(4.0, 0.8, DistortMode::Hard)));
+    chain.push(Box::new(DistortFilter::new(2.0, 0.7, DistortMode::Soft)));
+    chain
+}
+
+// The fastest of a few runs, to keep scheduling noise out of it.
+fn time<F: FnMut(&mut [f32])>(mut run: F) -> Duration {
+    let mut buffer: Vec<f32> = (0..BLOCK).map(|n| (n as f32 * 0.01).sin()).collect();
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..BLOCKS {
+                run(black_box(&mut buffer));
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mut filter: Box<dyn Filter> = Box::new(chain());
+    let per_sample = time(|buffer| {
+        for sample in buffer.iter_mut() {
+            *sample = filter.process(*sample);
+        }
+    });
+
+    let mut filter: Box<dyn Filter> = Box::new(chain());
+    let per_block = time(|buffer| filter.process_block(buffer));
+
+    let samples = (BLOCK * BLOCKS) as f64;
+    let ns = |elapsed: Duration| elapsed.as_nanos() as f64 / samples;
+    println!("process:       {:6.2} ns/sample", ns(per_sample));
+    println!("process_block: {:6.2} ns/sample", ns(per_block));
+    println!(
+        "{:.1}x faster by the block",
+        per_sample.as_secs_f64() / per_block.as_secs_f64()
+    );
+}
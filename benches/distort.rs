@@ -0,0 +1,50 @@
+// Times DistortFilter over a large buffer one sample at a time against
+// process_block, which uses SSE when built with `--features simd`.  Run with
+// `cargo bench --bench distort --features simd`, and again without the
+// feature for the plain loops.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use g2::effects::{DistortFilter, DistortMode, Filter};
+
+const SAMPLES: usize = 1 << 20;
+
+// The fastest of a few runs, to keep scheduling noise out of it.
+fn time<F: FnMut(&mut [f32])>(mut run: F) -> Duration {
+    let mut buffer: Vec<f32> = (0..SAMPLES).map(|n| (n as f32 * 0.01).sin()).collect();
+    (0..10)
+        .map(|_| {
+            let start = Instant::now();
+            run(black_box(&mut buffer));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    println!(
+        "simd feature {}",
+        if cfg!(feature = "simd") { "on" } else { "off" }
+    );
+    for mode in [DistortMode::Hard, DistortMode::Soft, DistortMode::Foldback] {
+        let mut filter = DistortFilter::new(4.0, 0.8, mode);
+        let per_sample = time(|buffer| {
+            for sample in buffer.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        });
+        let mut filter = DistortFilter::new(4.0, 0.8, mode);
+        let per_block = time(|buffer| filter.process_block(buffer));
+
+        let ns = |elapsed: Duration| elapsed.as_nanos() as f64 / SAMPLES as f64;
+        println!(
+            "{:?}: process {:.3} ns/sample, process_block {:.3} ns/sample, {:.1}x",
+            mode,
+            ns(per_sample),
+            ns(per_block),
+            per_sample.as_secs_f64() / per_block.as_secs_f64()
+        );
+    }
+}
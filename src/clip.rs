@@ -0,0 +1,54 @@
+// Watching the output for sustained clipping, so a long unattended run can
+// give up rather than keep recording distorted audio.
+
+// Counts clipped samples over consecutive windows of a fixed length, and
+// trips once a whole window clips more often than the threshold.  A short
+// burst of clipping that stays under the threshold is let through.
+pub struct ClipDetector {
+    window: usize,
+
+    // The fraction of a window's samples that may clip, from 0.0 to 1.0.
+    threshold: f32,
+
+    // Samples seen, and how many of them clipped, in the current window.
+    seen: usize,
+    clipped: usize,
+
+    tripped: bool,
+}
+
+impl ClipDetector {
+    // window is in samples, counting every channel.
+    pub fn new(window: usize, threshold: f32) -> ClipDetector {
+        ClipDetector {
+            window: window.max(1),
+            threshold,
+            seen: 0,
+            clipped: 0,
+            tripped: false,
+        }
+    }
+
+    // Adds a buffer of output.  Returns true once any window has clipped too
+    // much, and keeps returning true from then on.
+    pub fn update(&mut self, data: &[f32]) -> bool {
+        for sample in data {
+            if sample.abs() >= 1.0 {
+                self.clipped += 1;
+            }
+            self.seen += 1;
+            if self.seen == self.window {
+                if self.clipped as f32 > self.threshold * self.window as f32 {
+                    self.tripped = true;
+                }
+                self.seen = 0;
+                self.clipped = 0;
+            }
+        }
+        self.tripped
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+}
@@ -0,0 +1,2951 @@
+// The effects themselves, along with the traits that tie them together.
+// Nothing in here touches audio hardware, so they can be driven from files,
+// tests, or another program entirely.
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use cpal::SampleRate;
+use ringbuf::{Consumer, Producer, RingBuffer};
+
+// Common interface for every effect.  Filters are fed from the output
+// callback, so they must be Send to cross into the audio thread.
+pub trait Filter: Send {
+    fn process(&mut self, sample: f32) -> f32;
+
+    // Processes a run of consecutive samples in place.  Behind a Box<dyn
+    // Filter> this costs one virtual call per block instead of one per
+    // sample, and filters with a cheap inner loop can override it to let the
+    // compiler unroll and vectorize that loop.
+    fn process_block(&mut self, block: &mut [f32]) {
+        for sample in block.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+
+    // How many samples late the output is, for filters like the limiter that
+    // need to see a little way ahead.  Anything mixing this filter's output
+    // with another signal delays that signal by as much to keep them lined
+    // up.
+    fn latency_frames(&self) -> usize {
+        0
+    }
+
+    // Forgets everything the filter has heard, as if it had just been made:
+    // delay lines fall silent and envelopes drop back to rest.  Settings
+    // such as gains and cutoffs are kept.
+    fn reset(&mut self) {}
+}
+
+impl<F: Filter + ?Sized> Filter for Box<F> {
+    fn process(&mut self, sample: f32) -> f32 {
+        (**self).process(sample)
+    }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        (**self).process_block(block)
+    }
+
+    fn latency_frames(&self) -> usize {
+        (**self).latency_frames()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+// Blends the unprocessed input back in with the output of another filter.
+// This only mixes the final output: feedback inside the wrapped filter (such
+// as DelayFilter's echoes) still sees the fully processed signal.  The dry
+// signal is delayed by the wrapped filter's latency, as it stood when the
+// WetDry was made, so the two stay in phase.
+pub struct WetDry<F: Filter> {
+    inner: F,
+    wet: f32,
+    dry_delay: Option<DelayLine>,
+
+    // A copy of the input for process_block, kept between calls so it's only
+    // allocated once.
+    dry: Vec<f32>,
+}
+
+impl<F: Filter> WetDry<F> {
+    pub fn new(inner: F, wet: f32) -> WetDry<F> {
+        WetDry {
+            dry_delay: DelayLine::compensating(inner.latency_frames()),
+            inner,
+            wet: wet.clamp(0.0, 1.0),
+            dry: Vec::new(),
+        }
+    }
+}
+
+impl<F: Filter> Filter for WetDry<F> {
+    fn process(&mut self, sample: f32) -> f32 {
+        let processed = self.inner.process(sample);
+        let dry = match &mut self.dry_delay {
+            Some(line) => line.delay(sample),
+            None => sample,
+        };
+        dry * (1.0 - self.wet) + processed * self.wet
+    }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        self.dry.clear();
+        self.dry.extend_from_slice(block);
+        if let Some(line) = &mut self.dry_delay {
+            for sample in self.dry.iter_mut() {
+                *sample = line.delay(*sample);
+            }
+        }
+        self.inner.process_block(block);
+        for (sample, dry) in block.iter_mut().zip(&self.dry) {
+            *sample = dry * (1.0 - self.wet) + *sample * self.wet;
+        }
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.inner.latency_frames()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        if let Some(line) = &mut self.dry_delay {
+            line.clear();
+        }
+    }
+}
+
+// A fixed-length delay line.  Each read() pops the sample written
+// delay_frames writes ago, and must be paired with a write() so the line stays
+// full.
+struct DelayLine {
+    producer: Producer<f32>,
+    consumer: Consumer<f32>,
+}
+
+impl DelayLine {
+    fn new(delay_frames: usize) -> DelayLine {
+        let buffer = RingBuffer::new(delay_frames);
+        let (mut producer, consumer) = buffer.split();
+
+        while !producer.is_full() {
+            producer.push(0.0).expect("Can't fill buffer?");
+        }
+
+        DelayLine { producer, consumer }
+    }
+
+    // A line for holding one signal back to line up with another that's
+    // frames late, or None if it isn't late at all.
+    fn compensating(frames: usize) -> Option<DelayLine> {
+        (frames > 0).then(|| DelayLine::new(frames))
+    }
+
+    // Fills the line back up with silence.
+    fn clear(&mut self) {
+        while self.consumer.pop().is_some() {}
+        while !self.producer.is_full() {
+            self.write(0.0);
+        }
+    }
+
+    // Writes sample and returns the one that comes out the other end.
+    fn delay(&mut self, sample: f32) -> f32 {
+        let delayed = self.read();
+        self.write(sample);
+        delayed
+    }
+
+    fn read(&mut self) -> f32 {
+        self.consumer.pop().expect("Delay buffer empty?")
+    }
+
+    fn write(&mut self, sample: f32) {
+        self.producer
+            .push(sample)
+            .expect("Unable to refill delay buffer?");
+    }
+}
+
+// A delay line that can be read at fractional delays, interpolating linearly
+// between the two nearest samples.  Delays are counted in samples back from
+// the most recent write, so read(1.0) returns the last sample written and
+// read(1.5) is halfway between that one and the one before it.
+struct FractionalDelayLine {
+    buffer: Vec<f32>,
+    write_offset: usize,
+}
+
+impl FractionalDelayLine {
+    fn new(max_delay_frames: usize) -> FractionalDelayLine {
+        FractionalDelayLine {
+            buffer: vec![0.0; max_delay_frames + 1],
+            write_offset: 0,
+        }
+    }
+
+    fn tap(&self, reverse_offset: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[(self.write_offset + len - reverse_offset % len) % len]
+    }
+
+    fn read(&self, delay: f32) -> f32 {
+        let delay = delay.max(1.0);
+        let whole = delay.floor();
+        let fraction = delay - whole;
+
+        let near = self.tap(whole as usize);
+        let far = self.tap(whole as usize + 1);
+        near + (far - near) * fraction
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+
+    fn write(&mut self, sample: f32) {
+        self.buffer[self.write_offset] = sample;
+        self.write_offset += 1;
+        if self.write_offset >= self.buffer.len() {
+            self.write_offset = 0;
+        }
+    }
+}
+
+// An f32 that another thread can change while a filter is running, such as
+// from the keyboard.  It's stored as its bit pattern in an atomic, so reading
+// it from the audio callback never blocks.
+#[derive(Clone, Debug)]
+pub struct SharedParam(Arc<AtomicU32>);
+
+impl SharedParam {
+    pub fn new(value: f32) -> SharedParam {
+        SharedParam(Arc::new(AtomicU32::new(value.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// Like SharedParam, but an on/off switch.
+#[derive(Clone, Debug)]
+pub struct SharedFlag(Arc<AtomicBool>);
+
+impl SharedFlag {
+    pub fn new(value: bool) -> SharedFlag {
+        SharedFlag(Arc::new(AtomicBool::new(value)))
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, value: bool) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+// A running total of time spent, added to from the audio thread and read
+// from another.  Clones share the same total.
+#[derive(Clone, Debug, Default)]
+pub struct StageTimer(Arc<AtomicU64>);
+
+impl StageTimer {
+    pub fn new() -> StageTimer {
+        StageTimer::default()
+    }
+
+    pub fn add(&self, elapsed: Duration) {
+        self.0
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+pub struct DelayFilter {
+    decay: SharedParam,
+    line: DelayLine,
+
+    // One-pole low-pass on the feedback, so each echo is duller than the
+    // last.  0.0 leaves the echoes at full brightness.
+    damping: f32,
+    filter_state: f32,
+}
+
+#[derive(Debug)]
+pub struct InvalidDecay(f32);
+
+impl fmt::Display for InvalidDecay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Delay decay {} must be at least 0.0 and less than 1.0, or the echoes never die out",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidDecay {}
+
+impl DelayFilter {
+    pub fn new(delay_frames: usize, decay: f32, damping: f32) -> Result<DelayFilter, InvalidDecay> {
+        DelayFilter::shared(delay_frames, SharedParam::new(decay), damping)
+    }
+
+    // Like new, but the decay can be changed while the delay runs.  Whoever
+    // changes it is responsible for keeping it below 1.0.
+    pub fn shared(
+        delay_frames: usize,
+        decay: SharedParam,
+        damping: f32,
+    ) -> Result<DelayFilter, InvalidDecay> {
+        if !(0.0..1.0).contains(&decay.get()) {
+            return Err(InvalidDecay(decay.get()));
+        }
+
+        Ok(DelayFilter {
+            decay,
+            line: DelayLine::new(delay_frames),
+            damping: damping.clamp(0.0, 1.0),
+            filter_state: 0.0,
+        })
+    }
+}
+
+// Input louder than full scale can still build up in the feedback loop, so it
+// is clamped to this level as a last line of defence.
+const DELAY_LIMIT: f32 = 4.0;
+
+impl Filter for DelayFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let last = self.line.read();
+        self.filter_state = last * (1.0 - self.damping) + self.filter_state * self.damping;
+        let result =
+            (sample + self.filter_state * self.decay.get()).clamp(-DELAY_LIMIT, DELAY_LIMIT);
+        self.line.write(result);
+
+        result
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.filter_state = 0.0;
+    }
+}
+
+// Waveforms for sweeping a delay.  Each starts at its peak and covers one
+// cycle as the phase goes from 0 to 2pi.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfoShape {
+    // Smooth, lingering at either end of the sweep.
+    Sine,
+
+    // Sweeps at a constant rate, turning sharply at each end.
+    Triangle,
+
+    // Sweeps one way at a constant rate, then jumps back.
+    Saw,
+}
+
+impl LfoShape {
+    // The waveform's value, between -1.0 and 1.0, at phase radians.
+    pub fn at(self, phase: f32) -> f32 {
+        let cycle = (phase / (2.0 * PI)).rem_euclid(1.0);
+        match self {
+            LfoShape::Sine => phase.cos(),
+            LfoShape::Triangle => 4.0 * (cycle - 0.5).abs() - 1.0,
+            LfoShape::Saw => 1.0 - 2.0 * cycle,
+        }
+    }
+}
+
+pub struct FlangeFilter {
+    decay: f32,
+    amplitude: f32,
+    shape: LfoShape,
+
+    // convert from time in samples to an input to cosine such that we hit
+    // 2pi as t hits sample_rate * frequency
+    offset_coefficient: f32,
+
+    // elapsed time in samples, wrapped to one LFO period so it never gets
+    // big enough for f32 to lose track of single samples
+    t: f32,
+    period: f32,
+
+    line: FractionalDelayLine,
+}
+
+impl FlangeFilter {
+    pub fn new(
+        buffer_size: usize,
+        sample_rate: SampleRate,
+        frequency: f32,
+        amplitude: f32,
+        decay: f32,
+        shape: LfoShape,
+    ) -> FlangeFilter {
+        let SampleRate(sr) = sample_rate;
+
+        let offset_coefficient = PI / (2.0 * frequency * sr as f32);
+
+        FlangeFilter {
+            decay,
+            amplitude,
+            shape,
+            offset_coefficient,
+            t: 0.0,
+            period: 2.0 * PI / offset_coefficient,
+            line: FractionalDelayLine::new(buffer_size),
+        }
+    }
+
+    // The delay is left fractional and interpolated when read, so the sweep
+    // glides between samples instead of stepping.
+    fn offset(&self, t: f32) -> f32 {
+        let f = t * self.offset_coefficient;
+        (self.shape.at(f) + 1.0) * self.amplitude + 1.0
+    }
+
+    // The delay, in frames, that the next sample will be mixed from.
+    pub fn current_delay(&self) -> f32 {
+        self.offset(self.t)
+    }
+}
+
+impl Filter for FlangeFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let reverse_offset = self.offset(self.t);
+
+        let last = self.line.read(reverse_offset);
+
+        let result = sample + last * self.decay;
+
+        self.line.write(result);
+
+        self.t += 1.0;
+        if self.t >= self.period {
+            self.t -= self.period;
+        }
+
+        result
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.t = 0.0;
+    }
+}
+
+// Interface for effects that need to see every channel of a frame at once,
+// such as those that move sound between channels.  The frame is processed in
+// place.
+pub trait FrameFilter: Send {
+    fn process_frame(&mut self, frame: &mut [f32]);
+
+    // Processes a buffer of interleaved frames, channels samples each, in
+    // place.
+    fn process_block(&mut self, frames: &mut [f32], channels: usize) {
+        for frame in frames.chunks_mut(channels.max(1)) {
+            self.process_frame(frame);
+        }
+    }
+
+    // As Filter::reset.
+    fn reset(&mut self) {}
+}
+
+// Runs a series of frame filters, feeding the output of each into the next.
+#[derive(Default)]
+pub struct FrameChain {
+    filters: Vec<Box<dyn FrameFilter>>,
+}
+
+impl FrameChain {
+    pub fn new() -> FrameChain {
+        FrameChain {
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, filter: Box<dyn FrameFilter>) {
+        self.filters.push(filter);
+    }
+}
+
+impl FrameFilter for FrameChain {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        for filter in self.filters.iter_mut() {
+            filter.process_frame(frame);
+        }
+    }
+
+    fn process_block(&mut self, frames: &mut [f32], channels: usize) {
+        for filter in self.filters.iter_mut() {
+            filter.process_block(frames, channels);
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+// Adapts a per-sample Filter to a FrameFilter by running an independent
+// instance on each channel, so stateful effects don't mix up samples from
+// different channels.
+pub struct PerChannel<F: Filter> {
+    filters: Vec<F>,
+
+    // One channel's samples at a time, pulled out of an interleaved block.
+    // It grows to the largest block seen and stays there, so the audio
+    // callback only allocates the first time.
+    scratch: Vec<f32>,
+}
+
+impl<F: Filter> PerChannel<F> {
+    // Takes one filter for each channel.
+    pub fn new(filters: Vec<F>) -> PerChannel<F> {
+        PerChannel {
+            filters,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl<F: Filter> FrameFilter for PerChannel<F> {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        for (sample, filter) in frame.iter_mut().zip(self.filters.iter_mut()) {
+            *sample = filter.process(*sample);
+        }
+    }
+
+    fn process_block(&mut self, frames: &mut [f32], channels: usize) {
+        let channels = channels.max(1);
+        for (channel, filter) in self.filters.iter_mut().enumerate().take(channels) {
+            self.scratch.clear();
+            self.scratch
+                .extend(frames.iter().skip(channel).step_by(channels).copied());
+            filter.process_block(&mut self.scratch);
+            for (sample, processed) in frames
+                .iter_mut()
+                .skip(channel)
+                .step_by(channels)
+                .zip(&self.scratch)
+            {
+                *sample = *processed;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+// Stereo delay whose echoes bounce back and forth between left and right.
+// Each line carries one channel's signal over to the other side, and feeds
+// back into the line going the opposite way.  Only the first two channels of
+// a frame are touched, and mono frames pass through unchanged.
+pub struct PingPongDelay {
+    decay: f32,
+    to_left: DelayLine,
+    to_right: DelayLine,
+}
+
+impl PingPongDelay {
+    pub fn new(delay_frames: usize, decay: f32) -> Result<PingPongDelay, InvalidDecay> {
+        if !(0.0..1.0).contains(&decay) {
+            return Err(InvalidDecay(decay));
+        }
+
+        Ok(PingPongDelay {
+            decay,
+            to_left: DelayLine::new(delay_frames),
+            to_right: DelayLine::new(delay_frames),
+        })
+    }
+}
+
+impl FrameFilter for PingPongDelay {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        if frame.len() < 2 {
+            return;
+        }
+
+        let (left, right) = (frame[0], frame[1]);
+        let to_left = self.to_left.read();
+        let to_right = self.to_right.read();
+
+        frame[0] = left + to_left;
+        frame[1] = right + to_right;
+
+        self.to_right.write(self.decay * (left + to_left));
+        self.to_left.write(self.decay * (right + to_right));
+    }
+
+    fn reset(&mut self) {
+        self.to_left.clear();
+        self.to_right.clear();
+    }
+}
+
+// Narrows or widens the stereo image by scaling the difference between the
+// left and right channels.  Width 0.0 collapses to mono, 1.0 leaves the
+// signal alone, and anything above that exaggerates the separation.  Like
+// PingPongDelay, it only touches the first two channels.
+pub struct StereoWidth {
+    width: f32,
+}
+
+impl StereoWidth {
+    pub fn new(width: f32) -> StereoWidth {
+        StereoWidth { width }
+    }
+}
+
+impl FrameFilter for StereoWidth {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        if frame.len() < 2 {
+            return;
+        }
+
+        let mid = 0.5 * (frame[0] + frame[1]);
+        let side = 0.5 * (frame[0] - frame[1]) * self.width;
+        frame[0] = mid + side;
+        frame[1] = mid - side;
+    }
+}
+
+// Swaps the left and right channels, for when the cables are the wrong way
+// round.  Mono frames pass through, and beyond stereo only the first two
+// channels move.
+pub struct SwapChannels;
+
+impl FrameFilter for SwapChannels {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        if frame.len() >= 2 {
+            frame.swap(0, 1);
+        }
+    }
+}
+
+// Sweeps the signal from side to side with a sine LFO.  The first two
+// channels are mixed down and placed between them with constant-power gains,
+// so the sum of their powers, and with it the loudness, stays the same all
+// the way across.  depth 1.0 swings fully left and right; less keeps it
+// nearer the middle.  Mono frames pass through unchanged.
+pub struct AutoPanner {
+    depth: f32,
+
+    // As in Tremolo.
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl AutoPanner {
+    pub fn new(rate_hz: f32, depth: f32, sample_rate: SampleRate) -> AutoPanner {
+        let SampleRate(sr) = sample_rate;
+        AutoPanner {
+            depth: depth.clamp(0.0, 1.0),
+            phase_increment: 2.0 * PI * rate_hz / sr as f32,
+            phase: 0.0,
+        }
+    }
+
+    // How far it swings, after clamping to 0.0 - 1.0.
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+}
+
+impl FrameFilter for AutoPanner {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        if frame.len() < 2 {
+            return;
+        }
+
+        // From 0 (hard left) to pi/2 (hard right), starting in the middle.
+        let angle = (1.0 + self.depth * self.phase.sin()) * PI / 4.0;
+        let mono = 0.5 * (frame[0] + frame[1]);
+        frame[0] = mono * angle.cos();
+        frame[1] = mono * angle.sin();
+
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+// Holds individual channels back by a whole number of frames, to line up
+// mics at different distances from the source.  Channels with no delay, or
+// past the end of the list, pass straight through.
+pub struct ChannelDelay {
+    lines: Vec<Option<DelayLine>>,
+}
+
+impl ChannelDelay {
+    // Takes each channel's delay in frames, in channel order.
+    pub fn new(delays: &[usize]) -> ChannelDelay {
+        ChannelDelay {
+            lines: delays
+                .iter()
+                .map(|&frames| DelayLine::compensating(frames))
+                .collect(),
+        }
+    }
+}
+
+impl FrameFilter for ChannelDelay {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        for (sample, line) in frame.iter_mut().zip(&mut self.lines) {
+            if let Some(line) = line {
+                *sample = line.delay(*sample);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self.lines.iter_mut().flatten() {
+            line.clear();
+        }
+    }
+}
+
+// Runs a series of filters, feeding the output of each into the next.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+
+    // Whether each stage runs.  A disabled stage passes its input through,
+    // and is reset when it's switched back on so it doesn't pick up where
+    // it left off.
+    enabled: Vec<SharedFlag>,
+
+    // How far each stage is faded in, from 0.0 (bypassed) to 1.0 (running),
+    // and how far it moves each sample toward its switch.
+    mix: Vec<f32>,
+    fade_step: f32,
+
+    // When set, only this stage runs and the rest are bypassed.
+    solo: Option<usize>,
+
+    // One per stage when profiling, otherwise empty.
+    timers: Vec<StageTimer>,
+}
+
+impl FilterChain {
+    pub fn new() -> FilterChain {
+        FilterChain {
+            filters: Vec::new(),
+            enabled: Vec::new(),
+            mix: Vec::new(),
+            fade_step: 1.0,
+            solo: None,
+            timers: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+        self.enabled.push(SharedFlag::new(true));
+        self.mix.push(1.0);
+    }
+
+    // Makes stages fade in and out over time_ms when their switches flip,
+    // rather than cutting over between one sample and the next.  The stage
+    // keeps running while it fades, and its output is blended with its input.
+    pub fn set_fade(&mut self, time_ms: f32, sample_rate: SampleRate) {
+        self.fade_step = ramp_step(time_ms, sample_rate);
+    }
+
+    // Turns one stage on or off.  Does nothing past the end of the chain.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(flag) = self.enabled.get(index) {
+            flag.set(enabled);
+        }
+    }
+
+    // The switch for each stage, in order, for flipping from another thread.
+    pub fn switches(&self) -> Vec<SharedFlag> {
+        self.enabled.clone()
+    }
+
+    // Makes this chain's stages follow another chain's switches, so that one
+    // switch covers the same stage on every channel.  Each stage starts out
+    // fully on or off to match, without fading.
+    pub fn share_switches(&mut self, switches: &[SharedFlag]) {
+        for ((enabled, mix), switch) in self.enabled.iter_mut().zip(&mut self.mix).zip(switches) {
+            *enabled = switch.clone();
+            *mix = if switch.get() { 1.0 } else { 0.0 };
+        }
+    }
+
+    // Adds the time each stage spends in process_block to its timer, one
+    // timer per stage.  Timing whole blocks keeps the cost down to a couple
+    // of clock reads per stage per block; samples fed in one at a time
+    // through process aren't timed at all.
+    pub fn set_timers(&mut self, timers: &[StageTimer]) {
+        self.timers = timers.to_vec();
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // Bypasses every stage but the one at index, or with None runs them all
+    // again.  An index past the end of the chain passes the input through.
+    pub fn solo(&mut self, index: Option<usize>) {
+        self.solo = index;
+    }
+
+    // Whether a stage is switched on and not left out by a solo.  false past
+    // the end of the chain.
+    pub fn is_running(&self, index: usize) -> bool {
+        self.enabled.get(index).is_some_and(SharedFlag::get)
+            && self.solo.is_none_or(|solo| solo == index)
+    }
+
+    // Charges the time since start to a stage's timer, if it's being timed.
+    fn stop_timer(&self, index: usize, start: Option<Instant>) {
+        if let (Some(start), Some(timer)) = (start, self.timers.get(index)) {
+            timer.add(start.elapsed());
+        }
+    }
+}
+
+impl Filter for FilterChain {
+    // Counts bypassed stages too, so the figure holds still while stages are
+    // switched on and off.
+    fn latency_frames(&self) -> usize {
+        match self.solo {
+            Some(index) => self.filters.get(index).map_or(0, |f| f.latency_frames()),
+            None => self.filters.iter().map(|f| f.latency_frames()).sum(),
+        }
+    }
+
+    // Stages that are fully on or fully off handle the whole block at once;
+    // only one partway through a fade has to go sample by sample.
+    fn process_block(&mut self, block: &mut [f32]) {
+        if let Some(index) = self.solo {
+            let start = (!self.timers.is_empty()).then(Instant::now);
+            if let Some(filter) = self.filters.get_mut(index) {
+                filter.process_block(block);
+                self.stop_timer(index, start);
+            }
+            return;
+        }
+
+        for index in 0..self.filters.len() {
+            let start = (!self.timers.is_empty()).then(Instant::now);
+            let filter = &mut self.filters[index];
+            let mix = &mut self.mix[index];
+            let target = if self.enabled[index].get() { 1.0 } else { 0.0 };
+            if *mix == target {
+                if target == 1.0 {
+                    filter.process_block(block);
+                    self.stop_timer(index, start);
+                }
+                continue;
+            }
+
+            if *mix == 0.0 {
+                filter.reset();
+            }
+            for sample in block.iter_mut() {
+                *mix = if target == 1.0 {
+                    (*mix + self.fade_step).min(1.0)
+                } else {
+                    (*mix - self.fade_step).max(0.0)
+                };
+                if *mix > 0.0 {
+                    let processed = filter.process(*sample);
+                    *sample += *mix * (processed - *sample);
+                }
+            }
+            self.stop_timer(index, start);
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        match self.solo {
+            Some(index) => self
+                .filters
+                .get_mut(index)
+                .map_or(sample, |filter| filter.process(sample)),
+            None => {
+                let mut sample = sample;
+                let stages = self
+                    .filters
+                    .iter_mut()
+                    .zip(&self.enabled)
+                    .zip(&mut self.mix);
+                for ((filter, enabled), mix) in stages {
+                    if enabled.get() && *mix == 0.0 {
+                        filter.reset();
+                    }
+                    *mix = if enabled.get() {
+                        (*mix + self.fade_step).min(1.0)
+                    } else {
+                        (*mix - self.fade_step).max(0.0)
+                    };
+                    if *mix >= 1.0 {
+                        sample = filter.process(sample);
+                    } else if *mix > 0.0 {
+                        let processed = filter.process(sample);
+                        sample += *mix * (processed - sample);
+                    }
+                }
+                sample
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+// Switches from one filter to another without a click.  After switch_to, the
+// old and new filters both run for time_ms while the output blends linearly
+// from one to the other, and then the old filter is dropped.
+pub struct Crossfade {
+    current: Box<dyn Filter>,
+
+    // The filter being faded out, while a switch is under way.
+    outgoing: Option<Box<dyn Filter>>,
+
+    // How far the switch has got, from 0.0 (all old) to 1.0 (all new).
+    mix: f32,
+    step: f32,
+}
+
+impl Crossfade {
+    pub fn new(filter: Box<dyn Filter>, time_ms: f32, sample_rate: SampleRate) -> Crossfade {
+        Crossfade {
+            current: filter,
+            outgoing: None,
+            mix: 1.0,
+            step: ramp_step(time_ms, sample_rate),
+        }
+    }
+
+    // Starts fading over to filter.  Switching again before the last switch
+    // has finished drops the filter that was already fading out, so the
+    // output jumps by whatever that filter was still contributing.
+    pub fn switch_to(&mut self, mut filter: Box<dyn Filter>) {
+        filter.reset();
+        self.outgoing = Some(std::mem::replace(&mut self.current, filter));
+        self.mix = 0.0;
+    }
+
+    pub fn is_switching(&self) -> bool {
+        self.outgoing.is_some()
+    }
+}
+
+impl Filter for Crossfade {
+    fn process(&mut self, sample: f32) -> f32 {
+        let new = self.current.process(sample);
+        let Some(outgoing) = &mut self.outgoing else {
+            return new;
+        };
+
+        let old = outgoing.process(sample);
+        self.mix = (self.mix + self.step).min(1.0);
+        if self.mix >= 1.0 {
+            self.outgoing = None;
+        }
+        old + self.mix * (new - old)
+    }
+
+    // Finishes any switch under way, since the outgoing filter has nothing
+    // worth fading out any more.
+    fn reset(&mut self) {
+        self.current.reset();
+        self.outgoing = None;
+        self.mix = 1.0;
+    }
+}
+
+// Feeds the same input to several filters side by side and sums their
+// outputs, each scaled by its own gain.  Every branch keeps its own state, so
+// feedback inside one (a delay's echoes, say) never reaches the others.
+// Branches with less latency than the slowest are delayed to match it.
+#[derive(Default)]
+pub struct ParallelChain {
+    branches: Vec<Box<dyn Filter>>,
+    gains: Vec<f32>,
+    delays: Vec<Option<DelayLine>>,
+
+    // The input and one branch's output for process_block, kept between
+    // calls so they're only allocated once.
+    input: Vec<f32>,
+    branch: Vec<f32>,
+}
+
+impl ParallelChain {
+    pub fn new() -> ParallelChain {
+        ParallelChain::default()
+    }
+
+    pub fn push(&mut self, branch: Box<dyn Filter>, gain: f32) {
+        self.branches.push(branch);
+        self.gains.push(gain);
+
+        let latency = self.latency_frames();
+        self.delays = self
+            .branches
+            .iter()
+            .map(|branch| DelayLine::compensating(latency - branch.latency_frames()))
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+}
+
+impl Filter for ParallelChain {
+    fn process(&mut self, sample: f32) -> f32 {
+        let mut sum = 0.0;
+        for ((branch, gain), delay) in self
+            .branches
+            .iter_mut()
+            .zip(&self.gains)
+            .zip(&mut self.delays)
+        {
+            let mut processed = branch.process(sample);
+            if let Some(line) = delay {
+                processed = line.delay(processed);
+            }
+            sum += processed * gain;
+        }
+        sum
+    }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        self.input.clear();
+        self.input.extend_from_slice(block);
+        block.fill(0.0);
+        let branches = self
+            .branches
+            .iter_mut()
+            .zip(&self.gains)
+            .zip(&mut self.delays);
+        for ((branch, gain), delay) in branches {
+            self.branch.clear();
+            self.branch.extend_from_slice(&self.input);
+            branch.process_block(&mut self.branch);
+            if let Some(line) = delay {
+                for sample in self.branch.iter_mut() {
+                    *sample = line.delay(*sample);
+                }
+            }
+            for (sample, processed) in block.iter_mut().zip(&self.branch) {
+                *sample += processed * gain;
+            }
+        }
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|branch| branch.latency_frames())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        for branch in &mut self.branches {
+            branch.reset();
+        }
+        for line in self.delays.iter_mut().flatten() {
+            line.clear();
+        }
+    }
+}
+
+// Distortion is easy: You magnify the signal, then clamp samples to make the wave more square.
+pub struct DistortFilter {
+    gain: SharedParam,
+
+    // Min/max value to clamp outgoing samples to.  Should be 1.0 or less.
+    saturation: f32,
+
+    mode: DistortMode,
+
+    // Set by enable_auto_gain.
+    auto_gain: Option<AutoGain>,
+}
+
+// How quickly auto gain follows changes in level.  Slow enough that it
+// doesn't pump along with the waveform or undo the distortion's own squashing
+// of individual peaks.
+const AUTO_GAIN_MS: f32 = 300.0;
+
+// Slow running mean-square levels of a filter's input and output, for scaling
+// the output back to the loudness of the input.
+struct AutoGain {
+    coefficient: f32,
+    input_level: f32,
+    output_level: f32,
+}
+
+impl AutoGain {
+    fn process(&mut self, input: f32, output: f32) -> f32 {
+        let c = self.coefficient;
+        self.input_level = input * input + c * (self.input_level - input * input);
+        self.output_level = output * output + c * (self.output_level - output * output);
+        if self.output_level > 1e-12 {
+            output * (self.input_level / self.output_level).sqrt()
+        } else {
+            output
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input_level = 0.0;
+        self.output_level = 0.0;
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistortMode {
+    // Clamp straight to saturation.  Loud and buzzy.
+    Hard,
+
+    // Bend smoothly toward saturation without ever reaching it.  This uses
+    // x / sqrt(1 + x^2) rather than tanh, because tanh rounds to exactly 1.0
+    // in f32 for fairly modest inputs.
+    Soft,
+
+    // Reflect off saturation and back down rather than stopping there,
+    // folding over again as many times as it takes to land in range.  The
+    // harder it's driven, the more the waveform folds in on itself.
+    Foldback,
+}
+
+// Folds sample back into -threshold..=threshold, as if bouncing between
+// mirrors at either end: tracing a triangle wave with a slope of 1 through
+// the origin.
+fn fold(sample: f32, threshold: f32) -> f32 {
+    if threshold <= 0.0 {
+        return 0.0;
+    }
+    let offset = (sample + threshold).rem_euclid(4.0 * threshold);
+    if offset < 2.0 * threshold {
+        offset - threshold
+    } else {
+        3.0 * threshold - offset
+    }
+}
+
+impl DistortFilter {
+    pub fn new(gain: f32, saturation: f32, mode: DistortMode) -> DistortFilter {
+        DistortFilter::shared(SharedParam::new(gain), saturation, mode)
+    }
+
+    // Like new, but the gain can be changed while the filter runs.
+    pub fn shared(gain: SharedParam, saturation: f32, mode: DistortMode) -> DistortFilter {
+        DistortFilter {
+            gain,
+            saturation,
+            mode,
+            auto_gain: None,
+        }
+    }
+
+    // Scales the output so it's about as loud as the input, whatever the
+    // gain, so sweeping the drive changes the tone rather than the volume.
+    pub fn enable_auto_gain(&mut self, sample_rate: SampleRate) {
+        self.auto_gain = Some(AutoGain {
+            coefficient: one_pole_coefficient(AUTO_GAIN_MS, sample_rate),
+            input_level: 0.0,
+            output_level: 0.0,
+        });
+    }
+}
+
+impl Filter for DistortFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let driven = sample * self.gain.get();
+        let distorted = match self.mode {
+            DistortMode::Hard => driven.clamp(-self.saturation, self.saturation),
+            DistortMode::Soft => {
+                let x = driven / self.saturation;
+                self.saturation * x / (1.0 + x * x).sqrt()
+            }
+            DistortMode::Foldback => fold(driven, self.saturation),
+        };
+        match &mut self.auto_gain {
+            Some(auto_gain) => auto_gain.process(sample, distorted),
+            None => distorted,
+        }
+    }
+
+    // The gain is read once per block, and without auto gain the loop has
+    // no state to carry between samples.
+    fn process_block(&mut self, block: &mut [f32]) {
+        if self.auto_gain.is_some() {
+            for sample in block.iter_mut() {
+                *sample = self.process(*sample);
+            }
+            return;
+        }
+
+        let gain = self.gain.get();
+        let saturation = self.saturation;
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        let block = crate::simd::distort(block, gain, saturation, self.mode);
+        match self.mode {
+            DistortMode::Hard => {
+                for sample in block.iter_mut() {
+                    *sample = (*sample * gain).clamp(-saturation, saturation);
+                }
+            }
+            DistortMode::Soft => {
+                for sample in block.iter_mut() {
+                    let x = *sample * gain / saturation;
+                    *sample = saturation * x / (1.0 + x * x).sqrt();
+                }
+            }
+            DistortMode::Foldback => {
+                for sample in block.iter_mut() {
+                    *sample = fold(*sample * gain, saturation);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(auto_gain) = &mut self.auto_gain {
+            auto_gain.reset();
+        }
+    }
+}
+
+// A second-order IIR section using the coefficient formulas from Robert
+// Bristow-Johnson's Audio EQ Cookbook.  Coefficients are stored already
+// divided through by a0.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+
+    // Previous two inputs and outputs.
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn low_pass(cutoff_hz: f32, q: f32, sample_rate: SampleRate) -> Biquad {
+        let (cos_w0, alpha) = Biquad::intermediates(cutoff_hz, q, sample_rate);
+        Biquad::new(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    fn high_pass(cutoff_hz: f32, q: f32, sample_rate: SampleRate) -> Biquad {
+        let (cos_w0, alpha) = Biquad::intermediates(cutoff_hz, q, sample_rate);
+        Biquad::new(
+            (1.0 + cos_w0) / 2.0,
+            -(1.0 + cos_w0),
+            (1.0 + cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    // Passes a band around center_hz at unity gain and rolls off either
+    // side.  Higher Q makes the band narrower.
+    fn band_pass(center_hz: f32, q: f32, sample_rate: SampleRate) -> Biquad {
+        let (cos_w0, alpha) = Biquad::intermediates(center_hz, q, sample_rate);
+        Biquad::new(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+
+    // Boosts or cuts a band around freq_hz, leaving everything far from it
+    // alone.  Q sets how wide the band is.
+    fn peaking(freq_hz: f32, q: f32, gain_db: f32, sample_rate: SampleRate) -> Biquad {
+        let (cos_w0, alpha) = Biquad::intermediates(freq_hz, q, sample_rate);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        Biquad::new(
+            1.0 + alpha * a,
+            -2.0 * cos_w0,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos_w0,
+            1.0 - alpha / a,
+        )
+    }
+
+    // Returns cos(w0) and alpha, which nearly every cookbook filter needs.
+    fn intermediates(freq_hz: f32, q: f32, sample_rate: SampleRate) -> (f32, f32) {
+        let SampleRate(sr) = sample_rate;
+        let w0 = 2.0 * PI * freq_hz / sr as f32;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    // Takes on the coefficients of another biquad while keeping this one's
+    // history, so a filter can be swept without clicking.
+    fn retune(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    fn clear(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let result = self.b0 * sample + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = sample;
+        self.y2 = self.y1;
+        self.y1 = result;
+
+        result
+    }
+}
+
+pub struct LowPassFilter {
+    biquad: Biquad,
+}
+
+impl LowPassFilter {
+    pub fn new(cutoff_hz: f32, q: f32, sample_rate: SampleRate) -> LowPassFilter {
+        LowPassFilter {
+            biquad: Biquad::low_pass(cutoff_hz, q, sample_rate),
+        }
+    }
+}
+
+impl Filter for LowPassFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.biquad.process(sample)
+    }
+
+    fn reset(&mut self) {
+        self.biquad.clear();
+    }
+}
+
+pub struct HighPassFilter {
+    biquad: Biquad,
+}
+
+impl HighPassFilter {
+    pub fn new(cutoff_hz: f32, q: f32, sample_rate: SampleRate) -> HighPassFilter {
+        HighPassFilter {
+            biquad: Biquad::high_pass(cutoff_hz, q, sample_rate),
+        }
+    }
+}
+
+impl Filter for HighPassFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.biquad.process(sample)
+    }
+
+    fn reset(&mut self) {
+        self.biquad.clear();
+    }
+}
+
+// Splits a signal in two at freq_hz with a pair of fourth-order
+// Linkwitz-Riley filters, each two Butterworth biquads in a row.  The two
+// halves add back up to the input's level at every frequency, shifted in
+// phase by a second-order allpass.
+struct Crossover {
+    low: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl Crossover {
+    fn new(freq_hz: f32, sample_rate: SampleRate) -> Crossover {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        let low = || Biquad::low_pass(freq_hz, q, sample_rate);
+        let high = || Biquad::high_pass(freq_hz, q, sample_rate);
+        Crossover {
+            low: [low(), low()],
+            high: [high(), high()],
+        }
+    }
+
+    fn clear(&mut self) {
+        for biquad in self.low.iter_mut().chain(&mut self.high) {
+            biquad.clear();
+        }
+    }
+
+    // Returns the low and high halves of sample.
+    fn split(&mut self, sample: f32) -> (f32, f32) {
+        let low = self.low[0].process(sample);
+        let high = self.high[0].process(sample);
+        (self.low[1].process(low), self.high[1].process(high))
+    }
+}
+
+// Splits the signal into low, mid and high bands at two crossover
+// frequencies, runs each band through its own filter, and sums them.  With
+// filters that leave their band alone the output has the input's level at
+// every frequency, though not its exact phase.
+pub struct Multiband {
+    low_crossover: Crossover,
+    high_crossover: Crossover,
+
+    // The low band never goes through the high crossover, so it's given the
+    // same phase shift by splitting it there too and adding it straight back
+    // up.  Otherwise it would partly cancel the mid band around low_hz.
+    low_allpass: Crossover,
+
+    bands: [Box<dyn Filter>; 3],
+
+    // Holds the quicker bands back to line up with the slowest one.
+    delays: [Option<DelayLine>; 3],
+}
+
+impl Multiband {
+    // bands are the low, mid and high filters, in that order.  low_hz should
+    // be below high_hz.
+    pub fn new(
+        low_hz: f32,
+        high_hz: f32,
+        sample_rate: SampleRate,
+        bands: [Box<dyn Filter>; 3],
+    ) -> Multiband {
+        let latency = bands.iter().map(|band| band.latency_frames()).max();
+        let delays = std::array::from_fn(|k| {
+            DelayLine::compensating(latency.unwrap_or(0) - bands[k].latency_frames())
+        });
+        Multiband {
+            low_crossover: Crossover::new(low_hz, sample_rate),
+            high_crossover: Crossover::new(high_hz, sample_rate),
+            low_allpass: Crossover::new(high_hz, sample_rate),
+            bands,
+            delays,
+        }
+    }
+}
+
+impl Filter for Multiband {
+    fn process(&mut self, sample: f32) -> f32 {
+        let (low, rest) = self.low_crossover.split(sample);
+        let (mid, high) = self.high_crossover.split(rest);
+        let (low_low, low_high) = self.low_allpass.split(low);
+
+        let mut sum = 0.0;
+        for ((band, delay), sample) in
+            self.bands
+                .iter_mut()
+                .zip(&mut self.delays)
+                .zip([low_low + low_high, mid, high])
+        {
+            let mut processed = band.process(sample);
+            if let Some(line) = delay {
+                processed = line.delay(processed);
+            }
+            sum += processed;
+        }
+        sum
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.bands
+            .iter()
+            .map(|band| band.latency_frames())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.low_crossover.clear();
+        self.high_crossover.clear();
+        self.low_allpass.clear();
+        for band in &mut self.bands {
+            band.reset();
+        }
+        for line in self.delays.iter_mut().flatten() {
+            line.clear();
+        }
+    }
+}
+
+// How many biquads make up each of Oversampler's anti-aliasing filters, and
+// how close to the original Nyquist frequency they cut off.
+const OVERSAMPLE_SECTIONS: usize = 8;
+const OVERSAMPLE_CUTOFF: f32 = 0.45;
+
+// How many frames of an impulse Oversampler watches for the peak that sets
+// its latency.  The low-passes ring for far less than this.
+const OVERSAMPLE_PROBE_FRAMES: usize = 64;
+
+// Runs a filter at factor times the sample rate, so the harmonics a nonlinear
+// effect adds have room above the original Nyquist frequency instead of
+// folding back down as aliases.  Each sample is padded out with zeros and
+// low-passed to smooth over the gaps, run through the filter, then low-passed
+// again to clear everything the original rate can't hold before only every
+// factor'th sample is kept.  Both low-passes are sixteenth-order
+// Butterworth, so even harmonics landing just past the original Nyquist
+// frequency are mostly cleared away.
+pub struct Oversampler<F: Filter> {
+    filter: F,
+    factor: usize,
+    up: [Biquad; OVERSAMPLE_SECTIONS],
+    down: [Biquad; OVERSAMPLE_SECTIONS],
+
+    // Frames, at the original rate, that the two low-passes between them
+    // hold an impulse back by.
+    filter_latency: usize,
+}
+
+impl<F: Filter> Oversampler<F> {
+    // A factor of 0 is taken as 1, which just runs the filter.
+    pub fn new(filter: F, factor: usize, sample_rate: SampleRate) -> Oversampler<F> {
+        let SampleRate(sr) = sample_rate;
+        let factor = factor.max(1);
+        let cutoff = OVERSAMPLE_CUTOFF * sr as f32;
+        let oversampled_rate = SampleRate(sr * factor as u32);
+        let sections = || {
+            std::array::from_fn(|k| {
+                let pole_angle = (2 * k + 1) as f32 * PI / (4 * OVERSAMPLE_SECTIONS) as f32;
+                Biquad::low_pass(cutoff, 0.5 / pole_angle.cos(), oversampled_rate)
+            })
+        };
+        // The Butterworth low-passes' delay has no tidy closed form, so an
+        // impulse is run through a copy of them to find where it peaks.
+        let mut probe = Oversampler {
+            filter: Gain::new(0.0),
+            factor,
+            up: sections(),
+            down: sections(),
+            filter_latency: 0,
+        };
+        let (filter_latency, _) = (0..OVERSAMPLE_PROBE_FRAMES)
+            .map(|frame| probe.process(if frame == 0 { 1.0 } else { 0.0 }).abs())
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |peak, (frame, level)| {
+                    if level > peak.1 {
+                        (frame, level)
+                    } else {
+                        peak
+                    }
+                },
+            );
+
+        Oversampler {
+            filter,
+            factor,
+            up: sections(),
+            down: sections(),
+            filter_latency,
+        }
+    }
+}
+
+impl<F: Filter> Filter for Oversampler<F> {
+    fn process(&mut self, sample: f32) -> f32 {
+        if self.factor == 1 {
+            return self.filter.process(sample);
+        }
+
+        let mut result = 0.0;
+        for step in 0..self.factor {
+            // The zeros dilute the signal, so the real sample makes up for
+            // them.
+            let padded = if step == 0 {
+                sample * self.factor as f32
+            } else {
+                0.0
+            };
+            let upsampled = self.up.iter_mut().fold(padded, |x, b| b.process(x));
+            let processed = self.filter.process(upsampled);
+            let downsampled = self.down.iter_mut().fold(processed, |x, b| b.process(x));
+            if step == 0 {
+                result = downsampled;
+            }
+        }
+        result
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.filter_latency + self.filter.latency_frames() / self.factor
+    }
+
+    fn reset(&mut self) {
+        for biquad in self.up.iter_mut().chain(&mut self.down) {
+            biquad.clear();
+        }
+        self.filter.reset();
+    }
+}
+
+// A single parametric EQ band.
+pub struct PeakingEq {
+    biquad: Biquad,
+}
+
+impl PeakingEq {
+    pub fn new(freq_hz: f32, q: f32, gain_db: f32, sample_rate: SampleRate) -> PeakingEq {
+        PeakingEq {
+            biquad: Biquad::peaking(freq_hz, q, gain_db, sample_rate),
+        }
+    }
+}
+
+impl Filter for PeakingEq {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.biquad.process(sample)
+    }
+
+    fn reset(&mut self) {
+        self.biquad.clear();
+    }
+}
+
+// Feedback comb filter with a one-pole low-pass in the feedback path, as used
+// in Freeverb.  damping of 0.0 leaves the echoes at full brightness.
+struct CombFilter {
+    line: DelayLine,
+    feedback: f32,
+    damping: f32,
+    filter_state: f32,
+}
+
+impl CombFilter {
+    fn new(delay_frames: usize, feedback: f32, damping: f32) -> CombFilter {
+        CombFilter {
+            line: DelayLine::new(delay_frames),
+            feedback,
+            damping,
+            filter_state: 0.0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.filter_state = 0.0;
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = self.line.read();
+        self.filter_state = out * (1.0 - self.damping) + self.filter_state * self.damping;
+        self.line.write(sample + self.filter_state * self.feedback);
+
+        out
+    }
+}
+
+// Schroeder allpass: passes every frequency at unit gain but shifts the
+// phase, by an amount that varies with frequency.  Long delays smear echoes
+// out, as in the reverb; a one-frame delay with a swept gain is the classic
+// phaser stage.
+pub struct AllpassFilter {
+    line: DelayLine,
+    gain: f32,
+}
+
+impl AllpassFilter {
+    pub fn new(delay_frames: usize, gain: f32) -> AllpassFilter {
+        AllpassFilter {
+            line: DelayLine::new(delay_frames.max(1)),
+            gain,
+        }
+    }
+
+    // Changes the gain without disturbing what's already in the delay line.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+impl Filter for AllpassFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let delayed = self.line.read();
+        let out = delayed - self.gain * sample;
+        self.line.write(sample + self.gain * out);
+
+        out
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+    }
+}
+
+// Schroeder reverb: parallel comb filters of mutually prime lengths build up
+// a dense echo tail, and allpasses in series diffuse it.
+pub struct ReverbFilter {
+    combs: Vec<CombFilter>,
+    allpasses: Vec<AllpassFilter>,
+    wet: f32,
+}
+
+// Delay lengths in frames at 44.1kHz, borrowed from Freeverb.
+const REVERB_COMB_FRAMES: [usize; 4] = [1557, 1617, 1491, 1422];
+const REVERB_ALLPASS_FRAMES: [usize; 2] = [225, 556];
+
+impl ReverbFilter {
+    pub fn new(room_size: f32, damping: f32, wet: f32, sample_rate: SampleRate) -> ReverbFilter {
+        let SampleRate(sr) = sample_rate;
+        let scale = |frames: usize| (frames as f32 * sr as f32 / 44100.0) as usize;
+
+        // Bigger rooms ring longer.  Keep the feedback under 1.0 so the tail
+        // always dies out.
+        let feedback = 0.7 + 0.28 * room_size.clamp(0.0, 1.0);
+
+        ReverbFilter {
+            combs: REVERB_COMB_FRAMES
+                .iter()
+                .map(|&frames| CombFilter::new(scale(frames), feedback, damping.clamp(0.0, 1.0)))
+                .collect(),
+            allpasses: REVERB_ALLPASS_FRAMES
+                .iter()
+                .map(|&frames| AllpassFilter::new(scale(frames), 0.5))
+                .collect(),
+            wet: wet.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Filter for ReverbFilter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let combed: f32 = self.combs.iter_mut().map(|comb| comb.process(sample)).sum();
+        let reverb = self
+            .allpasses
+            .iter_mut()
+            .fold(combed / self.combs.len() as f32, |s, allpass| {
+                allpass.process(s)
+            });
+
+        sample * (1.0 - self.wet) + reverb * self.wet
+    }
+
+    fn reset(&mut self) {
+        for comb in &mut self.combs {
+            comb.clear();
+        }
+        for allpass in &mut self.allpasses {
+            allpass.reset();
+        }
+    }
+}
+
+// Reverb from a recording of a real space: every output sample is the input
+// convolved with the impulse response.  This is done directly in the time
+// domain, so each sample costs one multiply-add per IR sample.  That's fine
+// for a short room (a 0.1s IR at 48kHz is under 5000 per sample) but a
+// multi-second hall will outrun the audio callback.
+pub struct ConvolutionReverb {
+    // The IR back to front, so it lines up with the history oldest first.
+    reversed_ir: Vec<f32>,
+
+    // The last ir.len() input samples, stored twice over so the window
+    // starting at position is always one contiguous slice.
+    history: Vec<f32>,
+    position: usize,
+}
+
+impl ConvolutionReverb {
+    pub fn new(impulse_response: Vec<f32>) -> ConvolutionReverb {
+        let mut reversed_ir = impulse_response;
+        if reversed_ir.is_empty() {
+            reversed_ir.push(0.0);
+        }
+        reversed_ir.reverse();
+
+        ConvolutionReverb {
+            history: vec![0.0; 2 * reversed_ir.len()],
+            reversed_ir,
+            position: 0,
+        }
+    }
+}
+
+impl Filter for ConvolutionReverb {
+    fn process(&mut self, sample: f32) -> f32 {
+        let len = self.reversed_ir.len();
+        self.history[self.position] = sample;
+        self.history[self.position + len] = sample;
+        self.position = (self.position + 1) % len;
+
+        self.history[self.position..self.position + len]
+            .iter()
+            .zip(&self.reversed_ir)
+            .map(|(x, h)| x * h)
+            .sum()
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+    }
+}
+
+// Returns the per-sample increment that ramps from 0.0 to 1.0 over time_ms.
+fn ramp_step(time_ms: f32, sample_rate: SampleRate) -> f32 {
+    let SampleRate(sr) = sample_rate;
+    let samples = time_ms * sr as f32 / 1000.0;
+    if samples > 1.0 {
+        1.0 / samples
+    } else {
+        1.0
+    }
+}
+
+// Mutes the signal while it's quiet.  The gate opens once the level reaches
+// open_threshold and only closes again when it falls below the lower
+// close_threshold, so a level hovering around either one doesn't make it
+// chatter.  The gain ramps rather than switching so opening and closing the
+// gate doesn't click.  As an expander, closing only turns the signal down.
+pub struct NoiseGate {
+    open_threshold: f32,
+    close_threshold: f32,
+    open: bool,
+    attack_step: f32,
+    release_step: f32,
+
+    // Peak detector on the incoming signal.
+    envelope: EnvelopeFollower,
+
+    gain: f32,
+
+    // With an expansion ratio, a closed gate turns the signal down instead
+    // of muting it: every dB below the threshold comes out ratio dB below.
+    expansion_ratio: Option<f32>,
+}
+
+// How long the peak detector takes to fall by 1/e.  Long enough to ride over
+// the troughs of a 50Hz wave.
+const GATE_DETECTOR_MS: f32 = 20.0;
+
+impl NoiseGate {
+    // A close_threshold above open_threshold is lowered to match it.
+    pub fn new(
+        open_threshold: f32,
+        close_threshold: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: SampleRate,
+    ) -> NoiseGate {
+        NoiseGate {
+            open_threshold,
+            close_threshold: close_threshold.min(open_threshold),
+            open: false,
+            attack_step: ramp_step(attack_ms, sample_rate),
+            release_step: ramp_step(release_ms, sample_rate),
+            envelope: EnvelopeFollower::new(0.0, GATE_DETECTOR_MS, sample_rate),
+            gain: 0.0,
+            expansion_ratio: None,
+        }
+    }
+
+    // Makes this a downward expander.  A ratio of 1.0 leaves the signal
+    // alone, and the higher it goes the closer it gets to a gate; anything
+    // below 1.0 is raised to it.
+    pub fn enable_expansion(&mut self, ratio: f32) {
+        self.expansion_ratio = Some(ratio.max(1.0));
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // The gain a closed gate heads for at this level.
+    fn closed_gain(&self, level: f32) -> f32 {
+        match self.expansion_ratio {
+            Some(ratio) if self.open_threshold > 0.0 => {
+                (level / self.open_threshold).min(1.0).powf(ratio - 1.0)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+impl Filter for NoiseGate {
+    fn process(&mut self, sample: f32) -> f32 {
+        let level = self.envelope.process(sample);
+        if level >= self.open_threshold {
+            self.open = true;
+        } else if level < self.close_threshold {
+            self.open = false;
+        }
+
+        let target = if self.open {
+            1.0
+        } else {
+            self.closed_gain(level)
+        };
+        if self.gain < target {
+            self.gain = (self.gain + self.attack_step).min(target);
+        } else {
+            self.gain = (self.gain - self.release_step).max(target);
+        }
+
+        sample * self.gain
+    }
+
+    fn reset(&mut self) {
+        self.envelope.reset();
+        self.open = false;
+        self.gain = 0.0;
+    }
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * linear.max(1e-10).log10()
+}
+
+// Returns the coefficient for a one-pole smoother that covers 1 - 1/e of the
+// distance to its target in time_ms.
+fn one_pole_coefficient(time_ms: f32, sample_rate: SampleRate) -> f32 {
+    let SampleRate(sr) = sample_rate;
+    let samples = time_ms * sr as f32 / 1000.0;
+    if samples > 0.0 {
+        (-1.0 / samples).exp()
+    } else {
+        0.0
+    }
+}
+
+// Tracks how loud a signal is.  The level moves toward rising input with the
+// attack time constant and falls back with the release time constant, so a
+// short attack catches transients and a longer release rides over the gaps
+// between cycles.
+pub struct EnvelopeFollower {
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    envelope: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(attack_ms: f32, release_ms: f32, sample_rate: SampleRate) -> EnvelopeFollower {
+        EnvelopeFollower {
+            attack_coefficient: one_pole_coefficient(attack_ms, sample_rate),
+            release_coefficient: one_pole_coefficient(release_ms, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    // Follows one more sample, returning the new smoothed magnitude.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let level = sample.abs();
+        let coefficient = if level > self.envelope {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.envelope = level + coefficient * (self.envelope - level);
+        self.envelope
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+}
+
+// Turns down anything louder than threshold_db.  Above the threshold, every
+// ratio dB of input level becomes one dB of output level.
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+
+    // Width of the soft knee, centered on the threshold.  0.0 is a hard knee.
+    knee_db: f32,
+
+    makeup_gain: f32,
+    envelope: EnvelopeFollower,
+}
+
+impl Compressor {
+    pub fn new(
+        threshold_db: f32,
+        ratio: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        makeup_gain_db: f32,
+        sample_rate: SampleRate,
+    ) -> Compressor {
+        Compressor {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            knee_db: 0.0,
+            makeup_gain: db_to_linear(makeup_gain_db),
+            envelope: EnvelopeFollower::new(attack_ms, release_ms, sample_rate),
+        }
+    }
+
+    // Eases into compression over knee_db around the threshold instead of
+    // starting at full ratio the moment the level crosses it.
+    pub fn set_knee(&mut self, knee_db: f32) {
+        self.knee_db = knee_db.max(0.0);
+    }
+
+    // How many dB to turn a signal at level_db down by.  Within the knee the
+    // reduction follows a parabola that meets the straight lines either side
+    // of it at the same slope, so the curve has no corner.
+    pub fn gain_reduction_db(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+        let slope = 1.0 - 1.0 / self.ratio;
+        let half_knee = self.knee_db / 2.0;
+        if over <= -half_knee {
+            0.0
+        } else if over < half_knee {
+            slope * (over + half_knee).powi(2) / (2.0 * self.knee_db)
+        } else {
+            over * slope
+        }
+    }
+
+    // Compresses sample by however much key calls for, rather than by the
+    // level of sample itself.  This is how a voice on key ducks music.
+    pub fn process_keyed(&mut self, sample: f32, key: f32) -> f32 {
+        sample * self.key_gain(key)
+    }
+
+    // Runs key through the detector and returns the gain to apply.
+    fn key_gain(&mut self, key: f32) -> f32 {
+        let level = self.envelope.process(key);
+        let reduction = self.gain_reduction_db(linear_to_db(level));
+        db_to_linear(-reduction) * self.makeup_gain
+    }
+}
+
+impl Filter for Compressor {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.process_keyed(sample, sample)
+    }
+
+    fn reset(&mut self) {
+        self.envelope.reset();
+    }
+}
+
+// Where a SidechainCompressor's key signal comes from.
+pub enum Sidechain {
+    // One channel of the frame.  That channel passes through untouched.
+    Channel(usize),
+
+    // A recording, one sample per frame from the start.  Once it runs out
+    // the key is silent.
+    Samples(Vec<f32>),
+}
+
+// A compressor whose gain reduction follows a separate key signal, applied
+// equally to every channel it compresses.
+pub struct SidechainCompressor {
+    compressor: Compressor,
+    key: Sidechain,
+
+    // The next sample to take from Sidechain::Samples.
+    position: usize,
+}
+
+impl SidechainCompressor {
+    pub fn new(compressor: Compressor, key: Sidechain) -> SidechainCompressor {
+        SidechainCompressor {
+            compressor,
+            key,
+            position: 0,
+        }
+    }
+}
+
+impl FrameFilter for SidechainCompressor {
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        let key = match &self.key {
+            Sidechain::Channel(channel) => frame.get(*channel).copied().unwrap_or(0.0),
+            Sidechain::Samples(samples) => {
+                let key = samples.get(self.position).copied().unwrap_or(0.0);
+                self.position = (self.position + 1).min(samples.len());
+                key
+            }
+        };
+        let gain = self.compressor.key_gain(key);
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            if !matches!(self.key, Sidechain::Channel(key_channel) if key_channel == channel) {
+                *sample *= gain;
+            }
+        }
+    }
+
+    // A key read from a file starts again from its beginning.
+    fn reset(&mut self) {
+        self.compressor.reset();
+        self.position = 0;
+    }
+}
+
+// How far ahead the limiter looks for peaks.
+const LIMITER_LOOKAHEAD_MS: f32 = 2.0;
+const LIMITER_RELEASE_MS: f32 = 80.0;
+
+// Keeps the signal under ceiling without clipping it.  The audio is delayed
+// by a short lookahead so the gain can start coming down before a peak
+// arrives, and recovers slowly afterwards so the limiting doesn't pump.
+pub struct Limiter {
+    ceiling: f32,
+    line: DelayLine,
+
+    // The gain each of the last lookahead + 1 input samples needs to stay
+    // under the ceiling.  Entries are overwritten oldest first, so between
+    // samples the one at position belongs to the next sample to come out of
+    // the delay line.
+    needed: Vec<f32>,
+    position: usize,
+
+    gain: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+}
+
+impl Limiter {
+    pub fn new(ceiling: f32, sample_rate: SampleRate) -> Limiter {
+        let SampleRate(sr) = sample_rate;
+        let lookahead = ((LIMITER_LOOKAHEAD_MS * sr as f32 / 1000.0) as usize).max(1);
+        Limiter {
+            ceiling,
+            line: DelayLine::new(lookahead),
+            needed: vec![1.0; lookahead + 1],
+            position: 0,
+            gain: 1.0,
+            attack_coefficient: one_pole_coefficient(LIMITER_LOOKAHEAD_MS / 4.0, sample_rate),
+            release_coefficient: one_pole_coefficient(LIMITER_RELEASE_MS, sample_rate),
+        }
+    }
+
+    // How many samples late the output is.
+    pub fn lookahead_frames(&self) -> usize {
+        self.needed.len() - 1
+    }
+}
+
+impl Filter for Limiter {
+    fn process(&mut self, sample: f32) -> f32 {
+        let delayed = self.line.read();
+        self.line.write(sample);
+
+        let level = sample.abs();
+        self.needed[self.position] = if level > self.ceiling {
+            self.ceiling / level
+        } else {
+            1.0
+        };
+        self.position = (self.position + 1) % self.needed.len();
+
+        let target = self.needed.iter().fold(1.0f32, |a, b| a.min(*b));
+        let coefficient = if target < self.gain {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.gain = target + coefficient * (self.gain - target);
+
+        // The smoothed gain may still be on its way down; never let the
+        // sample going out now exceed the ceiling.
+        delayed * self.gain.min(self.needed[self.position])
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.lookahead_frames()
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.needed.fill(1.0);
+        self.gain = 1.0;
+    }
+}
+
+// How many samples AutoWah goes between recalculating its filter.
+const AUTOWAH_RETUNE_INTERVAL: usize = 16;
+
+// A resonant band-pass whose center follows the input level.  Quiet input
+// rests at min_freq; louder playing sweeps it up toward max_freq.  The sweep
+// is exponential, so equal changes in level move it by equal musical
+// intervals.
+pub struct AutoWah {
+    sensitivity: f32,
+    min_freq: f32,
+    max_freq: f32,
+    q: f32,
+    sample_rate: SampleRate,
+
+    envelope: EnvelopeFollower,
+    level: f32,
+
+    biquad: Biquad,
+    center_hz: f32,
+    countdown: usize,
+}
+
+impl AutoWah {
+    pub fn new(
+        sensitivity: f32,
+        min_freq: f32,
+        max_freq: f32,
+        q: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: SampleRate,
+    ) -> AutoWah {
+        AutoWah {
+            sensitivity,
+            min_freq,
+            max_freq,
+            q,
+            sample_rate,
+            envelope: EnvelopeFollower::new(attack_ms, release_ms, sample_rate),
+            level: 0.0,
+            biquad: Biquad::band_pass(min_freq, q, sample_rate),
+            center_hz: min_freq,
+            countdown: 0,
+        }
+    }
+
+    // Where the filter center should sit for the current envelope.
+    fn target_hz(&self) -> f32 {
+        let sweep = (self.level * self.sensitivity).min(1.0);
+        self.min_freq * (self.max_freq / self.min_freq).powf(sweep)
+    }
+
+    // The band-pass center the filter is tuned to right now.
+    pub fn center_hz(&self) -> f32 {
+        self.center_hz
+    }
+}
+
+impl Filter for AutoWah {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.level = self.envelope.process(sample);
+
+        if self.countdown == 0 {
+            self.center_hz = self.target_hz();
+            self.biquad
+                .retune(&Biquad::band_pass(self.center_hz, self.q, self.sample_rate));
+            self.countdown = AUTOWAH_RETUNE_INTERVAL;
+        }
+        self.countdown -= 1;
+
+        self.biquad.process(sample)
+    }
+
+    fn reset(&mut self) {
+        self.envelope.reset();
+        self.level = 0.0;
+        self.biquad.clear();
+        self.center_hz = self.min_freq;
+        self.countdown = 0;
+    }
+}
+
+// How the de-esser listens for sibilance: a fairly wide band around its
+// frequency, with a detector quick enough to catch a single "s".
+const DEESSER_Q: f32 = 1.0;
+const DEESSER_ATTACK_MS: f32 = 1.0;
+const DEESSER_RELEASE_MS: f32 = 60.0;
+
+// Turns the whole signal down while there's too much energy around freq_hz,
+// which is where the hiss of "s" and "sh" sounds sits.  Like Compressor, but
+// listening to only that band; everything below it passes untouched until a
+// sibilant comes along.
+pub struct DeEsser {
+    detector: Biquad,
+    envelope: EnvelopeFollower,
+    threshold_db: f32,
+    ratio: f32,
+}
+
+impl DeEsser {
+    pub fn new(freq_hz: f32, threshold: f32, ratio: f32, sample_rate: SampleRate) -> DeEsser {
+        DeEsser {
+            detector: Biquad::band_pass(freq_hz, DEESSER_Q, sample_rate),
+            envelope: EnvelopeFollower::new(DEESSER_ATTACK_MS, DEESSER_RELEASE_MS, sample_rate),
+            threshold_db: linear_to_db(threshold),
+            ratio: ratio.max(1.0),
+        }
+    }
+}
+
+impl Filter for DeEsser {
+    fn process(&mut self, sample: f32) -> f32 {
+        let band = self.detector.process(sample);
+        let over = linear_to_db(self.envelope.process(band)) - self.threshold_db;
+        if over > 0.0 {
+            sample * db_to_linear(-over * (1.0 - 1.0 / self.ratio))
+        } else {
+            sample
+        }
+    }
+
+    fn reset(&mut self) {
+        self.detector.clear();
+        self.envelope.reset();
+    }
+}
+
+pub const MAX_CHORUS_VOICES: usize = 4;
+
+// Delay that the chorus voices sweep around, in milliseconds.
+const CHORUS_BASE_MS: f32 = 15.0;
+
+// Several copies of the signal, each delayed by its own slowly wandering
+// amount, layered over the dry signal.  Every voice reads from one shared
+// delay line and gets an LFO that is slightly detuned and out of phase from
+// the others, so they never line up.
+pub struct Chorus {
+    line: FractionalDelayLine,
+    base_frames: f32,
+    depth_frames: f32,
+    mix: f32,
+
+    // Per-voice LFO phase and increment, as in Vibrato.
+    phases: Vec<f32>,
+    phase_increments: Vec<f32>,
+}
+
+impl Chorus {
+    pub fn new(
+        voices: usize,
+        rate_hz: f32,
+        depth_ms: f32,
+        mix: f32,
+        sample_rate: SampleRate,
+    ) -> Chorus {
+        let SampleRate(sr) = sample_rate;
+        let voices = voices.clamp(1, MAX_CHORUS_VOICES);
+        let base_frames = CHORUS_BASE_MS * sr as f32 / 1000.0;
+        let depth_frames = (depth_ms * sr as f32 / 1000.0).max(0.0);
+
+        Chorus {
+            line: FractionalDelayLine::new((base_frames + depth_frames).ceil() as usize + 2),
+            base_frames,
+            depth_frames,
+            mix: mix.clamp(0.0, 1.0),
+            phases: Chorus::start_phases(voices),
+            phase_increments: (0..voices)
+                .map(|v| 2.0 * PI * rate_hz * (1.0 + 0.13 * v as f32) / sr as f32)
+                .collect(),
+        }
+    }
+
+    // The voices' LFOs start evenly spread around the cycle, so they never
+    // all sweep together.
+    fn start_phases(voices: usize) -> Vec<f32> {
+        (0..voices)
+            .map(|v| 2.0 * PI * v as f32 / voices as f32)
+            .collect()
+    }
+}
+
+impl Filter for Chorus {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.line.write(sample);
+
+        let mut voices = 0.0;
+        for (phase, increment) in self.phases.iter_mut().zip(&self.phase_increments) {
+            let lfo = 0.5 + 0.5 * phase.sin();
+            *phase = (*phase + increment) % (2.0 * PI);
+            voices += self
+                .line
+                .read(1.0 + self.base_frames + self.depth_frames * lfo);
+        }
+        voices /= self.phases.len() as f32;
+
+        sample * (1.0 - self.mix) + voices * self.mix
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.phases = Chorus::start_phases(self.phases.len());
+    }
+}
+
+// Multiplies the signal by a sine carrier, which replaces each input
+// frequency with a pair at the sum and difference with the carrier.
+pub struct RingModulator {
+    mix: f32,
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl RingModulator {
+    pub fn new(carrier_hz: f32, mix: f32, sample_rate: SampleRate) -> RingModulator {
+        let SampleRate(sr) = sample_rate;
+        RingModulator {
+            mix: mix.clamp(0.0, 1.0),
+            phase_increment: 2.0 * PI * carrier_hz / sr as f32,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Filter for RingModulator {
+    fn process(&mut self, sample: f32) -> f32 {
+        let modulated = sample * self.phase.sin();
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+
+        sample * (1.0 - self.mix) + modulated * self.mix
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+pub const MAX_PHASER_STAGES: usize = 24;
+
+// Phaser sweep range.  depth scales how far up from the bottom it goes.
+const PHASER_MIN_HZ: f32 = 200.0;
+const PHASER_MAX_HZ: f32 = 4000.0;
+
+// Classic phaser: a cascade of allpasses whose break frequency sweeps with
+// an LFO.  Mixing the phase-shifted signal back with the dry signal cancels
+// out frequencies where the stages add up to 180 degrees, giving one notch
+// for every two stages.
+pub struct Phaser {
+    // One-frame allpasses, each shifting the phase by 90 degrees at the
+    // current sweep frequency.
+    stages: Vec<AllpassFilter>,
+    depth: f32,
+    feedback: f32,
+    sample_rate: SampleRate,
+
+    // Same cosine LFO as FlangeFilter, but with the phase kept wrapped.
+    phase_increment: f32,
+    phase: f32,
+
+    last: f32,
+}
+
+impl Phaser {
+    pub fn new(
+        stages: usize,
+        rate_hz: f32,
+        depth: f32,
+        feedback: f32,
+        sample_rate: SampleRate,
+    ) -> Phaser {
+        let SampleRate(sr) = sample_rate;
+        Phaser {
+            stages: std::iter::repeat_with(|| AllpassFilter::new(1, 0.0))
+                .take(stages.clamp(1, MAX_PHASER_STAGES))
+                .collect(),
+            depth: depth.clamp(0.0, 1.0),
+            feedback: feedback.clamp(0.0, 0.99),
+            sample_rate,
+            phase_increment: 2.0 * PI * rate_hz / sr as f32,
+            phase: 0.0,
+            last: 0.0,
+        }
+    }
+
+    // Returns the allpass gain that puts the 90 degree point at freq_hz.
+    fn stage_gain(freq_hz: f32, sample_rate: SampleRate) -> f32 {
+        let SampleRate(sr) = sample_rate;
+        let t = (PI * freq_hz / sr as f32).tan();
+        (1.0 - t) / (1.0 + t)
+    }
+}
+
+impl Filter for Phaser {
+    fn process(&mut self, sample: f32) -> f32 {
+        let lfo = 0.5 + 0.5 * self.phase.cos();
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+
+        let freq_hz = PHASER_MIN_HZ * (PHASER_MAX_HZ / PHASER_MIN_HZ).powf(lfo * self.depth);
+        let gain = Phaser::stage_gain(freq_hz, self.sample_rate);
+
+        let input = sample + self.last * self.feedback;
+        let shifted = self.stages.iter_mut().fold(input, |s, stage| {
+            stage.set_gain(gain);
+            stage.process(s)
+        });
+        self.last = shifted;
+
+        0.5 * (sample + shifted)
+    }
+
+    fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+        self.last = 0.0;
+        self.phase = 0.0;
+    }
+}
+
+// Triangular (TPDF) dither for rounding to a coarser set of levels.  Noise
+// of up to a step either way is added before rounding, which turns the
+// rounding error into a steady hiss that no longer follows the signal.  On
+// average the result is exactly the input, however it falls between levels.
+// The noise comes from xorshift32, so a given seed always gives the same
+// output.
+pub struct Dither {
+    // Never zero.
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(seed: u32) -> Dither {
+        Dither { state: seed.max(1) }
+    }
+
+    // Rounds sample to the nearest whole multiple of step, after dithering.
+    pub fn quantize(&mut self, sample: f32, step: f32) -> f32 {
+        let noise = self.uniform() - self.uniform();
+        (sample / step + noise).round() * step
+    }
+
+    // Between 0.0 and 1.0.
+    fn uniform(&mut self) -> f32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x as f32 / u32::MAX as f32
+    }
+}
+
+// Lo-fi effect: quantizes to a reduced bit depth, and holds each value for
+// several samples to fake a lower sample rate.
+pub struct BitCrusher {
+    // Number of steps between -1.0 and 1.0.  One less than the number of
+    // output levels.
+    steps: f32,
+
+    downsample: usize,
+    counter: usize,
+    held: f32,
+    dither: Option<Dither>,
+}
+
+impl BitCrusher {
+    pub fn new(bits: u32, downsample: usize) -> BitCrusher {
+        let bits = bits.clamp(1, 16);
+        BitCrusher {
+            steps: ((1u32 << bits) - 1) as f32,
+            downsample: downsample.max(1),
+            counter: 0,
+            held: 0.0,
+            dither: None,
+        }
+    }
+
+    // Dithers each sample before it's crushed.  This trades the crusher's
+    // gritty distortion for noise, which is mostly useful at higher bit
+    // depths.
+    pub fn enable_dither(&mut self, seed: u32) {
+        self.dither = Some(Dither::new(seed));
+    }
+
+    fn quantize(&mut self, sample: f32) -> f32 {
+        let unipolar = (sample.clamp(-1.0, 1.0) + 1.0) / 2.0 * self.steps;
+        let level = match &mut self.dither {
+            Some(dither) => dither.quantize(unipolar, 1.0).clamp(0.0, self.steps),
+            None => unipolar.round(),
+        };
+        level / self.steps * 2.0 - 1.0
+    }
+}
+
+impl Filter for BitCrusher {
+    fn process(&mut self, sample: f32) -> f32 {
+        if self.counter == 0 {
+            self.held = self.quantize(sample);
+        }
+        self.counter = (self.counter + 1) % self.downsample;
+
+        self.held
+    }
+
+    fn reset(&mut self) {
+        self.counter = 0;
+        self.held = 0.0;
+    }
+}
+
+// Offset added before the overdrive curve so positive and negative halves of
+// the wave clip differently, which adds even harmonics.
+const OVERDRIVE_BIAS: f32 = 0.2;
+
+// Tube-style overdrive using the cubic soft clipper x - x^3/3, which leaves
+// quiet signals nearly untouched and rounds off loud ones.  Unlike
+// DistortFilter the curve is biased, so the clipping is asymmetric.
+pub struct Overdrive {
+    drive: f32,
+    level: f32,
+}
+
+impl Overdrive {
+    pub fn new(drive: f32, level: f32) -> Overdrive {
+        Overdrive { drive, level }
+    }
+
+    // Flattens out at +/-1.0 once |x| reaches 1.0.
+    fn curve(x: f32) -> f32 {
+        let x = x.clamp(-1.0, 1.0);
+        1.5 * (x - x * x * x / 3.0)
+    }
+}
+
+impl Filter for Overdrive {
+    fn process(&mut self, sample: f32) -> f32 {
+        // Subtracting the curve at the bias point keeps silence silent.
+        let shaped = Overdrive::curve(sample * self.drive + OVERDRIVE_BIAS)
+            - Overdrive::curve(OVERDRIVE_BIAS);
+        self.level * shaped
+    }
+}
+
+// How much of the tape's last output feeds back into the next, which makes
+// the output lag behind changes in the input the way magnetized tape does.
+// It has to stay below 1.0 or the output would latch.
+const TAPE_HYSTERESIS: f32 = 0.3;
+
+// The wow and flutter LFO.  Real machines wobble at a mix of rates; one slow
+// one is enough to take the edge off a perfectly steady pitch.
+const TAPE_FLUTTER_HZ: f32 = 1.5;
+
+// The tone control sweeps the treble rolloff between these, logarithmically.
+const TAPE_DARK_HZ: f32 = 2000.0;
+const TAPE_BRIGHT_HZ: f32 = 20000.0;
+
+// A tape machine driven hard: soft compression with a little hysteresis,
+// then a treble rolloff, then a slight pitch wobble from sweeping a short
+// fractional delay, as in Vibrato.  Quiet signals come through at drive
+// times their level; loud ones flatten out toward 1.0.
+pub struct TapeSaturation {
+    drive: f32,
+
+    // The saturator's last output, for the hysteresis.
+    magnetization: f32,
+
+    // One-pole lowpass for the rolloff.
+    tone_coefficient: f32,
+    toned: f32,
+
+    line: FractionalDelayLine,
+    flutter_frames: f32,
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl TapeSaturation {
+    // tone runs from 0.0, dark, to 1.0, bright.  flutter_depth_ms is how far
+    // the delay sweeps.
+    pub fn new(
+        drive: f32,
+        tone: f32,
+        flutter_depth_ms: f32,
+        sample_rate: SampleRate,
+    ) -> TapeSaturation {
+        let SampleRate(sr) = sample_rate;
+        let cutoff_hz = (TAPE_DARK_HZ * (TAPE_BRIGHT_HZ / TAPE_DARK_HZ).powf(tone.clamp(0.0, 1.0)))
+            .min(0.45 * sr as f32);
+        let flutter_frames = (flutter_depth_ms * sr as f32 / 1000.0).max(0.0);
+        TapeSaturation {
+            drive,
+            magnetization: 0.0,
+            tone_coefficient: one_pole_coefficient(1000.0 / (2.0 * PI * cutoff_hz), sample_rate),
+            toned: 0.0,
+            line: FractionalDelayLine::new(flutter_frames.ceil() as usize + 1),
+            flutter_frames,
+            phase_increment: 2.0 * PI * TAPE_FLUTTER_HZ / sr as f32,
+            phase: 0.0,
+        }
+    }
+
+    // Rises steadily and levels out at +/-1.0 without ever reaching it.
+    fn curve(x: f32) -> f32 {
+        x / (1.0 + x.abs())
+    }
+}
+
+impl Filter for TapeSaturation {
+    fn process(&mut self, sample: f32) -> f32 {
+        // Scaling the input by 1 - TAPE_HYSTERESIS makes up for the
+        // feedback, so quiet signals still get exactly drive's gain.
+        let driven = sample * self.drive * (1.0 - TAPE_HYSTERESIS);
+        self.magnetization = TapeSaturation::curve(driven + TAPE_HYSTERESIS * self.magnetization);
+
+        self.toned = self.magnetization + self.tone_coefficient * (self.toned - self.magnetization);
+
+        self.line.write(self.toned);
+        let lfo = 0.5 + 0.5 * self.phase.sin();
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+        self.line.read(1.0 + self.flutter_frames * lfo)
+    }
+
+    fn reset(&mut self) {
+        self.magnetization = 0.0;
+        self.toned = 0.0;
+        self.line.clear();
+        self.phase = 0.0;
+    }
+}
+
+// A plain volume control.  Large negative gains, down to -inf dB, fade to
+// silence.
+pub struct Gain {
+    gain: f32,
+}
+
+impl Gain {
+    pub fn new(gain_db: f32) -> Gain {
+        Gain {
+            gain: db_to_linear(gain_db),
+        }
+    }
+}
+
+impl Filter for Gain {
+    fn process(&mut self, sample: f32) -> f32 {
+        sample * self.gain
+    }
+}
+
+// Flips the polarity of the signal.  Useful for fixing a device that's wired
+// backwards, or for null testing against the dry signal.
+pub struct InvertPhase;
+
+impl Filter for InvertPhase {
+    fn process(&mut self, sample: f32) -> f32 {
+        -sample
+    }
+}
+
+// A gain that starts at silence and fades up to unity over time_ms, so
+// streams can start (and, with fade_out, stop) without a pop.
+pub struct GainRamp {
+    gain: f32,
+    step: f32,
+    fading_out: bool,
+}
+
+impl GainRamp {
+    pub fn new(time_ms: f32, sample_rate: SampleRate) -> GainRamp {
+        GainRamp {
+            gain: 0.0,
+            step: ramp_step(time_ms, sample_rate),
+            fading_out: false,
+        }
+    }
+
+    // Turns the ramp around.  It fades back down over the same time.
+    pub fn fade_out(&mut self) {
+        self.fading_out = true;
+    }
+
+    // The gain for the next frame.
+    pub fn next_gain(&mut self) -> f32 {
+        self.gain = if self.fading_out {
+            (self.gain - self.step).max(0.0)
+        } else {
+            (self.gain + self.step).min(1.0)
+        };
+        self.gain
+    }
+}
+
+// Amplitude modulation by a low-frequency sine.  With depth 1.0 the volume
+// swings all the way down to silence once per cycle.
+pub struct Tremolo {
+    depth: f32,
+
+    // Like FlangeFilter's offset_coefficient: converts elapsed samples into
+    // radians of LFO phase.
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl Tremolo {
+    pub fn new(rate_hz: f32, depth: f32, sample_rate: SampleRate) -> Tremolo {
+        let SampleRate(sr) = sample_rate;
+        Tremolo {
+            depth: depth.clamp(0.0, 1.0),
+            phase_increment: 2.0 * PI * rate_hz / sr as f32,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Filter for Tremolo {
+    fn process(&mut self, sample: f32) -> f32 {
+        let lfo = 0.5 + 0.5 * self.phase.sin();
+        let result = sample * ((1.0 - self.depth) + self.depth * lfo);
+
+        // Wrap so the phase never grows large enough to lose precision.
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+
+        result
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+// Pitch wobble: plays back only the delayed signal, sweeping the delay with
+// a sine LFO.  The delay moves smoothly between samples so the pitch bends
+// rather than steps.
+pub struct Vibrato {
+    line: FractionalDelayLine,
+    depth_frames: f32,
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl Vibrato {
+    pub fn new(rate_hz: f32, depth_ms: f32, sample_rate: SampleRate) -> Vibrato {
+        let SampleRate(sr) = sample_rate;
+        let depth_frames = (depth_ms * sr as f32 / 1000.0).max(0.0);
+        Vibrato {
+            line: FractionalDelayLine::new(depth_frames.ceil() as usize + 1),
+            depth_frames,
+            phase_increment: 2.0 * PI * rate_hz / sr as f32,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Filter for Vibrato {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.line.write(sample);
+
+        let lfo = 0.5 + 0.5 * self.phase.sin();
+        self.phase = (self.phase + self.phase_increment) % (2.0 * PI);
+
+        self.line.read(1.0 + self.depth_frames * lfo)
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.phase = 0.0;
+    }
+}
+
+// Length of the delay sweep each pitch shifter read pointer makes before
+// jumping back.  Longer windows smear transients; shorter ones sound rough.
+const PITCH_WINDOW_MS: f32 = 40.0;
+
+// Shifts pitch without changing tempo, using the same machinery as vibrato:
+// a read pointer sliding steadily through a delay line plays the signal back
+// faster or slower.  Each pointer has to jump back once it reaches the end
+// of the window, so there are two of them half a window apart, crossfading
+// so whichever is about to jump is silent.
+pub struct PitchShifter {
+    line: FractionalDelayLine,
+    window_frames: f32,
+
+    // Position of the first pointer through the window, from 0.0 up to 1.0.
+    // The second is always half a window away.
+    phase: f32,
+    phase_increment: f32,
+}
+
+impl PitchShifter {
+    pub fn new(semitones: f32, sample_rate: SampleRate) -> PitchShifter {
+        let SampleRate(sr) = sample_rate;
+        let window_frames = PITCH_WINDOW_MS * sr as f32 / 1000.0;
+        let ratio = 2.0f32.powf(semitones / 12.0);
+        PitchShifter {
+            line: FractionalDelayLine::new(window_frames.ceil() as usize + 2),
+            window_frames,
+            phase: 0.0,
+            // The delay has to shrink by ratio - 1 samples every sample for
+            // the playback to run ratio times faster.
+            phase_increment: (1.0 - ratio) / window_frames,
+        }
+    }
+
+    fn read_pointer(&self, phase: f32) -> f32 {
+        // sin^2 windows half a cycle apart always add up to one.
+        let fade = (PI * phase).sin().powi(2);
+        fade * self.line.read(1.0 + self.window_frames * phase)
+    }
+}
+
+impl Filter for PitchShifter {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.line.write(sample);
+
+        self.phase = (self.phase + self.phase_increment).rem_euclid(1.0);
+        let other = (self.phase + 0.5) % 1.0;
+        self.read_pointer(self.phase) + self.read_pointer(other)
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.phase = 0.0;
+    }
+}
@@ -0,0 +1,162 @@
+// Test signals, for trying effects out without a microphone or a file.
+
+use std::f32::consts::PI;
+
+use clap::ValueEnum;
+use cpal::SampleRate;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    // A steady sine at the generator's frequency.
+    Sine,
+
+    // Equal energy at every frequency.  Sounds like hiss.
+    White,
+
+    // Equal energy in every octave, so it sounds balanced rather than
+    // bright.  Made by filtering white noise.
+    Pink,
+
+    // A sine gliding from 20Hz up to 20kHz (or Nyquist), SWEEP_SECONDS long,
+    // rising at a steady number of octaves per second.  Handy for hearing a
+    // filter's frequency response.
+    Sweep,
+}
+
+const SWEEP_LOW_HZ: f64 = 20.0;
+const SWEEP_HIGH_HZ: f64 = 20000.0;
+const SWEEP_SECONDS: f64 = 10.0;
+
+// An exponential sine sweep from low_hz to high_hz.  The frequency climbs by
+// the same number of octaves every second, so each octave gets equal time.
+// Signal::Sweep plays one of these on repeat, and --measure-response plays
+// one through the effect and correlates the output against its phase.
+#[derive(Clone, Copy, Debug)]
+pub struct Sweep {
+    low_hz: f64,
+    sample_rate: f64,
+    frames: usize,
+
+    // Seconds for the frequency to rise by a factor of e.
+    rate: f64,
+}
+
+impl Sweep {
+    pub fn new(low_hz: f64, high_hz: f64, seconds: f64, sample_rate: SampleRate) -> Sweep {
+        let SampleRate(sr) = sample_rate;
+        Sweep {
+            low_hz,
+            sample_rate: sr as f64,
+            frames: (seconds * sr as f64) as usize,
+            rate: seconds / (high_hz / low_hz).ln(),
+        }
+    }
+
+    // How long the sweep is.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    // The phase frame samples in, in radians.  The frequency at time t is
+    // low_hz * e^(t/rate), and this is its integral.
+    pub fn phase(&self, frame: usize) -> f64 {
+        let t = frame as f64 / self.sample_rate;
+        2.0 * std::f64::consts::PI * self.low_hz * self.rate * ((t / self.rate).exp() - 1.0)
+    }
+
+    // The frame at which the sweep passes freq_hz.
+    pub fn frame_at(&self, freq_hz: f64) -> usize {
+        (self.rate * (freq_hz / self.low_hz).ln() * self.sample_rate) as usize
+    }
+}
+
+pub struct SignalGenerator {
+    signal: Signal,
+    amplitude: f32,
+
+    // Radians of phase per sample, for the sine.
+    phase_increment: f32,
+    phase: f32,
+
+    // The sweep, and how many samples into it we are.
+    sweep: Sweep,
+    sweep_position: usize,
+
+    // xorshift32 state for the noise.  Never zero.
+    noise_state: u32,
+
+    // Paul Kellet's pink noise filter.
+    pink: [f32; 7],
+}
+
+impl SignalGenerator {
+    pub fn new(
+        signal: Signal,
+        frequency: f32,
+        amplitude: f32,
+        sample_rate: SampleRate,
+    ) -> SignalGenerator {
+        let SampleRate(sr) = sample_rate;
+        let sweep_high_hz = SWEEP_HIGH_HZ.min(sr as f64 / 2.0);
+        SignalGenerator {
+            signal,
+            amplitude,
+            phase_increment: 2.0 * PI * frequency / sr as f32,
+            phase: 0.0,
+            sweep: Sweep::new(SWEEP_LOW_HZ, sweep_high_hz, SWEEP_SECONDS, sample_rate),
+            sweep_position: 0,
+            noise_state: 0x9e37_79b9,
+            pink: [0.0; 7],
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = match self.signal {
+            Signal::Sine => {
+                let sample = self.phase.sin();
+                self.advance_phase(self.phase_increment);
+                sample
+            }
+            Signal::White => self.white(),
+            Signal::Pink => self.pink(),
+            Signal::Sweep => {
+                let sample = self.sweep.phase(self.sweep_position).sin() as f32;
+                self.sweep_position = (self.sweep_position + 1) % self.sweep.frames();
+                sample
+            }
+        };
+        sample * self.amplitude
+    }
+
+    // Kept within one turn so long runs don't lose precision.
+    fn advance_phase(&mut self, increment: f32) {
+        self.phase = (self.phase + increment) % (2.0 * PI);
+    }
+
+    // Uniform noise between -1.0 and 1.0.
+    fn white(&mut self) -> f32 {
+        let mut x = self.noise_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.noise_state = x;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn pink(&mut self) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+
+        // Kellet's own scaling, which brings it back to roughly the same
+        // range as the white noise.
+        pink * 0.11
+    }
+}
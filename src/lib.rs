@@ -0,0 +1,12 @@
+// The signal processing side of g2.  The binary wires these effects up to
+// audio devices; everything here works on plain f32 samples.
+
+pub mod clip;
+pub mod effects;
+pub mod generator;
+pub mod resample;
+pub mod response;
+pub mod retry;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd;
+pub mod transfer;
@@ -1,50 +1,401 @@
+#[cfg(feature = "midi")]
+mod midi;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::io::Write;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use std::{error::Error, f32::consts::PI};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Device, Devices, Host, InputCallbackInfo, InputDevices, OutputCallbackInfo, StreamError,
 };
-use cpal::{SampleRate, StreamConfig};
+use cpal::{
+    BuildStreamError, DefaultStreamConfigError, DeviceNameError, DevicesError, Sample,
+    SampleFormat, SampleRate, Stream, StreamConfig, SupportedStreamConfig,
+    SupportedStreamConfigRange, SupportedStreamConfigsError,
+};
+use g2::clip::ClipDetector;
+use g2::effects::{
+    db_to_linear, linear_to_db, AutoPanner, AutoWah, BitCrusher, ChannelDelay, Chorus, Compressor,
+    ConvolutionReverb, DeEsser, DelayFilter, DistortFilter, DistortMode, Dither, Filter,
+    FilterChain, FlangeFilter, FrameChain, FrameFilter, Gain, GainRamp, HighPassFilter,
+    InvertPhase, LfoShape, Limiter, LowPassFilter, Multiband, NoiseGate, Overdrive, Oversampler,
+    ParallelChain, PeakingEq, PerChannel, Phaser, PingPongDelay, PitchShifter, ReverbFilter,
+    RingModulator, SharedFlag, SharedParam, Sidechain, SidechainCompressor, StageTimer,
+    StereoWidth, SwapChannels, TapeSaturation, Tremolo, Vibrato, WetDry, MAX_CHORUS_VOICES,
+    MAX_PHASER_STAGES,
+};
+use g2::generator::{Signal, SignalGenerator};
+use g2::resample::{ResampleQuality, Resampler};
+use g2::response::{measure_response, THIRD_OCTAVES};
+use g2::retry::{is_recoverable, Backoff};
+use g2::transfer::{pop_samples, push_samples};
 use ringbuf::{Consumer, Producer, RingBuffer};
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+// Set by --stdout-raw, when stdout carries samples rather than messages, and
+// by --show-config, when it carries the settings.
+static STDOUT_IS_DATA: AtomicBool = AtomicBool::new(false);
+
+// println!, except that it moves to stderr when stdout is carrying audio.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STDOUT_IS_DATA.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+fn list_hosts() {
+    let default_id = cpal::default_host().id();
+    for id in cpal::available_hosts() {
+        let marker = if id == default_id { '*' } else { ' ' };
+        println!("{} {}", marker, id.name());
+    }
+    println!();
+    println!("* marks the default host");
+}
+
+// Finds a host by name, ignoring case.  Hosts differ by platform and by which
+// features cpal was built with, so an unknown name falls back to the default
+// rather than stopping.
+fn select_host(name: Option<&str>) -> Host {
+    let Some(name) = name else {
+        return cpal::default_host();
+    };
+
+    let available = cpal::available_hosts();
+    match available
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    {
+        Some(id) => match cpal::host_from_id(*id) {
+            Ok(host) => return host,
+            Err(err) => eprintln!("Couldn't start the {} host: {}", id.name(), err),
+        },
+        None => {
+            let names: Vec<_> = available.iter().map(|id| id.name()).collect();
+            eprintln!(
+                "No {} host in this build; available hosts: {}",
+                name,
+                names.join(", ")
+            );
+        }
+    }
+
+    let host = cpal::default_host();
+    eprintln!("Using the default host, {}", host.id().name());
+    host
+}
 
 fn list_output_devices(host: &Host) -> Result<(), Box<dyn Error>> {
-    let mut i = 0;
-    for device in host.output_devices()? {
-        println!("Output device {}: {}", i, device.name()?);
-        i += 1;
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    for (i, device) in host.output_devices()?.enumerate() {
+        let name = device.name()?;
+        let marker = if Some(&name) == default_name.as_ref() {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{} Output device {}: {}", marker, i, name);
+        print_device_configs(
+            device.default_output_config(),
+            device.supported_output_configs(),
+        );
     }
 
     Ok(())
 }
 
 fn list_input_devices(host: &Host) -> Result<(), Box<dyn Error>> {
-    let mut i = 0;
-    for device in host.input_devices()? {
-        println!("Input device {}: {}", i, device.name()?);
-        i += 1;
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    for (i, device) in host.input_devices()?.enumerate() {
+        let name = device.name()?;
+        let marker = if Some(&name) == default_name.as_ref() {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{} Input device {}: {}", marker, i, name);
+        print_device_configs(
+            device.default_input_config(),
+            device.supported_input_configs(),
+        );
+    }
+
+    Ok(())
+}
+
+// Prints every device on the host as a JSON array, for other programs to
+// read.  Like the text listing, a device that can't describe itself gets its
+// errors listed in place of the missing details.
+fn list_devices_json(host: &Host) -> Result<(), Box<dyn Error>> {
+    let mut devices = Vec::new();
+
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    for (i, device) in host.input_devices()?.enumerate() {
+        devices.push(DeviceInfo::new(
+            i,
+            &device,
+            "input",
+            &default_name,
+            device.default_input_config(),
+            device.supported_input_configs(),
+        ));
+    }
+
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    for (i, device) in host.output_devices()?.enumerate() {
+        devices.push(DeviceInfo::new(
+            i,
+            &device,
+            "output",
+            &default_name,
+            device.default_output_config(),
+            device.supported_output_configs(),
+        ));
     }
 
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &devices)?;
+    writeln!(stdout)?;
     Ok(())
 }
 
+// One device's entry in --list-json.
+#[derive(Serialize)]
+struct DeviceInfo {
+    index: usize,
+    name: Option<String>,
+    direction: &'static str,
+    default: bool,
+    default_config: Option<StreamConfigInfo>,
+    configs: Vec<ConfigRangeInfo>,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StreamConfigInfo {
+    sample_rate: u32,
+    channels: u16,
+    sample_format: String,
+}
+
+impl StreamConfigInfo {
+    fn new(sample_rate: SampleRate, channels: u16, format: SampleFormat) -> StreamConfigInfo {
+        StreamConfigInfo {
+            sample_rate: sample_rate.0,
+            channels,
+            sample_format: format!("{:?}", format),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ConfigRangeInfo {
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    channels: u16,
+    sample_format: String,
+}
+
+impl DeviceInfo {
+    fn new<I>(
+        index: usize,
+        device: &Device,
+        direction: &'static str,
+        default_name: &Option<String>,
+        default: Result<SupportedStreamConfig, DefaultStreamConfigError>,
+        supported: Result<I, SupportedStreamConfigsError>,
+    ) -> DeviceInfo
+    where
+        I: Iterator<Item = SupportedStreamConfigRange>,
+    {
+        let mut errors = Vec::new();
+        let name = match device.name() {
+            Ok(name) => Some(name),
+            Err(err) => {
+                errors.push(err.to_string());
+                None
+            }
+        };
+        let is_default = name.is_some() && name == *default_name;
+
+        let default_config = match default {
+            Ok(config) => Some(StreamConfigInfo::new(
+                config.sample_rate(),
+                config.channels(),
+                config.sample_format(),
+            )),
+            Err(err) => {
+                errors.push(err.to_string());
+                None
+            }
+        };
+
+        let configs = match supported {
+            Ok(ranges) => ranges
+                .map(|range| ConfigRangeInfo {
+                    min_sample_rate: range.min_sample_rate().0,
+                    max_sample_rate: range.max_sample_rate().0,
+                    channels: range.channels(),
+                    sample_format: format!("{:?}", range.sample_format()),
+                })
+                .collect(),
+            Err(err) => {
+                errors.push(err.to_string());
+                Vec::new()
+            }
+        };
+
+        DeviceInfo {
+            index,
+            name,
+            direction,
+            default: is_default,
+            default_config,
+            configs,
+            errors,
+        }
+    }
+}
+
+// The name clap knows a value by, as in --effect or --signal.
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string())
+}
+
+// Prints what a device can do underneath its name in the listing.  Some
+// backends refuse to describe certain devices, so errors are printed in
+// place rather than cutting the listing short.
+fn print_device_configs<I>(
+    default: Result<SupportedStreamConfig, DefaultStreamConfigError>,
+    supported: Result<I, SupportedStreamConfigsError>,
+) where
+    I: Iterator<Item = SupportedStreamConfigRange>,
+{
+    match default {
+        Ok(config) => println!(
+            "      Default: {} Hz ({} channels, {:?})",
+            config.sample_rate().0,
+            config.channels(),
+            config.sample_format()
+        ),
+        Err(err) => println!("      Default: unavailable ({})", err),
+    }
+
+    match supported {
+        Ok(ranges) => {
+            for range in ranges {
+                println!("      Supports {}", format_range(&range));
+            }
+        }
+        Err(err) => println!("      Supports: unavailable ({})", err),
+    }
+}
+
+// Everything that can go wrong while finding the devices and agreeing on how
+// to talk to them.
 #[derive(Debug)]
-struct NoDefaultDevice;
+enum G2Error {
+    // The host has no default device of the kind asked for.
+    NoDefaultDevice,
+
+    // No device name contained the substring asked for.
+    DeviceNotFound {
+        substring: String,
+        available: Vec<String>,
+    },
+
+    // The devices, or the input file and output device, can't agree on a
+    // stream config.
+    UnsupportedConfig(String),
+
+    // The backend refused to open a stream.
+    StreamBuild(BuildStreamError),
 
-impl fmt::Display for NoDefaultDevice {
+    // The backend failed while describing its devices.
+    Backend(String),
+}
+
+impl fmt::Display for G2Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "NoDefaultDevice")
+        match self {
+            G2Error::NoDefaultDevice => write!(f, "No default audio device is available"),
+            G2Error::DeviceNotFound {
+                substring,
+                available,
+            } => write!(
+                f,
+                "No device matching \"{}\".  Available devices: {}",
+                substring,
+                available.join(", ")
+            ),
+            G2Error::UnsupportedConfig(message) => write!(f, "{}", message),
+            G2Error::StreamBuild(err) => write!(f, "Couldn't open audio stream: {}", err),
+            G2Error::Backend(message) => write!(f, "Audio backend error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for G2Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            G2Error::StreamBuild(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BuildStreamError> for G2Error {
+    fn from(err: BuildStreamError) -> G2Error {
+        G2Error::StreamBuild(err)
+    }
+}
+
+impl From<DevicesError> for G2Error {
+    fn from(err: DevicesError) -> G2Error {
+        G2Error::Backend(err.to_string())
+    }
+}
+
+impl From<DeviceNameError> for G2Error {
+    fn from(err: DeviceNameError) -> G2Error {
+        G2Error::Backend(err.to_string())
     }
 }
 
-impl std::error::Error for NoDefaultDevice {}
+impl From<SupportedStreamConfigsError> for G2Error {
+    fn from(err: SupportedStreamConfigsError) -> G2Error {
+        G2Error::Backend(err.to_string())
+    }
+}
+
+impl From<DefaultStreamConfigError> for G2Error {
+    fn from(err: DefaultStreamConfigError) -> G2Error {
+        G2Error::Backend(err.to_string())
+    }
+}
 
 fn nth_device(
     devices: InputDevices<Devices>,
     default_device: Device,
     index: Option<usize>,
-) -> Result<Device, Box<dyn Error>> {
+) -> Result<Device, G2Error> {
     if let Some(ii) = index {
         let mut count = ii;
         let devices = devices;
@@ -60,236 +411,4410 @@ fn nth_device(
     Ok(default_device)
 }
 
-fn nth_input_device(host: &Host, index: Option<usize>) -> Result<Device, Box<dyn Error>> {
-    let default_device = host.default_input_device().ok_or(NoDefaultDevice {})?;
+// Returns the first device whose name contains substring, ignoring case.
+fn device_by_name<I>(devices: I, substring: &str) -> Result<Device, G2Error>
+where
+    I: Iterator<Item = Device>,
+{
+    let needle = substring.to_lowercase();
+    let mut available = Vec::new();
+    for device in devices {
+        let name = device.name()?;
+        if name.to_lowercase().contains(&needle) {
+            return Ok(device);
+        }
+        available.push(name);
+    }
+
+    Err(G2Error::DeviceNotFound {
+        substring: substring.to_string(),
+        available,
+    })
+}
+
+fn nth_input_device(host: &Host, index: Option<usize>) -> Result<Device, G2Error> {
+    let default_device = host
+        .default_input_device()
+        .ok_or(G2Error::NoDefaultDevice)?;
     nth_device(host.input_devices()?, default_device, index)
 }
 
-fn nth_output_device(host: &Host, index: Option<usize>) -> Result<Device, Box<dyn Error>> {
-    let default_device = host.default_output_device().ok_or(NoDefaultDevice {})?;
+fn nth_output_device(host: &Host, index: Option<usize>) -> Result<Device, G2Error> {
+    let default_device = host
+        .default_output_device()
+        .ok_or(G2Error::NoDefaultDevice)?;
     nth_device(host.output_devices()?, default_device, index)
 }
 
-fn on_error(err: StreamError) {
-    let _ = err;
+fn select_input_device(
+    host: &Host,
+    index: Option<usize>,
+    name: Option<&str>,
+) -> Result<Device, G2Error> {
+    match name {
+        Some(name) => {
+            if index.is_some() {
+                eprintln!("Both --input-device and --input-name given; using --input-name");
+            }
+            device_by_name(host.input_devices()?, name)
+        }
+        None => nth_input_device(host, index),
+    }
+}
+
+fn select_output_device(
+    host: &Host,
+    index: Option<usize>,
+    name: Option<&str>,
+) -> Result<Device, G2Error> {
+    match name {
+        Some(name) => {
+            if index.is_some() {
+                eprintln!("Both --output-device and --output-name given; using --output-name");
+            }
+            device_by_name(host.output_devices()?, name)
+        }
+        None => nth_output_device(host, index),
+    }
 }
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    /// Input device
-    #[clap(short, long, value_parser)]
-    input_device: Option<usize>,
+fn format_ranges(ranges: &[SupportedStreamConfigRange]) -> String {
+    if ranges.is_empty() {
+        return "no configs at all".to_string();
+    }
 
-    /// Output device
-    #[clap(short, long, value_parser)]
-    output_device: Option<usize>,
+    ranges
+        .iter()
+        .map(format_range)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    /// List available input and output devices
-    #[clap(long)]
-    list: bool,
+fn format_range(range: &SupportedStreamConfigRange) -> String {
+    format!(
+        "{}-{} Hz ({} channels, {:?})",
+        range.min_sample_rate().0,
+        range.max_sample_rate().0,
+        range.channels(),
+        range.sample_format()
+    )
 }
 
-#[allow(dead_code)]
-struct DelayFilter {
-    decay: f32,
-    producer: Producer<f32>,
-    consumer: Consumer<f32>,
+// Puts the config ranges we'd most like to use first, so negotiation picks
+// them when it has a choice.  f32 comes first since it needs no conversion,
+// then ranges with enough channels, fewest extra first.  If none have enough
+// channels, the widest one wins so remix_frame repeats as little as possible.
+fn sort_config_ranges(ranges: &mut [SupportedStreamConfigRange], channels_needed: u16) {
+    ranges.sort_by_key(|range| {
+        (
+            range.sample_format() != SampleFormat::F32,
+            range.channels() < channels_needed,
+            range.channels().abs_diff(channels_needed),
+        )
+    });
 }
 
-#[allow(dead_code)]
-impl DelayFilter {
-    fn new(delay_frames: usize, decay: f32) -> DelayFilter {
-        let buffer = RingBuffer::new(delay_frames);
-        let (mut producer, consumer) = buffer.split();
+// Sample rates to try first, in order, when both devices support several.
+const PREFERRED_SAMPLE_RATES: [u32; 4] = [48000, 44100, 96000, 88200];
 
-        while !producer.is_full() {
-            producer.push(0.0).expect("Can't fill buffer?");
+// Finds a sample rate supported by both an input and an output config range.
+// Returns the matching ranges along with the rate.  Earlier ranges in each
+// list win ties.
+fn negotiate_sample_rate(
+    input: &[SupportedStreamConfigRange],
+    output: &[SupportedStreamConfigRange],
+) -> Option<(
+    SupportedStreamConfigRange,
+    SupportedStreamConfigRange,
+    SampleRate,
+)> {
+    let mut overlaps = Vec::new();
+    for i in input {
+        for o in output {
+            let low = i.min_sample_rate().max(o.min_sample_rate());
+            let high = i.max_sample_rate().min(o.max_sample_rate());
+            if low <= high {
+                overlaps.push((i, o, low, high));
+            }
         }
+    }
 
-        DelayFilter {
-            decay,
-            producer,
-            consumer,
+    for rate in PREFERRED_SAMPLE_RATES.map(SampleRate) {
+        if let Some((i, o, _, _)) = overlaps
+            .iter()
+            .find(|(_, _, low, high)| *low <= rate && rate <= *high)
+        {
+            return Some(((*i).clone(), (*o).clone(), rate));
         }
     }
 
-    fn filter(&mut self, sample: f32) -> f32 {
-        let last = self.consumer.pop().expect("Delay buffer empty?");
-        let result = sample + last * self.decay;
-        self.producer
-            .push(result)
-            .expect("Unable to refill delay buffer?");
+    // None of the usual rates work, so take the fastest one available.
+    overlaps
+        .iter()
+        .max_by_key(|(_, _, _, high)| *high)
+        .map(|(i, o, _, high)| ((*i).clone(), (*o).clone(), *high))
+}
 
-        return result;
-    }
+// The rate a device should run at when it doesn't have to match another one.
+fn preferred_sample_rate(range: &SupportedStreamConfigRange) -> SampleRate {
+    PREFERRED_SAMPLE_RATES
+        .map(SampleRate)
+        .into_iter()
+        .find(|rate| range.min_sample_rate() <= *rate && *rate <= range.max_sample_rate())
+        .unwrap_or_else(|| range.max_sample_rate())
 }
 
-#[allow(dead_code)]
-struct FlangeFilter {
-    decay: f32,
-    amplitude: f32,
+// The stream settings for both ends of the pipeline.
+#[derive(Clone)]
+struct StreamConfigs {
+    input: StreamConfig,
+    input_format: SampleFormat,
+    output: StreamConfig,
+    output_format: SampleFormat,
+}
 
-    // convert from time in samples to an input to cosine such that we hit
-    // 2pi as t hits sample_rate * frequency
-    offset_coefficient: f32,
+// Picks configs that let the input and output devices run at the same rate,
+// or if they have none in common, the rates each one likes best.  The output
+// callback resamples whenever the rates differ.
+fn negotiate_stream_configs(
+    input_device: &Device,
+    output_device: &Device,
+) -> Result<StreamConfigs, G2Error> {
+    // Try to stick with the output device's usual channel count, and capture
+    // just as many channels as that needs.
+    let output_channels = output_device.default_output_config()?.channels();
+    let mut input_ranges: Vec<_> = input_device.supported_input_configs()?.collect();
+    sort_config_ranges(&mut input_ranges, output_channels);
+    let mut output_ranges: Vec<_> = output_device.supported_output_configs()?.collect();
+    sort_config_ranges(&mut output_ranges, output_channels);
 
-    // elapsed time in samples
-    t: f32,
+    let (input_range, input_rate, output_range, output_rate) =
+        match negotiate_sample_rate(&input_ranges, &output_ranges) {
+            Some((input_range, output_range, sample_rate)) => {
+                (input_range, sample_rate, output_range, sample_rate)
+            }
+            None => {
+                let (Some(input_range), Some(output_range)) =
+                    (input_ranges.first(), output_ranges.first())
+                else {
+                    return Err(G2Error::UnsupportedConfig(format!(
+                        "No usable stream configs.  Input supports {}; output supports {}",
+                        format_ranges(&input_ranges),
+                        format_ranges(&output_ranges)
+                    )));
+                };
+                (
+                    input_range.clone(),
+                    preferred_sample_rate(input_range),
+                    output_range.clone(),
+                    preferred_sample_rate(output_range),
+                )
+            }
+        };
 
-    buffer: Vec<f32>,
-    read_offset: usize,
+    Ok(StreamConfigs {
+        input_format: input_range.sample_format(),
+        input: input_range.with_sample_rate(input_rate).into(),
+        output_format: output_range.sample_format(),
+        output: output_range.with_sample_rate(output_rate).into(),
+    })
 }
 
-#[allow(dead_code)]
-impl FlangeFilter {
-    fn new(
-        buffer_size: usize,
-        sample_rate: SampleRate,
-        frequency: f32,
-        amplitude: f32,
-        decay: f32,
-    ) -> FlangeFilter {
-        let mut buffer = Vec::with_capacity(buffer_size);
-        for _ in 0..buffer_size {
-            buffer.push(0.0);
-        }
+// For sources that aren't a device: the file, generator or pipe supplies
+// f32 samples with the given channel count, and the output device runs at
+// its default config.  Without a rate of its own, the input is taken to run
+// at the output's rate; otherwise it's resampled when they differ.
+fn source_stream_configs(
+    output_device: &Device,
+    channels: u16,
+    sample_rate: Option<SampleRate>,
+) -> Result<StreamConfigs, G2Error> {
+    let output_config = output_device.default_output_config()?;
+    let output_format = output_config.sample_format();
+    let output: StreamConfig = output_config.into();
+
+    Ok(StreamConfigs {
+        input: StreamConfig {
+            channels,
+            sample_rate: sample_rate.unwrap_or(output.sample_rate),
+            ..output.clone()
+        },
+        input_format: SampleFormat::F32,
+        output,
+        output_format,
+    })
+}
 
-        let SampleRate(sr) = sample_rate;
+// How long to wait between attempts to reopen a stream whose device went
+// away, and how many attempts to make before giving up.
+const STREAM_RETRY_INITIAL: Duration = Duration::from_millis(250);
+const STREAM_RETRY_MAX: Duration = Duration::from_secs(4);
+const STREAM_RETRY_ATTEMPTS: u32 = 6;
 
-        let offset_coefficient = PI / (2.0 * frequency * sr as f32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StreamKind {
+    Input,
+    Output,
+}
 
-        FlangeFilter {
-            decay,
-            amplitude,
-            offset_coefficient,
-            t: 0.0,
-            buffer,
-            read_offset: 0,
+impl fmt::Display for StreamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamKind::Input => write!(f, "input"),
+            StreamKind::Output => write!(f, "output"),
         }
     }
+}
 
-    fn read_buffer(&mut self, reverse_offset: usize) -> f32 {
-        let offset = if self.read_offset >= reverse_offset {
-            self.read_offset - reverse_offset
-        } else {
-            self.buffer.len() - reverse_offset + self.read_offset
-        };
-
-        self.buffer[offset]
+// Logs a stream's errors as they happen, and passes them on to the main
+// thread, if it's listening, to decide whether to reopen the stream.  cpal
+// calls this from its own threads, where there's nothing better to do.
+fn stream_error_handler(
+    kind: StreamKind,
+    failures: Option<Sender<(StreamKind, StreamError)>>,
+) -> impl FnMut(StreamError) + Send + 'static {
+    move |err| {
+        eprintln!("Error on the {} stream: {}", kind, err);
+        if let Some(failures) = &failures {
+            let _ = failures.send((kind, err));
+        }
     }
+}
 
-    fn write_buffer(&mut self, sample: f32) {
-        self.buffer[self.read_offset] = sample;
-        self.read_offset += 1;
-        if self.read_offset >= self.buffer.len() {
-            self.read_offset = 0;
-        }
+// A stream that can be rebuilt from scratch after its device goes away.
+// reopen builds and starts a fresh stream around the same callback, which
+// is shared between them through shared_input or shared_output.
+struct RecoverableStream {
+    kind: StreamKind,
+    stream: Option<Stream>,
+    reopen: Box<dyn Fn() -> Result<Stream, Box<dyn Error>>>,
+}
+
+impl RecoverableStream {
+    fn open(
+        kind: StreamKind,
+        reopen: Box<dyn Fn() -> Result<Stream, Box<dyn Error>>>,
+    ) -> Result<RecoverableStream, Box<dyn Error>> {
+        let stream = reopen()?;
+        Ok(RecoverableStream {
+            kind,
+            stream: Some(stream),
+            reopen,
+        })
     }
 
-    fn offset(&self, t: f32) -> usize {
-        let f = t * self.offset_coefficient;
-        let res = (f.cos() + 1.0) * self.amplitude + 1.0;
+    // Handles an error the stream reported: if it might go away by itself,
+    // throws the stream away and keeps trying to open a new one as backoff
+    // allows.  Gives up straight away on anything else, or early if shutdown
+    // is set while waiting.
+    fn recover(
+        &mut self,
+        err: StreamError,
+        backoff: &Backoff,
+        shutdown: &AtomicBool,
+    ) -> Result<(), Box<dyn Error>> {
+        if !is_recoverable(&err) {
+            return Err(format!("The {} stream failed: {}", self.kind, err).into());
+        }
 
-        res as usize
+        self.stream = None;
+        let mut attempt = 0;
+        while let Some(delay) = backoff.delay(attempt) {
+            status!(
+                "Reopening the {} stream in {:.2}s",
+                self.kind,
+                delay.as_secs_f64()
+            );
+            thread::sleep(delay);
+            if shutdown.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            match (self.reopen)() {
+                Ok(stream) => {
+                    status!("Reopened the {} stream", self.kind);
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(err) => eprintln!("Couldn't reopen the {} stream: {}", self.kind, err),
+            }
+            attempt += 1;
+        }
+        Err(format!(
+            "Gave up on the {} stream after {} attempts to reopen it",
+            self.kind, attempt
+        )
+        .into())
     }
 
-    fn filter(&mut self, sample: f32) -> f32 {
-        let reverse_offset = self.offset(self.t);
+    fn pause(&self) {
+        if let Some(stream) = &self.stream {
+            let _ = stream.pause();
+        }
+    }
+}
 
-        let last = self.read_buffer(reverse_offset);
+// Wraps a callback so that each stream built around it gets its own thin
+// forwarding closure, and a reopened stream picks up where the last left off.
+fn shared_input<D>(
+    data_fn: &Arc<Mutex<D>>,
+) -> impl FnMut(&[f32], &InputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+{
+    let data_fn = data_fn.clone();
+    move |data, cbinfo| (data_fn.lock().unwrap())(data, cbinfo)
+}
 
-        let result = sample + last * self.decay;
+fn shared_output<D>(
+    data_fn: &Arc<Mutex<D>>,
+) -> impl FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static
+where
+    D: FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+{
+    let data_fn = data_fn.clone();
+    move |data, cbinfo| (data_fn.lock().unwrap())(data, cbinfo)
+}
 
-        self.write_buffer(result);
+// Builds an input stream in the device's native sample format.  The callback
+// always sees f32 samples, so the effects never have to care what the
+// hardware uses.
+fn open_input_stream<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    data_fn: D,
+    error_fn: E,
+) -> Result<Stream, G2Error>
+where
+    D: FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    match format {
+        SampleFormat::F32 => Ok(device.build_input_stream(config, data_fn, error_fn)?),
+        SampleFormat::I16 => {
+            open_converted_input_stream::<i16, D, E>(device, config, data_fn, error_fn)
+        }
+        SampleFormat::U16 => {
+            open_converted_input_stream::<u16, D, E>(device, config, data_fn, error_fn)
+        }
+    }
+}
 
-        self.t += 1.0;
+fn open_converted_input_stream<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut data_fn: D,
+    error_fn: E,
+) -> Result<Stream, G2Error>
+where
+    T: Sample,
+    D: FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let mut converted = Vec::new();
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], cbinfo: &InputCallbackInfo| {
+            samples_to_f32(data, &mut converted);
+            data_fn(&converted, cbinfo);
+        },
+        error_fn,
+    )?;
+    Ok(stream)
+}
 
-        result
+// The output counterpart of open_input_stream: the callback fills an f32
+// buffer, which is then converted to whatever the device plays.
+fn open_output_stream<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    data_fn: D,
+    error_fn: E,
+) -> Result<Stream, G2Error>
+where
+    D: FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    match format {
+        SampleFormat::F32 => Ok(device.build_output_stream(config, data_fn, error_fn)?),
+        SampleFormat::I16 => {
+            open_converted_output_stream::<i16, D, E>(device, config, data_fn, error_fn)
+        }
+        SampleFormat::U16 => {
+            open_converted_output_stream::<u16, D, E>(device, config, data_fn, error_fn)
+        }
     }
 }
 
-// Distortion is easy: You magnify the signal, then clamp samples to make the wave more square.
-#[allow(dead_code)]
-struct DistortFilter {
-    gain: f32,
+fn open_converted_output_stream<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut data_fn: D,
+    error_fn: E,
+) -> Result<Stream, G2Error>
+where
+    T: Sample,
+    D: FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let mut converted = Vec::new();
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], cbinfo: &OutputCallbackInfo| {
+            converted.resize(data.len(), 0.0);
+            data_fn(&mut converted, cbinfo);
+            samples_from_f32(&converted, data);
+        },
+        error_fn,
+    )?;
+    Ok(stream)
+}
 
-    // Min/max value to clamp outgoing samples to.  Should be 1.0 or less.
-    saturation: f32,
+// Replaces output with input's samples as f32.
+fn samples_to_f32<T: Sample>(input: &[T], output: &mut Vec<f32>) {
+    output.clear();
+    output.extend(input.iter().map(Sample::to_f32));
 }
 
-#[allow(dead_code)]
-impl DistortFilter {
-    fn new(gain: f32, saturation: f32) -> DistortFilter {
-        DistortFilter { gain, saturation }
+// Converts input into output's format.  Any samples past the end of the
+// shorter of the two are left alone.
+fn samples_from_f32<T: Sample>(input: &[f32], output: &mut [T]) {
+    for (out, sample) in output.iter_mut().zip(input) {
+        *out = T::from(sample);
     }
+}
 
-    fn filter(&self, sample: f32) -> f32 {
-        (sample * self.gain).clamp(-self.saturation, self.saturation)
-    }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Effect {
+    Distort,
+    Delay,
+    Flange,
+    Lowpass,
+    Highpass,
+    Reverb,
+    Gate,
+    Compress,
+    Bitcrush,
+    Tremolo,
+    Vibrato,
+    Pingpong,
+    Phaser,
+    Chorus,
+    Ringmod,
+    Overdrive,
+    Autowah,
+    Width,
+    Pitch,
+    Deesser,
+    Convolve,
+    Tape,
+    Multiband,
+    Panner,
+    Expander,
+    Foldback,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Input device
+    #[clap(short, long, value_parser)]
+    input_device: Option<usize>,
 
-    let host = cpal::default_host();
+    /// Output device
+    #[clap(short, long, value_parser)]
+    output_device: Option<usize>,
 
-    if args.list {
-        list_input_devices(&host)?;
-        println!("");
-        list_output_devices(&host)?;
-        return Ok(());
-    }
+    /// Select the input device by a case-insensitive substring of its name
+    #[clap(long, value_parser)]
+    input_name: Option<String>,
 
-    let input_device = nth_input_device(&host, args.input_device)?;
-    let output_device = nth_output_device(&host, args.output_device)?;
+    /// Select the output device by a case-insensitive substring of its name
+    #[clap(long, value_parser)]
+    output_name: Option<String>,
 
-    println!("Using {}", input_device.name()?);
-    println!("And {}", output_device.name()?);
+    /// List available input and output devices
+    #[clap(long)]
+    list: bool,
 
-    const BUFFER_SIZE: usize = 960;
-    let buffer: RingBuffer<f32> = RingBuffer::new(BUFFER_SIZE);
-    let (mut producer, mut consumer) = buffer.split();
+    /// List the devices as a JSON array, for other programs to read
+    #[clap(long)]
+    list_json: bool,
 
-    let mut supported_configs = input_device.supported_input_configs()?;
+    /// Audio host (backend) to use, such as ALSA, JACK, WASAPI or ASIO
+    #[clap(long)]
+    host: Option<String>,
 
-    let supported_config = supported_configs.next().unwrap().with_max_sample_rate();
+    /// List the audio hosts available in this build
+    #[clap(long)]
+    list_hosts: bool,
 
-    let config: StreamConfig = supported_config.into();
+    /// Effect to apply to the input signal [default: distort]
+    #[clap(short, long, value_enum)]
+    effect: Option<Effect>,
 
-    println!("Sample rate {:?}", config.sample_rate);
-    println!("");
+    /// Use a built-in effect chain.  An explicit --effect, --config or
+    /// --chain takes precedence.
+    #[clap(long, value_enum)]
+    preset: Option<Preset>,
 
-    // let mut delay = DelayFilter::new(10000, 0.9);
-    // let mut filter = FlangeFilter::new(10000, config.sample_rate, 0.5, 100.0, 0.8);
-    let filter = DistortFilter::new(12.0, 0.7);
+    /// Load an effect chain from a TOML file instead of using --effect.
+    /// Parameters the file leaves out fall back to the command-line flags.
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
 
-    let input_data_fn = move |data: &[f32], _cbinfo: &InputCallbackInfo| {
-        for datum in data {
-            producer
-                .push(*datum)
-                .expect("Unable to refill output buffer");
-        }
-    };
+    /// An effect chain written inline, as space-separated
+    /// name:param=value,... stages, e.g. "highpass:cutoff=100
+    /// distort:gain=12,saturation=0.7".  Parameters left out fall back to
+    /// the command-line flags, as with --config.
+    #[clap(long, value_parser = parse_chain, conflicts_with = "config")]
+    chain: Option<ChainConfig>,
 
-    let output_data_fn = move |data: &mut [f32], _cbinfo: &OutputCallbackInfo| {
-        for sample in data {
-            *sample = match consumer.pop() {
-                Some(s) => filter.filter(s),
-                None => 0.0,
-            }
-        }
-    };
+    /// Effect chains run side by side on the same input and summed,
+    /// separated by "|".  Each branch is written like --chain, or as "dry"
+    /// for the unprocessed input, and may start with level=<dB> to set its
+    /// volume, e.g. "dry | level=-6 reverb:size=0.9".
+    #[clap(long, value_parser = parse_parallel, conflicts_with_all = &["config", "chain"])]
+    parallel: Option<ParallelConfig>,
 
-    let input_stream = input_device.build_input_stream(&config, input_data_fn, &on_error)?;
-    let output_stream = output_device.build_output_stream(&config, output_data_fn, &on_error)?;
-    input_stream.play()?;
-    output_stream.play()?;
+    /// Distortion gain
+    #[clap(long, value_parser, default_value_t = 12.0)]
+    gain: f32,
+
+    /// Distortion saturation level, and the level foldback folds at (0.0 -
+    /// 1.0)
+    #[clap(long, value_parser, default_value_t = 0.7)]
+    saturation: f32,
 
-    let s = &mut String::new();
-    let _ = std::io::stdin().read_line(s);
+    /// Distortion clipping curve
+    #[clap(long, value_enum, default_value = "hard")]
+    distort_mode: DistortMode,
 
-    println!("Goodbye World!");
+    /// Foldback input gain: how many times the signal folds back on itself
+    #[clap(long, value_parser, default_value_t = 2.0)]
+    fold_amount: f32,
 
-    Ok(())
+    /// Scale the distortion's output back to the level of its input, so the
+    /// gain changes the tone without changing the volume
+    #[clap(long)]
+    distort_autogain: bool,
+
+    /// Feedback decay for delay effects and flange (default 0.9 for delay, 0.7 for pingpong,
+    /// 0.8 for flange)
+    #[clap(long, value_parser)]
+    decay: Option<f32>,
+
+    /// How much duller each delay echo gets than the last (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    delay_damping: f32,
+
+    /// Length of the delay line in frames
+    #[clap(long, value_parser, default_value_t = 10000)]
+    delay_frames: usize,
+
+    /// Flange sweep frequency
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    frequency: f32,
+
+    /// Shape of the flange sweep
+    #[clap(long, value_enum, default_value_t = LfoShape::Sine)]
+    flange_lfo: LfoShape,
+
+    /// Flange sweep amplitude, in frames
+    #[clap(long, value_parser, default_value_t = 100.0)]
+    amplitude: f32,
+
+    /// Filter cutoff frequency in Hz
+    #[clap(long, value_parser, default_value_t = 2000.0)]
+    cutoff: f32,
+
+    /// Filter resonance (Q)
+    #[clap(long, value_parser, default_value_t = 0.707)]
+    q: f32,
+
+    /// Parametric EQ band as freq:q:gain_db; repeat to add more bands
+    #[clap(long = "eq", value_parser = parse_eq_band)]
+    eq: Vec<EqBand>,
+
+    /// Reverb room size (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    room_size: f32,
+
+    /// Reverb high-frequency damping (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    damping: f32,
+
+    /// Reverb wet level (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.33)]
+    wet: f32,
+
+    /// Level at which the noise gate or expander opens, as a linear amplitude
+    #[clap(long, value_parser, default_value_t = 0.02)]
+    threshold: f32,
+
+    /// Level below which the noise gate closes again, as a linear amplitude
+    /// [default: half of --threshold]
+    #[clap(long, value_parser)]
+    close_threshold: Option<f32>,
+
+    /// Compressor threshold, in dBFS
+    #[clap(long, value_parser, default_value_t = -20.0, allow_hyphen_values = true)]
+    threshold_db: f32,
+
+    /// Compression ratio, or for the expander, how many dB the output drops
+    /// for each dB the input falls below --threshold
+    #[clap(long, value_parser, default_value_t = 4.0)]
+    ratio: f32,
+
+    /// Gain applied after compression, in dB
+    #[clap(long, value_parser, default_value_t = 0.0, allow_hyphen_values = true)]
+    makeup_gain_db: f32,
+
+    /// Width of the compressor's soft knee around --threshold-db, in dB.  0
+    /// is a hard knee.
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    comp_knee: f32,
+
+    /// Drive --effect compress from this input channel instead of from the
+    /// signal itself, so one channel can duck the others.  The key channel
+    /// passes through uncompressed.
+    #[clap(long, value_parser, conflicts_with = "sidechain-file")]
+    sidechain_channel: Option<usize>,
+
+    /// Drive --effect compress from this WAV file, played from the start
+    /// alongside the input, instead of from the signal itself
+    #[clap(long, value_parser)]
+    sidechain_file: Option<PathBuf>,
+
+    /// Time for the gate to open or the compressor to react, in milliseconds
+    #[clap(long, value_parser, default_value_t = 5.0)]
+    attack_ms: f32,
+
+    /// Time for the gate to close or the compressor to recover, in milliseconds
+    #[clap(long, value_parser, default_value_t = 100.0)]
+    release_ms: f32,
+
+    /// Bitcrusher bit depth (1 - 16)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=16), default_value_t = 8)]
+    bits: u32,
+
+    /// Bitcrusher sample-and-hold factor
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1)]
+    downsample: u64,
+
+    /// Modulation rate in Hz (default 5.0 for tremolo and vibrato, 0.5 for phaser and panner,
+    /// 0.8 for chorus)
+    #[clap(long, value_parser)]
+    rate: Option<f32>,
+
+    /// Modulation depth for tremolo, phaser and panner (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    depth: f32,
+
+    /// Number of allpass stages in the phaser
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..=MAX_PHASER_STAGES as u64), default_value_t = 4)]
+    stages: u64,
+
+    /// Phaser feedback (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    feedback: f32,
+
+    /// Number of chorus voices
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..=MAX_CHORUS_VOICES as u64), default_value_t = 3)]
+    voices: u64,
+
+    /// Level of the chorus voices against the dry signal (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    chorus_mix: f32,
+
+    /// Ring modulator carrier frequency, in Hz
+    #[clap(long, value_parser, default_value_t = 500.0)]
+    carrier: f32,
+
+    /// Level of the ring-modulated signal against the dry signal (0.0 - 1.0)
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    ringmod_mix: f32,
+
+    /// Overdrive and tape input gain
+    #[clap(long, value_parser, default_value_t = 4.0)]
+    drive: f32,
+
+    /// Overdrive output level
+    #[clap(long, value_parser, default_value_t = 0.8)]
+    level: f32,
+
+    /// Run distort, foldback and overdrive at 2 or 4 times the sample rate,
+    /// so the harmonics they add alias less
+    #[clap(long, value_parser = parse_oversample, default_value_t = 1)]
+    oversample: usize,
+
+    /// How strongly the input level sweeps the auto-wah filter
+    #[clap(long, value_parser, default_value_t = 4.0)]
+    sensitivity: f32,
+
+    /// Auto-wah resting frequency, in Hz
+    #[clap(long, value_parser, default_value_t = 300.0)]
+    min_freq: f32,
+
+    /// Auto-wah frequency at full sweep, in Hz
+    #[clap(long, value_parser, default_value_t = 2500.0)]
+    max_freq: f32,
+
+    /// Auto-wah filter resonance (Q)
+    #[clap(long, value_parser, default_value_t = 5.0)]
+    wah_q: f32,
+
+    /// Impulse response WAV for the convolve effect.  Multichannel files are
+    /// mixed down to mono.
+    #[clap(long, value_parser)]
+    ir: Option<PathBuf>,
+
+    /// De-esser detection frequency, in Hz
+    #[clap(long, value_parser, default_value_t = 6000.0)]
+    deess_freq: f32,
+
+    /// Sibilance level, as a linear amplitude, above which the de-esser
+    /// turns the signal down.  It uses --ratio as well.
+    #[clap(long, value_parser, default_value_t = 0.05)]
+    deess_threshold: f32,
+
+    /// Tape treble, from 0.0 (dark) to 1.0 (bright)
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    tape_tone: f32,
+
+    /// How far tape wow and flutter sweeps the delay, in milliseconds
+    #[clap(long, value_parser, default_value_t = 0.3)]
+    flutter_depth: f32,
+
+    /// Multiband distortion crossover between the clean low band and the
+    /// mid band, in Hz
+    #[clap(long, value_parser, default_value_t = 200.0)]
+    crossover_low: f32,
+
+    /// Multiband distortion crossover between the mid and high bands, in Hz
+    #[clap(long, value_parser, default_value_t = 2000.0)]
+    crossover_high: f32,
+
+    /// Stereo width: 0.0 is mono, 1.0 unchanged, more than 1.0 wider
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    width: f32,
+
+    /// Pitch shift, in semitones
+    #[clap(long, value_parser, default_value_t = 0.0, allow_hyphen_values = true)]
+    semitones: f32,
+
+    /// Vibrato and chorus delay sweep depth, in milliseconds
+    #[clap(long, value_parser, default_value_t = 2.0)]
+    depth_ms: f32,
+
+    /// Wet/dry mix of the effect, from 0.0 (dry) to 1.0 (fully processed).
+    /// Feedback inside the effect is unaffected.
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    mix: f32,
+
+    /// Gain applied before the effect, in dB
+    #[clap(long, value_parser, default_value_t = 0.0, allow_hyphen_values = true)]
+    input_gain: f32,
+
+    /// Gain applied after the effect and EQ, in dB
+    #[clap(long, value_parser, default_value_t = 0.0, allow_hyphen_values = true)]
+    output_gain: f32,
+
+    /// Finish with a lookahead limiter so the output never clips
+    #[clap(long)]
+    limit: bool,
+
+    /// Highest level the limiter lets through, as a linear amplitude
+    #[clap(long, value_parser, default_value_t = 0.98)]
+    ceiling: f32,
+
+    /// Average all input channels into one signal, played on every output
+    /// channel
+    #[clap(long)]
+    mono: bool,
+
+    /// Copy input straight to output, skipping the effect and EQ.  Input and
+    /// output gain still apply so levels match for A/B comparison.
+    #[clap(long)]
+    passthrough: bool,
+
+    /// Swap the left and right output channels
+    #[clap(long)]
+    swap_channels: bool,
+
+    /// Flip the polarity of every output channel
+    #[clap(long)]
+    invert_phase: bool,
+
+    /// Delay one channel by a whole number of frames, as channel:frames with
+    /// channels counted from 0; repeat for more channels
+    #[clap(long = "channel-delay", value_parser = parse_channel_delay)]
+    channel_delay: Vec<ChannelOffset>,
+
+    /// Silence the output.  Recording carries on as normal.
+    #[clap(long)]
+    mute_input: bool,
+
+    /// Bypass every stage of a --config, --chain or --preset chain except
+    /// this one, counting from 0
+    #[clap(long, value_parser)]
+    solo: Option<usize>,
+
+    /// Bypass this stage of the effect chain, counting from 0; repeat to
+    /// bypass more.  Type b and the stage number while running to toggle it.
+    #[clap(long, value_parser)]
+    bypass: Vec<usize>,
+
+    /// Frames of buffering between input and output (default 20ms worth)
+    #[clap(long, value_parser)]
+    buffer_frames: Option<usize>,
+
+    /// Abort on buffer overruns instead of dropping samples
+    #[clap(long)]
+    strict: bool,
+
+    /// Show input level meters on stderr
+    #[clap(long)]
+    meter: bool,
+
+    /// Log buffer levels, dropouts and callback timing to stderr once a
+    /// second
+    #[clap(long)]
+    verbose: bool,
+
+    /// Time each stage of the effect in the output callback, and print how
+    /// much of the real-time budget each one used on exit
+    #[clap(long)]
+    profile: bool,
+
+    /// Show a spectrum of the input on stderr
+    #[clap(long, conflicts_with = "meter")]
+    spectrum: bool,
+
+    /// Samples per spectrum FFT; must be a power of two
+    #[clap(long, value_parser = parse_fft_size, default_value_t = 2048)]
+    fft_size: usize,
+
+    /// Fade the output in over this many milliseconds when starting, and out
+    /// again when stopping
+    #[clap(long, value_parser, default_value_t = 0)]
+    fade_in_ms: u64,
+
+    /// How long the level meters hold their peak, in milliseconds
+    #[clap(long, value_parser, default_value_t = 1500)]
+    peak_hold_ms: u64,
+
+    /// Control the distortion gain and delay decay with MIDI knobs on the
+    /// first MIDI input port
+    #[cfg(feature = "midi")]
+    #[clap(long)]
+    midi: bool,
+
+    /// MIDI controller number that sets the distortion gain
+    #[cfg(feature = "midi")]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=127), default_value_t = 1)]
+    midi_gain_cc: u8,
+
+    /// MIDI controller number that sets the delay decay
+    #[cfg(feature = "midi")]
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=127), default_value_t = 2)]
+    midi_decay_cc: u8,
+
+    /// Measure round-trip latency by playing an impulse and listening for it
+    /// on the input.  Needs the output looped back into the input.
+    #[clap(long)]
+    measure_latency: bool,
+
+    /// Print the devices, stream settings and effect chain a run would use,
+    /// after negotiating with the devices, as JSON on stdout, then exit
+    #[clap(long, conflicts_with = "measure-latency")]
+    show_config: bool,
+
+    /// Play a sine sweep through the effect offline, without any audio
+    /// devices, and print its gain at each third-octave frequency as CSV.
+    /// Runs at --raw-rate, or 48kHz.
+    #[clap(long)]
+    measure_response: bool,
+
+    /// Stop after this many seconds instead of waiting for Enter
+    #[clap(long, value_parser = parse_seconds)]
+    duration: Option<Duration>,
+
+    /// Stop, finish the recording and exit with an error once the output
+    /// keeps clipping, rather than carry on capturing distortion
+    #[clap(long)]
+    clip_detect_abort: bool,
+
+    /// Percentage of the samples in one --clip-abort-window that may clip
+    /// before --clip-detect-abort stops
+    #[clap(long, value_parser = parse_percentage, default_value_t = 1.0)]
+    clip_abort_threshold: f32,
+
+    /// Seconds of output --clip-detect-abort judges the clipping over at a time
+    #[clap(long, value_parser = parse_seconds, default_value = "1")]
+    clip_abort_window: Duration,
+
+    /// Process --input-file or --stdin-raw offline without opening any audio
+    /// devices, and
+    /// report statistics.  Writes to --record, or raw f32 samples on stdout.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Record the processed output to a WAV file
+    #[clap(long, value_parser)]
+    record: Option<PathBuf>,
+
+    /// Sample size for --record: 16 or 24 for PCM, or 32 for floating point
+    #[clap(long, value_parser = parse_record_bits, default_value_t = 32)]
+    record_bits: u16,
+
+    /// Add TPDF dither wherever samples are rounded to fewer bits: PCM
+    /// recordings and the bitcrusher
+    #[clap(long)]
+    dither: bool,
+
+    /// Seed for --dither's noise, for output that's the same every run
+    /// [default: from the clock]
+    #[clap(long, value_parser)]
+    seed: Option<u32>,
+
+    /// Also write the output to stdout as interleaved little-endian f32
+    /// samples, for piping into another program.  Messages go to stderr.
+    #[clap(long)]
+    stdout_raw: bool,
+
+    /// Play a WAV file through the effect instead of live input
+    #[clap(long, value_parser)]
+    input_file: Option<PathBuf>,
+
+    /// Start --input-file over from the top each time it ends, until stopped
+    #[clap(long = "loop", requires = "input-file")]
+    loop_input: bool,
+
+    /// Read interleaved little-endian f32 samples from stdin instead of live
+    /// input, stopping at EOF.  The stream has --raw-channels channels and
+    /// runs at --raw-rate.
+    #[clap(long, conflicts_with_all = &["input-file", "generate"])]
+    stdin_raw: bool,
+
+    /// Channels in the --stdin-raw stream
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 1)]
+    raw_channels: u16,
+
+    /// Sample rate of the --stdin-raw stream.  By default it's assumed to
+    /// match the output device; otherwise it's resampled as needed.
+    #[clap(long, value_parser)]
+    raw_rate: Option<u32>,
+
+    /// Play a generated test signal through the effect instead of live input
+    #[clap(long, value_enum, conflicts_with = "input-file")]
+    generate: Option<Signal>,
+
+    /// Frequency of the --generate sine, in Hz
+    #[clap(long, value_parser, default_value_t = 440.0)]
+    tone_freq: f32,
+
+    /// Peak level of the --generate signal
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    tone_amp: f32,
+
+    /// How to resample when the input and output rates differ
+    #[clap(long, value_enum, default_value_t = ResampleQuality::Cubic)]
+    resample: ResampleQuality,
+}
+
+// Named effect parameters, as read from a config file.  Values are kept as
+// strings and parsed when the effect asks for them, so every source of
+// parameters can share one set of validation rules.  Anything not given falls
+// back to the matching command-line flag.
+#[derive(Default)]
+struct Params {
+    values: HashMap<String, String>,
+    used: HashSet<&'static str>,
+
+    // Every parameter the effect asked for, with the value it ended up with,
+    // for --show-config.
+    resolved: BTreeMap<&'static str, ParamValue>,
+}
+
+impl Params {
+    fn new(values: HashMap<String, String>) -> Params {
+        Params {
+            values,
+            used: HashSet::new(),
+            resolved: BTreeMap::new(),
+        }
+    }
+
+    fn resolve(&mut self, name: &'static str, value: ParamValue) {
+        self.resolved.insert(name, value);
+    }
+
+    fn lookup(&mut self, name: &'static str) -> Option<&str> {
+        self.used.insert(name);
+        self.values.get(name).map(String::as_str)
+    }
+
+    fn number<R>(&mut self, name: &'static str, default: f32, valid: R) -> Result<f32, String>
+    where
+        R: RangeBounds<f32> + fmt::Debug,
+    {
+        let value = match self.lookup(name) {
+            Some(text) => {
+                let value: f32 = text
+                    .parse()
+                    .map_err(|_| format!("{} must be a number, not \"{}\"", name, text))?;
+                if !valid.contains(&value) {
+                    return Err(format!("{} = {} is outside {:?}", name, value, valid));
+                }
+                value
+            }
+            None => default,
+        };
+        self.resolve(name, value.into());
+        Ok(value)
+    }
+
+    fn integer<R>(&mut self, name: &'static str, default: usize, valid: R) -> Result<usize, String>
+    where
+        R: RangeBounds<usize> + fmt::Debug,
+    {
+        let value = match self.lookup(name) {
+            Some(text) => {
+                let value: usize = text
+                    .parse()
+                    .map_err(|_| format!("{} must be a whole number, not \"{}\"", name, text))?;
+                if !valid.contains(&value) {
+                    return Err(format!("{} = {} is outside {:?}", name, value, valid));
+                }
+                value
+            }
+            None => default,
+        };
+        self.resolve(name, value.into());
+        Ok(value)
+    }
+
+    fn choice<T: ValueEnum>(&mut self, name: &'static str, default: T) -> Result<T, String> {
+        let value = match self.lookup(name) {
+            Some(text) => T::from_str(text, true).map_err(|_| {
+                let names: Vec<_> = T::value_variants()
+                    .iter()
+                    .filter_map(|v| v.to_possible_value())
+                    .map(|v| v.get_name().to_string())
+                    .collect();
+                format!(
+                    "{} must be one of {}, not \"{}\"",
+                    name,
+                    names.join(", "),
+                    text
+                )
+            })?,
+            None => default,
+        };
+        self.resolve(name, value_name(&value).into());
+        Ok(value)
+    }
+
+    fn flag(&mut self, name: &'static str, default: bool) -> Result<bool, String> {
+        let value = match self.lookup(name) {
+            Some(text) => text
+                .parse()
+                .map_err(|_| format!("{} must be true or false, not \"{}\"", name, text))?,
+            None => default,
+        };
+        self.resolve(name, value.into());
+        Ok(value)
+    }
+
+    // Fails if any parameter was given that the effect never asked for.
+    fn finish(self) -> Result<(), String> {
+        let mut unknown: Vec<_> = self
+            .values
+            .keys()
+            .filter(|name| !self.used.contains(name.as_str()))
+            .cloned()
+            .collect();
+        unknown.sort();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unknown parameters: {}", unknown.join(", ")))
+        }
+    }
+}
+
+// Builds a per-sample effect, wrapped in its wet/dry mix.  With controls,
+// the parameters they cover follow the keyboard instead of staying fixed.
+fn build_effect(
+    effect: Effect,
+    params: &mut Params,
+    args: &Args,
+    controls: Option<&LiveControls>,
+    sample_rate: SampleRate,
+) -> Result<Box<dyn Filter>, String> {
+    let filter: Box<dyn Filter> = match effect {
+        Effect::Distort => {
+            let gain = params.number("gain", args.gain, 0.0..)?;
+            let gain = match controls {
+                Some(controls) => controls.gain.clone(),
+                None => SharedParam::new(gain),
+            };
+            let mode = params.choice("mode", args.distort_mode)?;
+            let saturation = saturation(params, args, mode)?;
+            let mut distort = DistortFilter::shared(gain, saturation, mode);
+            if params.flag("auto_gain", args.distort_autogain)? {
+                // The level follower runs inside any oversampler, so it has
+                // to be timed at the oversampled rate.
+                let factor = oversample_factor(params, args)?;
+                distort.enable_auto_gain(SampleRate(sample_rate.0 * factor as u32));
+            }
+            oversampled(distort, params, args, sample_rate)?
+        }
+        Effect::Delay => {
+            let decay = params.number("decay", args.decay.unwrap_or(0.9), 0.0..1.0)?;
+            let decay = match controls {
+                Some(controls) => controls.decay.clone(),
+                None => SharedParam::new(decay),
+            };
+            Box::new(
+                DelayFilter::shared(
+                    params.integer("frames", args.delay_frames, 1..)?,
+                    decay,
+                    params.number("damping", args.delay_damping, 0.0..=1.0)?,
+                )
+                .map_err(|err| err.to_string())?,
+            )
+        }
+        Effect::Flange => Box::new(FlangeFilter::new(
+            params.integer("frames", args.delay_frames, 1..)?,
+            sample_rate,
+            params.number("frequency", args.frequency, 0.0..)?,
+            params.number("amplitude", args.amplitude, 0.0..)?,
+            params.number("decay", args.decay.unwrap_or(0.8), 0.0..)?,
+            params.choice("lfo", args.flange_lfo)?,
+        )),
+        Effect::Lowpass => {
+            let (cutoff, q) = cutoff_and_q(params, args, sample_rate)?;
+            Box::new(LowPassFilter::new(cutoff, q, sample_rate))
+        }
+        Effect::Highpass => {
+            let (cutoff, q) = cutoff_and_q(params, args, sample_rate)?;
+            Box::new(HighPassFilter::new(cutoff, q, sample_rate))
+        }
+        Effect::Reverb => Box::new(ReverbFilter::new(
+            params.number("room_size", args.room_size, 0.0..=1.0)?,
+            params.number("damping", args.damping, 0.0..=1.0)?,
+            params.number("wet", args.wet, 0.0..=1.0)?,
+            sample_rate,
+        )),
+        Effect::Gate | Effect::Expander => {
+            let open = params.number("threshold", args.threshold, 0.0..)?;
+            let close = params.number(
+                "close_threshold",
+                args.close_threshold.unwrap_or(open / 2.0),
+                0.0..=open,
+            )?;
+            let mut gate = NoiseGate::new(
+                open,
+                close,
+                params.number("attack_ms", args.attack_ms, 0.0..)?,
+                params.number("release_ms", args.release_ms, 0.0..)?,
+                sample_rate,
+            );
+            if let Effect::Expander = effect {
+                gate.enable_expansion(params.number("ratio", args.ratio, 1.0..)?);
+            }
+            Box::new(gate)
+        }
+        Effect::Compress => {
+            let mut compressor = Compressor::new(
+                params.number("threshold_db", args.threshold_db, ..=0.0)?,
+                params.number("ratio", args.ratio, 1.0..)?,
+                params.number("attack_ms", args.attack_ms, 0.0..)?,
+                params.number("release_ms", args.release_ms, 0.0..)?,
+                params.number("makeup_gain_db", args.makeup_gain_db, ..)?,
+                sample_rate,
+            );
+            compressor.set_knee(params.number("knee_db", args.comp_knee, 0.0..)?);
+            Box::new(compressor)
+        }
+        Effect::Bitcrush => {
+            let mut crusher = BitCrusher::new(
+                params.integer("bits", args.bits as usize, 1..=16)? as u32,
+                params.integer("downsample", args.downsample as usize, 1..)?,
+            );
+            if params.flag("dither", args.dither)? {
+                crusher.enable_dither(dither_seed(args));
+            }
+            Box::new(crusher)
+        }
+        Effect::Tremolo => Box::new(Tremolo::new(
+            params.number("rate", args.rate.unwrap_or(5.0), 0.0..)?,
+            params.number("depth", args.depth, 0.0..=1.0)?,
+            sample_rate,
+        )),
+        Effect::Vibrato => Box::new(Vibrato::new(
+            params.number("rate", args.rate.unwrap_or(5.0), 0.0..)?,
+            params.number("depth_ms", args.depth_ms, 0.0..)?,
+            sample_rate,
+        )),
+        Effect::Phaser => Box::new(Phaser::new(
+            params.integer("stages", args.stages as usize, 1..=MAX_PHASER_STAGES)?,
+            params.number("rate", args.rate.unwrap_or(0.5), 0.0..)?,
+            params.number("depth", args.depth, 0.0..=1.0)?,
+            params.number("feedback", args.feedback, 0.0..1.0)?,
+            sample_rate,
+        )),
+        Effect::Chorus => Box::new(Chorus::new(
+            params.integer("voices", args.voices as usize, 1..=MAX_CHORUS_VOICES)?,
+            params.number("rate", args.rate.unwrap_or(0.8), 0.0..)?,
+            params.number("depth_ms", args.depth_ms, 0.0..)?,
+            params.number("chorus_mix", args.chorus_mix, 0.0..=1.0)?,
+            sample_rate,
+        )),
+        Effect::Ringmod => Box::new(RingModulator::new(
+            params.number("carrier", args.carrier, 0.0..)?,
+            params.number("ringmod_mix", args.ringmod_mix, 0.0..=1.0)?,
+            sample_rate,
+        )),
+        Effect::Foldback => {
+            let folder = DistortFilter::new(
+                params.number("fold_amount", args.fold_amount, 0.0..)?,
+                saturation(params, args, DistortMode::Foldback)?,
+                DistortMode::Foldback,
+            );
+            oversampled(folder, params, args, sample_rate)?
+        }
+        Effect::Overdrive => {
+            let overdrive = Overdrive::new(
+                params.number("drive", args.drive, 0.0..)?,
+                params.number("level", args.level, 0.0..)?,
+            );
+            oversampled(overdrive, params, args, sample_rate)?
+        }
+        Effect::Autowah => {
+            let min_freq = params.number("min_freq", args.min_freq, 1.0..)?;
+            Box::new(AutoWah::new(
+                params.number("sensitivity", args.sensitivity, 0.0..)?,
+                min_freq,
+                params.number("max_freq", args.max_freq, min_freq..)?,
+                params.number("wah_q", args.wah_q, 0.01..)?,
+                params.number("attack_ms", args.attack_ms, 0.0..)?,
+                params.number("release_ms", args.release_ms, 0.0..)?,
+                sample_rate,
+            ))
+        }
+        Effect::Pitch => Box::new(PitchShifter::new(
+            params.number("semitones", args.semitones, -48.0..=48.0)?,
+            sample_rate,
+        )),
+        Effect::Deesser => Box::new(DeEsser::new(
+            params.number("freq_hz", args.deess_freq, 1.0..)?,
+            params.number("threshold", args.deess_threshold, 0.0..)?,
+            params.number("ratio", args.ratio, 1.0..)?,
+            sample_rate,
+        )),
+        Effect::Tape => Box::new(TapeSaturation::new(
+            params.number("drive", args.drive, 0.0..)?,
+            params.number("tone", args.tape_tone, 0.0..=1.0)?,
+            params.number("flutter_depth", args.flutter_depth, 0.0..)?,
+            sample_rate,
+        )),
+        // The low band is left clean so the bass stays tight, and the mid and
+        // high bands are distorted separately so they don't intermodulate.
+        Effect::Multiband => {
+            let nyquist = sample_rate.0 as f32 / 2.0;
+            let low_hz = params.number("crossover_low", args.crossover_low, 0.0..nyquist)?;
+            let high_hz = params.number("crossover_high", args.crossover_high, 0.0..nyquist)?;
+            if low_hz <= 0.0 || high_hz <= low_hz {
+                return Err(format!(
+                    "The crossovers must satisfy 0 < {} < {}",
+                    low_hz, high_hz
+                ));
+            }
+            let gain = params.number("gain", args.gain, 0.0..)?;
+            let mode = params.choice("mode", args.distort_mode)?;
+            let saturation = saturation(params, args, mode)?;
+            let distort =
+                || -> Box<dyn Filter> { Box::new(DistortFilter::new(gain, saturation, mode)) };
+            Box::new(Multiband::new(
+                low_hz,
+                high_hz,
+                sample_rate,
+                [Box::new(FilterChain::new()), distort(), distort()],
+            ))
+        }
+        Effect::Convolve => {
+            let path = match params.lookup("ir") {
+                Some(path) => PathBuf::from(path),
+                None => args
+                    .ir
+                    .clone()
+                    .ok_or("The convolve effect needs an --ir file")?,
+            };
+            params.resolve("ir", path.display().to_string().into());
+            Box::new(ConvolutionReverb::new(load_mono_wav(&path, sample_rate)?))
+        }
+        Effect::Pingpong | Effect::Width | Effect::Panner => {
+            return Err(format!(
+                "{:?} works on whole frames and can't be part of a chain",
+                effect
+            )
+            .to_lowercase())
+        }
+    };
+
+    let mix = params.number("mix", args.mix, 0.0..=1.0)?;
+    Ok(Box::new(WetDry::new(filter, mix)))
+}
+
+// The level a distortion clips or folds at.  Hard clipping to a negative
+// level would panic and soft clipping divides by it, so like the filter
+// shapes below it's checked again for the command line's value.
+fn saturation(params: &mut Params, args: &Args, mode: DistortMode) -> Result<f32, String> {
+    let saturation = params.number("saturation", args.saturation, 0.0..=1.0)?;
+    if !(0.0..=1.0).contains(&saturation) {
+        return Err(format!("saturation = {} is outside 0.0..=1.0", saturation));
+    }
+    if saturation == 0.0 && matches!(mode, DistortMode::Soft) {
+        return Err("saturation must be above 0 for soft distortion".to_string());
+    }
+    Ok(saturation)
+}
+
+// The smallest q a low- or high-pass accepts.  At zero every output sample
+// comes out NaN.
+const MIN_FILTER_Q: f32 = 0.01;
+
+// The cutoff and q of a low- or high-pass.  params only range-checks values
+// that came from a config or chain, so the command line's are checked again
+// here.
+fn cutoff_and_q(
+    params: &mut Params,
+    args: &Args,
+    sample_rate: SampleRate,
+) -> Result<(f32, f32), String> {
+    let nyquist = sample_rate.0 as f32 / 2.0;
+    let cutoff = params.number("cutoff", args.cutoff, 0.0..nyquist)?;
+    let q = params.number("q", args.q, MIN_FILTER_Q..)?;
+    if cutoff <= 0.0 || cutoff >= nyquist {
+        return Err(format!(
+            "The cutoff must satisfy 0 < {} < {}",
+            cutoff, nyquist
+        ));
+    }
+    if q < MIN_FILTER_Q {
+        return Err(format!("q must be at least {}, not {}", MIN_FILTER_Q, q));
+    }
+    Ok((cutoff, q))
+}
+
+// Wraps a nonlinear effect in an Oversampler, if --oversample or its
+// oversample parameter asks for one.
+fn oversampled<F: Filter + 'static>(
+    filter: F,
+    params: &mut Params,
+    args: &Args,
+    sample_rate: SampleRate,
+) -> Result<Box<dyn Filter>, String> {
+    Ok(match oversample_factor(params, args)? {
+        1 => Box::new(filter),
+        factor => Box::new(Oversampler::new(filter, factor, sample_rate)),
+    })
+}
+
+// How many times faster than the stream a nonlinear effect should run.
+fn oversample_factor(params: &mut Params, args: &Args) -> Result<usize, String> {
+    match params.integer("oversample", args.oversample, 1..)? {
+        factor @ (1 | 2 | 4) => Ok(factor),
+        factor => Err(format!("oversample must be 1, 2 or 4, not {}", factor)),
+    }
+}
+
+// Reads a whole WAV file into memory, mixed down to mono, for effects that
+// work from a recording: the convolve impulse response and the compressor's
+// sidechain key.
+fn load_mono_wav(path: &Path, sample_rate: SampleRate) -> Result<Vec<f32>, String> {
+    let error = |err: hound::Error| format!("Couldn't read {}: {}", path.display(), err);
+    let reader = open_wav(path)?;
+    let spec = reader.spec();
+    if SampleRate(spec.sample_rate) != sample_rate {
+        eprintln!(
+            "Warning: {} is {} Hz but the effect runs at {} Hz, so it will play back {}",
+            path.display(),
+            spec.sample_rate,
+            sample_rate.0,
+            if spec.sample_rate > sample_rate.0 {
+                "slower and lower"
+            } else {
+                "faster and higher"
+            }
+        );
+    }
+
+    let samples = wav_samples(reader, false)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(error)?;
+    let mono: Vec<f32> = samples
+        .chunks(spec.channels as usize)
+        .map(downmix)
+        .collect();
+    if mono.is_empty() {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(mono)
+}
+
+// A problem with one stage of a chain.  location says where the stage came
+// from, e.g. "Config line 4", so the message can point at it.
+#[derive(Debug)]
+struct ConfigError {
+    location: String,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// The raw layout of a chain config file: an array of [[effect]] tables, each
+// naming its effect with a type key.  See examples/chain.toml.
+#[derive(Deserialize)]
+struct ChainFile {
+    effect: Vec<toml::Spanned<toml::Table>>,
+}
+
+// One stage of a chain, with its parameters still unvalidated.
+#[derive(Clone, Debug)]
+struct EffectConfig {
+    location: String,
+    effect: Effect,
+    params: HashMap<String, String>,
+}
+
+fn parse_chain(text: &str) -> Result<ChainConfig, String> {
+    ChainConfig::parse_inline(text, "--chain").map_err(|err| err.to_string())
+}
+
+// An ordered list of effects loaded from a TOML file or given with --chain.
+#[derive(Clone, Debug)]
+struct ChainConfig {
+    effects: Vec<EffectConfig>,
+}
+
+impl ChainConfig {
+    fn load(path: &Path) -> Result<ChainConfig, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(ChainConfig::parse(&text)?)
+    }
+
+    fn parse(text: &str) -> Result<ChainConfig, ConfigError> {
+        let line_of = |offset: usize| text[..offset].matches('\n').count() + 1;
+
+        let file: ChainFile = toml::from_str(text).map_err(|err| ConfigError {
+            location: format!(
+                "Config line {}",
+                err.span().map_or(1, |span| line_of(span.start))
+            ),
+            message: err.message().to_string(),
+        })?;
+
+        let mut effects = Vec::new();
+        for table in file.effect {
+            let location = format!("Config line {}", line_of(table.span().start));
+            let error = |message: String| ConfigError {
+                location: location.clone(),
+                message,
+            };
+
+            let mut params = HashMap::new();
+            let mut name = None;
+            for (key, value) in table.into_inner() {
+                let value = match value {
+                    toml::Value::String(s) => s,
+                    toml::Value::Integer(i) => i.to_string(),
+                    toml::Value::Float(f) => f.to_string(),
+                    toml::Value::Boolean(b) => b.to_string(),
+                    _ => {
+                        return Err(error(format!(
+                            "{} must be a number, a string or true/false",
+                            key
+                        )))
+                    }
+                };
+                if key == "type" {
+                    name = Some(value);
+                } else {
+                    params.insert(key, value);
+                }
+            }
+
+            let name = name.ok_or_else(|| error("Effect has no type".to_string()))?;
+            let effect = Effect::from_str(&name, true)
+                .map_err(|_| error(format!("Unknown effect \"{}\"", name)))?;
+            effects.push(EffectConfig {
+                location,
+                effect,
+                params,
+            });
+        }
+
+        Ok(ChainConfig { effects })
+    }
+
+    // Parses a --chain: effects separated by spaces, each written as
+    // name:param=value,param=value.  The parameters are optional, so a
+    // stage can be just a name.  flag is the option the chain came from, for
+    // error messages.
+    fn parse_inline(text: &str, flag: &str) -> Result<ChainConfig, ConfigError> {
+        let mut effects = Vec::new();
+        for token in text.split_whitespace() {
+            let location = format!("{} stage \"{}\"", flag, token);
+            let error = |message: String| ConfigError {
+                location: location.clone(),
+                message,
+            };
+
+            let (name, param_list) = token.split_once(':').unwrap_or((token, ""));
+            let effect = Effect::from_str(name, true)
+                .map_err(|_| error(format!("Unknown effect \"{}\"", name)))?;
+
+            let mut params = HashMap::new();
+            for param in param_list.split(',').filter(|param| !param.is_empty()) {
+                let (key, value) = param
+                    .split_once('=')
+                    .ok_or_else(|| error(format!("\"{}\" should look like name=value", param)))?;
+                if params.insert(key.to_string(), value.to_string()).is_some() {
+                    return Err(error(format!("{} is given more than once", key)));
+                }
+            }
+
+            effects.push(EffectConfig {
+                location,
+                effect,
+                params,
+            });
+        }
+
+        if effects.is_empty() {
+            return Err(ConfigError {
+                location: flag.to_string(),
+                message: "No effects given".to_string(),
+            });
+        }
+        Ok(ChainConfig { effects })
+    }
+
+    // Builds the chain, along with a description of each stage for
+    // --show-config.
+    fn build(
+        &self,
+        args: &Args,
+        sample_rate: SampleRate,
+    ) -> Result<(FilterChain, Vec<StageInfo>), ConfigError> {
+        let mut chain = FilterChain::new();
+        let mut stages = Vec::new();
+        for config in &self.effects {
+            let error = |message| ConfigError {
+                location: config.location.clone(),
+                message,
+            };
+
+            let mut params = Params::new(config.params.clone());
+            chain.push(
+                build_effect(config.effect, &mut params, args, None, sample_rate).map_err(error)?,
+            );
+            stages.push(StageInfo::new(
+                &value_name(&config.effect),
+                std::mem::take(&mut params.resolved),
+            ));
+            params.finish().map_err(error)?;
+        }
+
+        Ok((chain, stages))
+    }
+}
+
+fn parse_parallel(text: &str) -> Result<ParallelConfig, String> {
+    ParallelConfig::parse(text).map_err(|err| err.to_string())
+}
+
+// The branches of a --parallel, each with its level in dB.  A dry branch is
+// a chain with no stages.
+#[derive(Clone, Debug)]
+struct ParallelConfig {
+    branches: Vec<(f32, ChainConfig)>,
+}
+
+impl ParallelConfig {
+    fn parse(text: &str) -> Result<ParallelConfig, ConfigError> {
+        let mut branches = Vec::new();
+        for (index, branch) in text.split('|').enumerate() {
+            let error = |message: String| ConfigError {
+                location: format!("--parallel branch {}", index),
+                message,
+            };
+
+            let mut stages = branch.trim();
+            let mut level_db = 0.0;
+            if let Some(rest) = stages.strip_prefix("level=") {
+                let (level, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                level_db = level.parse().map_err(|_| {
+                    error(format!("level must be a number of dB, not \"{}\"", level))
+                })?;
+                stages = rest.trim();
+            }
+
+            let chain = match stages {
+                "" => return Err(error("No effects given".to_string())),
+                "dry" => ChainConfig {
+                    effects: Vec::new(),
+                },
+                stages => ChainConfig::parse_inline(stages, "--parallel")?,
+            };
+            branches.push((level_db, chain));
+        }
+        Ok(ParallelConfig { branches })
+    }
+
+    fn build(
+        &self,
+        args: &Args,
+        sample_rate: SampleRate,
+    ) -> Result<(ParallelChain, Vec<BranchInfo>), ConfigError> {
+        let mut parallel = ParallelChain::new();
+        let mut branches = Vec::new();
+        for (level_db, chain) in &self.branches {
+            let (chain, stages) = chain.build(args, sample_rate)?;
+            parallel.push(Box::new(chain), db_to_linear(*level_db));
+            branches.push(BranchInfo {
+                level_db: *level_db,
+                stages,
+            });
+        }
+        Ok((parallel, branches))
+    }
+}
+
+// The chain from --config or --chain, if either was given.
+fn chain_config(args: &Args) -> Result<Option<ChainConfig>, Box<dyn Error>> {
+    match (&args.config, &args.chain) {
+        (Some(path), _) => Ok(Some(ChainConfig::load(path)?)),
+        (None, chain) => Ok(chain.clone()),
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Preset {
+    Telephone,
+    Radio,
+    Cathedral,
+    FuzzLead,
+}
+
+impl Preset {
+    fn build(self, sample_rate: SampleRate) -> FilterChain {
+        match self {
+            Preset::Telephone => telephone_preset(sample_rate),
+            Preset::Radio => radio_preset(sample_rate),
+            Preset::Cathedral => cathedral_preset(sample_rate),
+            Preset::FuzzLead => fuzz_lead_preset(sample_rate),
+        }
+    }
+}
+
+// Squeezes everything into the 300Hz - 3.4kHz voice band of a phone line,
+// with a little grit.
+fn telephone_preset(sample_rate: SampleRate) -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(HighPassFilter::new(300.0, 0.707, sample_rate)));
+    chain.push(Box::new(LowPassFilter::new(3400.0, 0.707, sample_rate)));
+    chain.push(Box::new(DistortFilter::new(2.0, 0.9, DistortMode::Soft)));
+    chain
+}
+
+// AM radio: a wider band than a phone, squashed flat by a compressor.
+fn radio_preset(sample_rate: SampleRate) -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(HighPassFilter::new(150.0, 0.707, sample_rate)));
+    chain.push(Box::new(LowPassFilter::new(5000.0, 0.707, sample_rate)));
+    chain.push(Box::new(Compressor::new(
+        -24.0,
+        6.0,
+        5.0,
+        100.0,
+        6.0,
+        sample_rate,
+    )));
+    chain.push(Box::new(DistortFilter::new(1.2, 0.95, DistortMode::Soft)));
+    chain
+}
+
+// A huge, dark room.
+fn cathedral_preset(sample_rate: SampleRate) -> FilterChain {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(ReverbFilter::new(0.95, 0.3, 0.5, sample_rate)));
+    chain.push(Box::new(LowPassFilter::new(8000.0, 0.707, sample_rate)));
+    chain
+}
+
+// Heavy fuzz, with the fizz filtered off and a short slapback echo.
+fn fuzz_lead_preset(sample_rate: SampleRate) -> FilterChain {
+    let SampleRate(sr) = sample_rate;
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(HighPassFilter::new(80.0, 0.707, sample_rate)));
+    chain.push(Box::new(DistortFilter::new(30.0, 0.6, DistortMode::Hard)));
+    chain.push(Box::new(LowPassFilter::new(5000.0, 0.707, sample_rate)));
+    chain.push(Box::new(WetDry::new(
+        DelayFilter::new(sr as usize * 3 / 10, 0.35, 0.2).expect("Decay is in range"),
+        0.3,
+    )));
+    chain
+}
+
+// One --eq band, as given on the command line.
+#[derive(Clone, Copy, Debug)]
+struct EqBand {
+    freq_hz: f32,
+    q: f32,
+    gain_db: f32,
+}
+
+// Parses an EQ band written as freq:q:gain, e.g. 1000:1.4:-6.
+fn parse_eq_band(text: &str) -> Result<EqBand, String> {
+    let fields = text
+        .split(':')
+        .map(|field| field.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("{} in EQ band {:?}", err, text))?;
+    match fields[..] {
+        [freq_hz, q, gain_db] if freq_hz > 0.0 && q > 0.0 => Ok(EqBand {
+            freq_hz,
+            q,
+            gain_db,
+        }),
+        [_, _, _] => Err(format!(
+            "EQ band {:?} needs a positive frequency and Q",
+            text
+        )),
+        _ => Err(format!("EQ band {:?} should look like freq:q:gain", text)),
+    }
+}
+
+// One --channel-delay, as given on the command line.
+#[derive(Clone, Copy, Debug)]
+struct ChannelOffset {
+    channel: usize,
+    frames: usize,
+}
+
+// Parses a channel delay written as channel:frames, e.g. 1:3.
+fn parse_channel_delay(text: &str) -> Result<ChannelOffset, String> {
+    let fields = text
+        .split(':')
+        .map(|field| field.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("{} in channel delay {:?}", err, text))?;
+    match fields[..] {
+        [channel, frames] => Ok(ChannelOffset { channel, frames }),
+        _ => Err(format!(
+            "Channel delay {:?} should look like channel:frames",
+            text
+        )),
+    }
+}
+
+// Each channel's --channel-delay in frames, in channel order.
+fn channel_delays(args: &Args, channels: usize) -> Result<Vec<usize>, String> {
+    let mut delays = vec![None; channels];
+    for offset in &args.channel_delay {
+        let delay = delays.get_mut(offset.channel).ok_or_else(|| {
+            format!(
+                "--channel-delay {} is past the last of the {} channels",
+                offset.channel, channels
+            )
+        })?;
+        if delay.replace(offset.frames).is_some() {
+            return Err(format!(
+                "--channel-delay gives channel {} more than once",
+                offset.channel
+            ));
+        }
+    }
+    Ok(delays.into_iter().map(Option::unwrap_or_default).collect())
+}
+
+// Builds everything the output callback runs on each frame: the channel
+// delays, the input gain, the selected effect, any EQ bands, the output
+// gain, the limiter, the polarity flip, and then the channel swap.  With
+// --passthrough only the gain and limiter stages are left.  Each stage comes
+// back described as well, for --show-config.
+fn build_frame_filter(
+    args: &Args,
+    chain_config: Option<&ChainConfig>,
+    controls: Option<&mut LiveControls>,
+    channels: usize,
+    sample_rate: SampleRate,
+) -> Result<(FrameChain, Vec<StageInfo>), Box<dyn Error>> {
+    let gain_stage = |gain_db| {
+        let gains = (0..channels).map(|_| Gain::new(gain_db)).collect();
+        Box::new(PerChannel::new(gains))
+    };
+
+    let mut frame_chain = FrameChain::new();
+    let mut stages = Vec::new();
+    if !args.channel_delay.is_empty() {
+        let delays = channel_delays(args, channels)?;
+        frame_chain.push(Box::new(ChannelDelay::new(&delays)));
+        stages.push(StageInfo::new(
+            "channel_delay",
+            BTreeMap::from([("frames", delays.into())]),
+        ));
+    }
+    if args.input_gain != 0.0 {
+        frame_chain.push(gain_stage(args.input_gain));
+        stages.push(StageInfo::new(
+            "input_gain",
+            BTreeMap::from([("gain_db", args.input_gain.into())]),
+        ));
+    }
+    if args.passthrough {
+        eprintln!("Passthrough: the effect and EQ are bypassed");
+    } else {
+        let (effect, effect_stages) =
+            build_effect_filter(args, chain_config, controls, channels, sample_rate)?;
+        frame_chain.push(effect);
+        stages.extend(effect_stages);
+    }
+
+    if !args.eq.is_empty() && !args.passthrough {
+        let chains = (0..channels)
+            .map(|_| {
+                let mut chain = FilterChain::new();
+                for band in &args.eq {
+                    chain.push(Box::new(PeakingEq::new(
+                        band.freq_hz,
+                        band.q,
+                        band.gain_db,
+                        sample_rate,
+                    )));
+                }
+                chain
+            })
+            .collect();
+        frame_chain.push(Box::new(PerChannel::new(chains)));
+        stages.extend(args.eq.iter().map(|band| {
+            StageInfo::new(
+                "eq",
+                BTreeMap::from([
+                    ("freq_hz", band.freq_hz.into()),
+                    ("q", band.q.into()),
+                    ("gain_db", band.gain_db.into()),
+                ]),
+            )
+        }));
+    }
+
+    if args.output_gain != 0.0 {
+        frame_chain.push(gain_stage(args.output_gain));
+        stages.push(StageInfo::new(
+            "output_gain",
+            BTreeMap::from([("gain_db", args.output_gain.into())]),
+        ));
+    }
+
+    if args.limit {
+        let limiters = (0..channels)
+            .map(|_| Limiter::new(args.ceiling, sample_rate))
+            .collect();
+        frame_chain.push(Box::new(PerChannel::new(limiters)));
+        stages.push(StageInfo::new(
+            "limit",
+            BTreeMap::from([("ceiling", args.ceiling.into())]),
+        ));
+    }
+
+    if args.invert_phase {
+        let inverters = (0..channels).map(|_| InvertPhase).collect();
+        frame_chain.push(Box::new(PerChannel::new(inverters)));
+        stages.push(StageInfo::new("invert_phase", BTreeMap::new()));
+    }
+
+    if args.swap_channels {
+        frame_chain.push(Box::new(SwapChannels));
+        stages.push(StageInfo::new("swap_channels", BTreeMap::new()));
+    }
+
+    Ok((frame_chain, stages))
+}
+
+// An effect for whole frames, and a description of each of its stages.
+type DescribedEffect = (Box<dyn FrameFilter>, Vec<StageInfo>);
+
+// Builds the selected effect for frames of the given width, along with a
+// description of each of its stages.  Per-sample effects get an independent
+// chain on each channel.  The gain and decay keyboard controls only reach an
+// effect picked with --effect, not presets or config files, but every stage
+// can be bypassed.
+fn build_effect_filter(
+    args: &Args,
+    chain_config: Option<&ChainConfig>,
+    controls: Option<&mut LiveControls>,
+    channels: usize,
+    sample_rate: SampleRate,
+) -> Result<DescribedEffect, Box<dyn Error>> {
+    if let Some(key) = sidechain(args, channels, sample_rate)? {
+        if chain_config.is_some()
+            || args.parallel.is_some()
+            || !matches!(args.effect, Some(Effect::Compress))
+        {
+            return Err("A sidechain only works with --effect compress".into());
+        }
+        let mut compressor = Compressor::new(
+            args.threshold_db,
+            args.ratio,
+            args.attack_ms,
+            args.release_ms,
+            args.makeup_gain_db,
+            sample_rate,
+        );
+        compressor.set_knee(args.comp_knee);
+        let mut params = BTreeMap::from([
+            ("threshold_db", args.threshold_db.into()),
+            ("ratio", args.ratio.into()),
+            ("attack_ms", args.attack_ms.into()),
+            ("release_ms", args.release_ms.into()),
+            ("makeup_gain_db", args.makeup_gain_db.into()),
+            ("knee_db", args.comp_knee.into()),
+        ]);
+        match &key {
+            Sidechain::Channel(channel) => params.insert("sidechain_channel", (*channel).into()),
+            Sidechain::Samples(_) => params.insert(
+                "sidechain_file",
+                args.sidechain_file
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .into(),
+            ),
+        };
+        return Ok((
+            Box::new(SidechainCompressor::new(compressor, key)),
+            vec![StageInfo::new("compress", params)],
+        ));
+    }
+
+    if let Some(chain_config) = chain_config {
+        let (mut chains, mut stages) =
+            build_per_channel(channels, || chain_config.build(args, sample_rate))?;
+        link_stages(&mut chains, args, controls, sample_rate)?;
+        note_enabled(&mut stages, &chains);
+        return Ok((Box::new(PerChannel::new(chains)), stages));
+    }
+
+    // The whole of a --parallel is one stage, as far as --solo and --bypass
+    // are concerned.
+    if let Some(parallel) = &args.parallel {
+        let (mut chains, mut stages) = build_per_channel(channels, || {
+            let (parallel, branches) = parallel.build(args, sample_rate)?;
+            let mut chain = FilterChain::new();
+            chain.push(Box::new(parallel));
+            let mut stage = StageInfo::new("parallel", BTreeMap::new());
+            stage.branches = Some(branches);
+            Ok::<_, ConfigError>((chain, vec![stage]))
+        })?;
+        link_stages(&mut chains, args, controls, sample_rate)?;
+        note_enabled(&mut stages, &chains);
+        return Ok((Box::new(PerChannel::new(chains)), stages));
+    }
+
+    // A preset's stages are fixed, so only the mix can change.
+    if let (None, Some(preset)) = (args.effect, args.preset) {
+        let mut chains: Vec<_> = (0..channels).map(|_| preset.build(sample_rate)).collect();
+        link_stages(&mut chains, args, controls, sample_rate)?;
+        let chains = chains
+            .into_iter()
+            .map(|chain| WetDry::new(chain, args.mix))
+            .collect();
+        let params = BTreeMap::from([
+            ("name", value_name(&preset).into()),
+            ("mix", args.mix.into()),
+        ]);
+        return Ok((
+            Box::new(PerChannel::new(chains)),
+            vec![StageInfo::new("preset", params)],
+        ));
+    }
+
+    let effect = args.effect.unwrap_or(Effect::Distort);
+    let frame_effect = |filter: Box<dyn FrameFilter>, params| {
+        Ok((filter, vec![StageInfo::new(&value_name(&effect), params)]))
+    };
+    match effect {
+        Effect::Width => {
+            let width = args.width.max(0.0);
+            frame_effect(
+                Box::new(StereoWidth::new(width)),
+                BTreeMap::from([("width", width.into())]),
+            )
+        }
+        Effect::Pingpong => {
+            if args.delay_frames == 0 {
+                return Err("--delay-frames must be greater than zero".into());
+            }
+            let decay = args.decay.unwrap_or(0.7);
+            frame_effect(
+                Box::new(
+                    PingPongDelay::new(args.delay_frames, decay).map_err(|err| err.to_string())?,
+                ),
+                BTreeMap::from([
+                    ("frames", args.delay_frames.into()),
+                    ("decay", decay.into()),
+                ]),
+            )
+        }
+        Effect::Panner => {
+            let rate = args.rate.unwrap_or(0.5);
+            let panner = AutoPanner::new(rate, args.depth, sample_rate);
+            let params = BTreeMap::from([("rate", rate.into()), ("depth", panner.depth().into())]);
+            frame_effect(Box::new(panner), params)
+        }
+        effect => {
+            let (mut chains, mut stages) = build_per_channel(channels, || {
+                let mut params = Params::default();
+                let mut chain = FilterChain::new();
+                chain.push(build_effect(
+                    effect,
+                    &mut params,
+                    args,
+                    controls.as_deref(),
+                    sample_rate,
+                )?);
+                let stage = StageInfo::new(&value_name(&effect), params.resolved);
+                Ok::<_, String>((chain, vec![stage]))
+            })?;
+            link_stages(&mut chains, args, controls, sample_rate)?;
+            note_enabled(&mut stages, &chains);
+            Ok((Box::new(PerChannel::new(chains)), stages))
+        }
+    }
+}
+
+// Builds the same chain once for each channel.  Every channel gets the same
+// stages, so only one set of descriptions is kept.
+fn build_per_channel<E>(
+    channels: usize,
+    mut build: impl FnMut() -> Result<(FilterChain, Vec<StageInfo>), E>,
+) -> Result<(Vec<FilterChain>, Vec<StageInfo>), E> {
+    let mut chains = Vec::with_capacity(channels);
+    let mut stages = Vec::new();
+    for _ in 0..channels {
+        let (chain, chain_stages) = build()?;
+        chains.push(chain);
+        stages = chain_stages;
+    }
+    Ok((chains, stages))
+}
+
+// Records whether --solo and --bypass left each stage of a linked chain
+// running.
+fn note_enabled(stages: &mut [StageInfo], chains: &[FilterChain]) {
+    if let Some(chain) = chains.first() {
+        for (index, stage) in stages.iter_mut().enumerate() {
+            stage.enabled = Some(chain.is_running(index));
+        }
+    }
+}
+
+// The key for --sidechain-channel or --sidechain-file, if either was given.
+fn sidechain(
+    args: &Args,
+    channels: usize,
+    sample_rate: SampleRate,
+) -> Result<Option<Sidechain>, String> {
+    if let Some(channel) = args.sidechain_channel {
+        if channel >= channels {
+            return Err(format!(
+                "--sidechain-channel {} is past the last of the {} channels",
+                channel, channels
+            ));
+        }
+        return Ok(Some(Sidechain::Channel(channel)));
+    }
+    match &args.sidechain_file {
+        Some(path) => Ok(Some(Sidechain::Samples(load_mono_wav(path, sample_rate)?))),
+        None => Ok(None),
+    }
+}
+
+// One stage of what build_frame_filter built, for --show-config: its type
+// and then each parameter with the value it ended up with.
+#[derive(Serialize)]
+struct StageInfo {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(flatten)]
+    params: BTreeMap<&'static str, ParamValue>,
+
+    // Whether --solo and --bypass leave the stage running, for the stages
+    // they apply to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+
+    // The branches of a --parallel, each a chain of its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branches: Option<Vec<BranchInfo>>,
+}
+
+impl StageInfo {
+    fn new(kind: &str, params: BTreeMap<&'static str, ParamValue>) -> StageInfo {
+        StageInfo {
+            kind: kind.to_string(),
+            params,
+            enabled: None,
+            branches: None,
+        }
+    }
+}
+
+// A value a stage was built with.  Numbers are kept as f32 so they print the
+// way they were given.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ParamValue {
+    Number(f32),
+    Integer(usize),
+    Flag(bool),
+    Text(Option<String>),
+    Frames(Vec<usize>),
+}
+
+impl From<f32> for ParamValue {
+    fn from(value: f32) -> ParamValue {
+        ParamValue::Number(value)
+    }
+}
+
+impl From<usize> for ParamValue {
+    fn from(value: usize) -> ParamValue {
+        ParamValue::Integer(value)
+    }
+}
+
+impl From<bool> for ParamValue {
+    fn from(value: bool) -> ParamValue {
+        ParamValue::Flag(value)
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> ParamValue {
+        ParamValue::Text(Some(value))
+    }
+}
+
+impl From<Option<String>> for ParamValue {
+    fn from(value: Option<String>) -> ParamValue {
+        ParamValue::Text(value)
+    }
+}
+
+impl From<Vec<usize>> for ParamValue {
+    fn from(value: Vec<usize>) -> ParamValue {
+        ParamValue::Frames(value)
+    }
+}
+
+#[derive(Serialize)]
+struct BranchInfo {
+    level_db: f32,
+    stages: Vec<StageInfo>,
+}
+
+// Everything --show-config prints.  Keys only ever get added, so scripts can
+// rely on the ones already there.
+#[derive(Serialize)]
+struct ShownConfig {
+    input: InputInfo,
+    output: OutputInfo,
+    buffer_frames: usize,
+    resample: Option<String>,
+    stages: Vec<StageInfo>,
+}
+
+#[derive(Serialize)]
+struct InputInfo {
+    source: &'static str,
+    name: Option<String>,
+    #[serde(flatten)]
+    config: StreamConfigInfo,
+}
+
+#[derive(Serialize)]
+struct OutputInfo {
+    name: String,
+    #[serde(flatten)]
+    config: StreamConfigInfo,
+}
+
+// Prints what a run would use, once the devices have been negotiated with,
+// as a single JSON object.  stages are the ones build_frame_filter built.
+fn show_config(
+    args: &Args,
+    stages: Vec<StageInfo>,
+    source: &Source,
+    output_device: &Device,
+    configs: &StreamConfigs,
+    buffer_frames: usize,
+) -> Result<(), Box<dyn Error>> {
+    let (source_kind, name) = match source {
+        Source::Device(device) => ("device", Some(device.name()?)),
+        Source::File { path, .. } => ("file", Some(path.display().to_string())),
+        Source::Generator(signal) => ("generator", Some(value_name(signal))),
+        Source::Stdin => ("stdin", None),
+    };
+    let stream_info = |config: &StreamConfig, format| {
+        StreamConfigInfo::new(config.sample_rate, config.channels, format)
+    };
+    let shown = ShownConfig {
+        input: InputInfo {
+            source: source_kind,
+            name,
+            config: stream_info(&configs.input, configs.input_format),
+        },
+        output: OutputInfo {
+            name: output_device.name()?,
+            config: stream_info(&configs.output, configs.output_format),
+        },
+        buffer_frames,
+        resample: (configs.input.sample_rate != configs.output.sample_rate)
+            .then(|| value_name(&args.resample)),
+        stages,
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, &shown)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+// How long a stage takes to fade in or out when its bypass is toggled.
+const STAGE_FADE_MS: f32 = 10.0;
+
+// Applies --solo and --bypass to a chain built once per channel, and ties
+// each stage's bypass switch together across the channels so the keyboard
+// can flip them all at once.
+fn link_stages(
+    chains: &mut [FilterChain],
+    args: &Args,
+    controls: Option<&mut LiveControls>,
+    sample_rate: SampleRate,
+) -> Result<(), String> {
+    let Some(first) = chains.first() else {
+        return Ok(());
+    };
+    let stages = first.len();
+    let switches = first.switches();
+
+    let past_end = |flag: &str, index: usize| {
+        format!(
+            "{} {} is past the end of a {} stage chain",
+            flag, index, stages
+        )
+    };
+    if let Some(index) = args.solo.filter(|index| *index >= stages) {
+        return Err(past_end("--solo", index));
+    }
+    for &index in &args.bypass {
+        if index >= stages {
+            return Err(past_end("--bypass", index));
+        }
+        switches[index].set(false);
+    }
+
+    let timers: Vec<StageTimer> = if args.profile {
+        (0..stages).map(|_| StageTimer::new()).collect()
+    } else {
+        Vec::new()
+    };
+
+    for chain in chains.iter_mut() {
+        chain.share_switches(&switches);
+        chain.set_fade(STAGE_FADE_MS, sample_rate);
+        chain.solo(args.solo);
+        chain.set_timers(&timers);
+    }
+    if let Some(controls) = controls {
+        controls.stages = switches;
+        controls.timers = timers;
+    }
+    Ok(())
+}
+
+// Effect parameters that can be changed from the keyboard while running.
+struct LiveControls {
+    gain: SharedParam,
+    decay: SharedParam,
+
+    // One switch per stage of the effect chain, filled in once it's built.
+    stages: Vec<SharedFlag>,
+
+    // With --profile, a timer for each of those stages.
+    timers: Vec<StageTimer>,
+}
+
+impl LiveControls {
+    fn new(args: &Args) -> LiveControls {
+        LiveControls {
+            gain: SharedParam::new(args.gain),
+            decay: SharedParam::new(args.decay.unwrap_or(0.9)),
+            stages: Vec::new(),
+            timers: Vec::new(),
+        }
+    }
+}
+
+// Each + or - multiplies or divides the gain by this much.
+const GAIN_STEP: f32 = 1.25;
+
+// Each [ or ] moves the decay by this much, up to MAX_LIVE_DECAY.
+const DECAY_STEP: f32 = 0.05;
+const MAX_LIVE_DECAY: f32 = 0.95;
+
+// The gain a MIDI knob turned all the way up gives.
+#[cfg(feature = "midi")]
+const MIDI_MAX_GAIN: f32 = 50.0;
+
+// Reads keys from stdin until the user quits.  stdin is line buffered, so
+// keys take effect once Enter is pressed, and Enter on its own quits.  If
+// stdin is closed, as when running headless, this leaves quitting to Ctrl-C.
+fn keyboard_control(controls: LiveControls, shutdown: Arc<AtomicBool>) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(1..) => {}
+            _ => return,
+        }
+
+        let keys = line.trim();
+        if keys.is_empty() || keys.contains('q') {
+            shutdown.store(true, Ordering::Release);
+            return;
+        }
+
+        // b and a stage number toggles that stage's bypass.
+        if let Some(index) = keys.strip_prefix('b') {
+            match index
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| controls.stages.get(i))
+            {
+                Some(stage) => stage.set(!stage.get()),
+                None => eprintln!("No stage {} to bypass", index.trim()),
+            }
+            let states: Vec<&str> = controls
+                .stages
+                .iter()
+                .map(|stage| if stage.get() { "on" } else { "bypassed" })
+                .collect();
+            status!("Stages: {}", states.join(", "));
+            continue;
+        }
+
+        for key in keys.chars() {
+            match key {
+                '+' => controls.gain.set(controls.gain.get() * GAIN_STEP),
+                '-' => controls.gain.set(controls.gain.get() / GAIN_STEP),
+                ']' => controls
+                    .decay
+                    .set((controls.decay.get() + DECAY_STEP).min(MAX_LIVE_DECAY)),
+                '[' => controls
+                    .decay
+                    .set((controls.decay.get() - DECAY_STEP).max(0.0)),
+                _ => {}
+            }
+        }
+        status!(
+            "Gain {:.2}, decay {:.2}",
+            controls.gain.get(),
+            controls.decay.get()
+        );
+    }
+}
+
+// The WAV format for a recording at the given --record-bits: 16 or 24-bit
+// PCM, or 32-bit float.  Float is the default, as nothing is lost to
+// quantization.
+fn recording_spec(config: &StreamConfig, bits: u16) -> hound::WavSpec {
+    let SampleRate(sample_rate) = config.sample_rate;
+    hound::WavSpec {
+        channels: config.channels,
+        sample_rate,
+        bits_per_sample: bits,
+        sample_format: if bits == 32 {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    }
+}
+
+// Writes f32 samples to a WAV file in whatever format it was created with,
+// rounding to PCM, with dither if asked for, where the file needs it.
+struct WavEncoder {
+    writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+
+    // Full scale as a PCM value, or None for a float file.
+    full_scale: Option<f32>,
+    dither: Option<Dither>,
+}
+
+impl WavEncoder {
+    fn create(path: &Path, config: &StreamConfig, args: &Args) -> Result<WavEncoder, hound::Error> {
+        let spec = recording_spec(config, args.record_bits);
+        let full_scale = (spec.sample_format == hound::SampleFormat::Int)
+            .then(|| (1u32 << (spec.bits_per_sample - 1)) as f32);
+        Ok(WavEncoder {
+            writer: hound::WavWriter::create(path, spec)?,
+            full_scale,
+            dither: args.dither.then(|| Dither::new(dither_seed(args))),
+        })
+    }
+
+    fn write_sample(&mut self, sample: f32) -> Result<(), hound::Error> {
+        let Some(full_scale) = self.full_scale else {
+            return self.writer.write_sample(sample);
+        };
+        let scaled = sample * full_scale;
+        let level = match &mut self.dither {
+            Some(dither) => dither.quantize(scaled, 1.0),
+            None => scaled.round(),
+        };
+        self.writer
+            .write_sample(level.clamp(-full_scale, full_scale - 1.0) as i32)
+    }
+
+    fn finalize(self) -> Result<(), hound::Error> {
+        self.writer.finalize()
+    }
+}
+
+// Where a Recorder writes to.
+enum RecordSink {
+    Wav(WavEncoder),
+
+    // Raw little-endian f32 samples on stdout, for --stdout-raw.
+    Stdout(std::io::BufWriter<std::io::Stdout>),
+}
+
+impl RecordSink {
+    fn write_sample(&mut self, sample: f32) -> Result<(), hound::Error> {
+        match self {
+            RecordSink::Wav(writer) => writer.write_sample(sample),
+            RecordSink::Stdout(stdout) => Ok(stdout.write_all(&sample.to_le_bytes())?),
+        }
+    }
+
+    fn finish(self) -> Result<(), hound::Error> {
+        match self {
+            RecordSink::Wav(writer) => writer.finalize(),
+            RecordSink::Stdout(mut stdout) => Ok(stdout.flush()?),
+        }
+    }
+}
+
+// Writes samples to a WAV file, or to stdout, from a background thread.  The
+// audio callback pushes into the producer returned by start(), which never
+// blocks; if the writer falls behind, samples are dropped rather than
+// stalling playback.
+struct Recorder {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<Result<(), hound::Error>>,
+}
+
+impl Recorder {
+    fn start(
+        path: &Path,
+        config: &StreamConfig,
+        args: &Args,
+    ) -> Result<(Recorder, Producer<f32>), Box<dyn Error>> {
+        let encoder = WavEncoder::create(path, config, args)?;
+        Ok(Recorder::spawn(RecordSink::Wav(encoder), config))
+    }
+
+    fn start_stdout(config: &StreamConfig) -> (Recorder, Producer<f32>) {
+        let stdout = std::io::BufWriter::new(std::io::stdout());
+        Recorder::spawn(RecordSink::Stdout(stdout), config)
+    }
+
+    fn spawn(mut sink: RecordSink, config: &StreamConfig) -> (Recorder, Producer<f32>) {
+        let SampleRate(sample_rate) = config.sample_rate;
+
+        // A second's worth of slack for the writer thread.
+        let buffer = RingBuffer::new(sample_rate as usize * config.channels as usize);
+        let (producer, mut consumer) = buffer.split();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = done.clone();
+        let channels = config.channels as usize;
+
+        let thread = thread::spawn(move || {
+            let mut chunk = [0.0; 1024];
+            let mut written = 0;
+            loop {
+                // Check before draining so nothing pushed before finish() is lost.
+                let finished = thread_done.load(Ordering::Acquire);
+                let count = consumer.pop_slice(&mut chunk);
+                for sample in &chunk[..count] {
+                    sink.write_sample(*sample)?;
+                }
+                written += count;
+
+                if count == 0 {
+                    if finished {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+
+            // The stream may stop partway through a frame.  Pad it out with
+            // silence so the output holds a whole number of frames.
+            while written % channels != 0 {
+                sink.write_sample(0.0)?;
+                written += 1;
+            }
+
+            sink.finish()
+        });
+
+        (Recorder { done, thread }, producer)
+    }
+
+    // Drains anything still buffered and finalizes the WAV header, or
+    // flushes stdout.
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        self.done.store(true, Ordering::Release);
+        match self.thread.join() {
+            Ok(result) => Ok(result?),
+            Err(_) => Err("Recording thread panicked".into()),
+        }
+    }
+}
+
+// Tracks input levels for the --meter display.  The audio callback folds
+// each buffer into these atomics and a display thread periodically drains
+// them, so metering never takes a lock on the audio thread.
+struct LevelMeter {
+    channels: Vec<ChannelLevel>,
+}
+
+#[derive(Default)]
+struct ChannelLevel {
+    // Bit patterns of an f32 peak and an f64 sum of squares, since there are
+    // no atomic floats.
+    peak: AtomicU32,
+    sum_squares: AtomicU64,
+    samples: AtomicUsize,
+}
+
+impl LevelMeter {
+    fn new(channels: usize) -> LevelMeter {
+        LevelMeter {
+            channels: std::iter::repeat_with(ChannelLevel::default)
+                .take(channels)
+                .collect(),
+        }
+    }
+
+    // Accumulates an interleaved buffer.  Safe to call from the audio thread.
+    fn update(&self, data: &[f32]) {
+        let width = self.channels.len();
+        for (c, level) in self.channels.iter().enumerate() {
+            let mut peak = 0.0f32;
+            let mut sum_squares = 0.0f64;
+            let mut samples = 0;
+            for sample in data.iter().skip(c).step_by(width) {
+                peak = peak.max(sample.abs());
+                sum_squares += (*sample as f64) * (*sample as f64);
+                samples += 1;
+            }
+
+            let _ = level
+                .peak
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    (peak > f32::from_bits(bits)).then(|| peak.to_bits())
+                });
+            let _ = level
+                .sum_squares
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f64::from_bits(bits) + sum_squares).to_bits())
+                });
+            level.samples.fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+
+    // Returns the peak and RMS of each channel since the last call.
+    fn take(&self) -> Vec<(f32, f32)> {
+        self.channels
+            .iter()
+            .map(|level| {
+                let peak = f32::from_bits(level.peak.swap(0, Ordering::Relaxed));
+                let sum_squares = f64::from_bits(level.sum_squares.swap(0, Ordering::Relaxed));
+                let samples = level.samples.swap(0, Ordering::Relaxed);
+                let rms = if samples > 0 {
+                    (sum_squares / samples as f64).sqrt() as f32
+                } else {
+                    0.0
+                };
+                (peak, rms)
+            })
+            .collect()
+    }
+}
+
+const METER_WIDTH: usize = 30;
+const METER_FLOOR_DB: f32 = -60.0;
+
+// Draws one channel's meter: a bar for the RMS level, and a marker for the
+// held peak.
+fn format_meter(rms: f32, held_peak: f32) -> String {
+    let position = |level: f32| {
+        let db = linear_to_db(level).clamp(METER_FLOOR_DB, 0.0);
+        ((1.0 - db / METER_FLOOR_DB) * METER_WIDTH as f32) as usize
+    };
+    let bar = position(rms);
+    let peak = position(held_peak).min(METER_WIDTH - 1);
+
+    let mut meter: String = (0..METER_WIDTH)
+        .map(|i| if i < bar { '#' } else { '-' })
+        .collect();
+    if held_peak > 0.0 {
+        meter.replace_range(peak..peak + 1, "|");
+    }
+
+    format!("[{}] {:6.1} dB", meter, linear_to_db(held_peak))
+}
+
+// Redraws the level meters on stderr a few times a second until finished.
+struct MeterDisplay {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl MeterDisplay {
+    fn start(meter: Arc<LevelMeter>, peak_hold: Duration) -> MeterDisplay {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = done.clone();
+
+        let thread = thread::spawn(move || {
+            let mut held = vec![(0.0f32, Instant::now()); meter.channels.len()];
+            while !thread_done.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(200));
+
+                let line: Vec<String> = meter
+                    .take()
+                    .into_iter()
+                    .zip(held.iter_mut())
+                    .enumerate()
+                    .map(|(c, ((peak, rms), (held_peak, since)))| {
+                        if peak >= *held_peak || since.elapsed() >= peak_hold {
+                            *held_peak = peak;
+                            *since = Instant::now();
+                        }
+                        format!("{} {}", c, format_meter(rms, *held_peak))
+                    })
+                    .collect();
+                eprint!("\r{}", line.join("  "));
+            }
+            eprintln!();
+        });
+
+        MeterDisplay { done, thread }
+    }
+
+    fn finish(self) {
+        self.done.store(true, Ordering::Release);
+        let _ = self.thread.join();
+    }
+}
+
+// Callback timing and buffer levels for --verbose.  The callbacks update
+// these and DiagnosticsDisplay reads and resets them.
+#[derive(Default)]
+struct Diagnostics {
+    // Input samples waiting in the ringbuffer as of the last output callback.
+    buffered: AtomicUsize,
+
+    // The longest gap between callbacks since the last report, in
+    // microseconds.  Zero if there were none.
+    input_gap_us: AtomicU64,
+    output_gap_us: AtomicU64,
+}
+
+// Notes the time since last in gap_us, if it's the longest so far.
+fn record_callback_gap(last: &mut Option<Instant>, gap_us: &AtomicU64) {
+    let now = Instant::now();
+    if let Some(last) = *last {
+        let gap = now.duration_since(last).as_micros() as u64;
+        gap_us.fetch_max(gap, Ordering::Relaxed);
+    }
+    *last = Some(now);
+}
+
+// Logs a line of Diagnostics and XrunStats to stderr every second.
+struct DiagnosticsDisplay {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl DiagnosticsDisplay {
+    fn start(
+        diagnostics: Arc<Diagnostics>,
+        stats: Arc<XrunStats>,
+        capacity: usize,
+    ) -> DiagnosticsDisplay {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = done.clone();
+
+        let thread = thread::spawn(move || {
+            let gap_ms = |gap_us: &AtomicU64| match gap_us.swap(0, Ordering::Relaxed) {
+                0 => "-".to_string(),
+                gap => format!("{:.1}ms", gap as f64 / 1000.0),
+            };
+
+            while !thread_done.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_secs(1));
+
+                let buffered = diagnostics.buffered.load(Ordering::Relaxed);
+                eprintln!(
+                    "Buffer {:.0}% full, {} samples dropped, {} underruns, longest callback gap {} in, {} out",
+                    100.0 * buffered as f64 / capacity as f64,
+                    stats.dropped.load(Ordering::Relaxed),
+                    stats.underruns.load(Ordering::Relaxed),
+                    gap_ms(&diagnostics.input_gap_us),
+                    gap_ms(&diagnostics.output_gap_us),
+                );
+            }
+        });
+
+        DiagnosticsDisplay { done, thread }
+    }
+
+    fn finish(self) {
+        self.done.store(true, Ordering::Release);
+        let _ = self.thread.join();
+    }
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text.parse().map_err(|err| format!("{}", err))?;
+    Duration::try_from_secs_f64(seconds).map_err(|err| format!("{}", err))
+}
+
+fn parse_percentage(text: &str) -> Result<f32, String> {
+    let percentage: f32 = text.parse().map_err(|err| format!("{}", err))?;
+    if !(0.0..=100.0).contains(&percentage) {
+        return Err(format!("{} isn't between 0 and 100", percentage));
+    }
+    Ok(percentage)
+}
+
+fn parse_oversample(text: &str) -> Result<usize, String> {
+    match text.parse() {
+        Ok(factor @ (1 | 2 | 4)) => Ok(factor),
+        _ => Err(format!("{} isn't 1, 2 or 4", text)),
+    }
+}
+
+fn parse_record_bits(text: &str) -> Result<u16, String> {
+    match text.parse() {
+        Ok(bits @ (16 | 24 | 32)) => Ok(bits),
+        _ => Err(format!("{} isn't 16, 24 or 32", text)),
+    }
+}
+
+// The seed for --dither, from --seed if given.
+fn dither_seed(args: &Args) -> u32 {
+    args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |elapsed| elapsed.subsec_nanos())
+    })
+}
+
+fn parse_fft_size(text: &str) -> Result<usize, String> {
+    let size: usize = text.parse().map_err(|err| format!("{}", err))?;
+    if size < 16 || !size.is_power_of_two() {
+        return Err(format!("{} isn't a power of two of at least 16", size));
+    }
+    Ok(size)
+}
+
+// Spectrum bands shown, spaced logarithmically from SPECTRUM_LOW_HZ up to
+// the Nyquist frequency.
+const SPECTRUM_BANDS: usize = 48;
+const SPECTRUM_LOW_HZ: f32 = 30.0;
+const SPECTRUM_FLOOR_DB: f32 = -72.0;
+
+// Characters for each band, from silent to full scale.
+const SPECTRUM_LEVELS: &[u8] = b" .:-=+*#%@";
+
+// The Hann window, which tapers the ends of each block to zero so the FFT
+// doesn't see a jump where the block wraps around.
+fn hann_window(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / size as f32).cos())
+        .collect()
+}
+
+// Draws the loudest bin in each band as one character.  Magnitudes are
+// scaled so a full-scale sine reads 0 dB.
+fn format_spectrum(magnitudes: &[f32], sample_rate: u32) -> String {
+    let bin_hz = sample_rate as f32 / (2 * magnitudes.len()) as f32;
+    let ratio = (sample_rate as f32 / 2.0 / SPECTRUM_LOW_HZ).max(1.0);
+    let scale = 4.0 / (2 * magnitudes.len()) as f32;
+
+    (0..SPECTRUM_BANDS)
+        .map(|band| {
+            let low = SPECTRUM_LOW_HZ * ratio.powf(band as f32 / SPECTRUM_BANDS as f32);
+            let high = SPECTRUM_LOW_HZ * ratio.powf((band + 1) as f32 / SPECTRUM_BANDS as f32);
+            let first = ((low / bin_hz) as usize).min(magnitudes.len() - 1);
+            let last = ((high / bin_hz) as usize).clamp(first + 1, magnitudes.len());
+            let peak = magnitudes[first..last]
+                .iter()
+                .fold(0.0f32, |a, b| a.max(*b));
+
+            let db = linear_to_db(peak * scale).clamp(SPECTRUM_FLOOR_DB, 0.0);
+            let level = (1.0 - db / SPECTRUM_FLOOR_DB) * (SPECTRUM_LEVELS.len() - 1) as f32;
+            SPECTRUM_LEVELS[level.round() as usize] as char
+        })
+        .collect()
+}
+
+// Runs FFTs of the most recent input on its own thread and redraws the
+// spectrum on stderr a few times a second until finished.  The input
+// callback only has to push samples into the ring buffer it's started with.
+struct SpectrumDisplay {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl SpectrumDisplay {
+    fn start(mut samples: Consumer<f32>, fft_size: usize, sample_rate: u32) -> SpectrumDisplay {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = done.clone();
+
+        let thread = thread::spawn(move || {
+            let fft = FftPlanner::new().plan_fft_forward(fft_size);
+            let window = hann_window(fft_size);
+            let mut history: Vec<f32> = Vec::with_capacity(2 * fft_size);
+            let mut scratch = vec![0.0; samples.capacity()];
+            let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+
+            while !thread_done.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(200));
+
+                // Keep only the newest fft_size samples.
+                let count = samples.pop_slice(&mut scratch);
+                history.extend_from_slice(&scratch[..count]);
+                if history.len() > fft_size {
+                    history.drain(..history.len() - fft_size);
+                }
+                if history.len() < fft_size {
+                    continue;
+                }
+
+                for ((bin, sample), w) in buffer.iter_mut().zip(&history).zip(&window) {
+                    *bin = Complex::new(sample * w, 0.0);
+                }
+                fft.process(&mut buffer);
+
+                let magnitudes: Vec<f32> = buffer[..fft_size / 2]
+                    .iter()
+                    .map(|bin| bin.norm())
+                    .collect();
+                eprint!(
+                    "\r{} Hz [{}] {} Hz",
+                    SPECTRUM_LOW_HZ,
+                    format_spectrum(&magnitudes, sample_rate),
+                    sample_rate / 2
+                );
+            }
+            eprintln!();
+        });
+
+        SpectrumDisplay { done, thread }
+    }
+
+    fn finish(self) {
+        self.done.store(true, Ordering::Release);
+        let _ = self.thread.join();
+    }
+}
+
+// Counts buffer overruns and underruns between the input and output
+// callbacks, so a glitch costs a few samples instead of the whole session.
+#[derive(Default)]
+struct XrunStats {
+    // Input samples thrown away because the buffer was full.
+    dropped: AtomicUsize,
+
+    // Output callbacks that ran out of input and had to fill with silence.
+    underruns: AtomicUsize,
+}
+
+// What actually reached the output device, to tell whether it clipped.
+#[derive(Default)]
+struct ClipStats {
+    // Samples at or beyond full scale.
+    clipped: AtomicUsize,
+
+    // The loudest sample, as f32 bits.  Levels are never negative, so their
+    // bit patterns sort the same way the levels do and fetch_max works.
+    peak: AtomicU32,
+}
+
+impl ClipStats {
+    fn update(&self, data: &[f32]) {
+        let mut clipped = 0;
+        let mut peak = 0.0f32;
+        for sample in data {
+            let level = sample.abs();
+            if level >= 1.0 {
+                clipped += 1;
+            }
+            peak = peak.max(level);
+        }
+
+        if clipped > 0 {
+            self.clipped.fetch_add(clipped, Ordering::Relaxed);
+        }
+        self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// Where the output callback's time goes, for --profile.  The per-stage
+// timers live in the chains themselves.
+#[derive(Default)]
+struct Profile {
+    // The whole effect, including any stages that work on whole frames.
+    effect: StageTimer,
+
+    // Everything the callback does, effect and all.
+    callback: StageTimer,
+
+    // Frames the callback has produced, which sets the real-time budget.
+    frames: AtomicUsize,
+}
+
+impl Profile {
+    fn print(&self, stages: &[StageTimer], sample_rate: u32) {
+        let audio = self.frames.load(Ordering::Relaxed) as f64 / sample_rate as f64;
+        let share = |spent: Duration| 100.0 * spent.as_secs_f64() / audio.max(1e-9);
+        status!(
+            "CPU time over {:.1}s of audio, as a share of the real-time budget:",
+            audio
+        );
+        for (index, timer) in stages.iter().enumerate() {
+            status!(
+                "  Stage {}: {:.3}s ({:.2}%)",
+                index,
+                timer.total().as_secs_f64(),
+                share(timer.total())
+            );
+        }
+        status!(
+            "  Whole effect: {:.3}s ({:.2}%)",
+            self.effect.total().as_secs_f64(),
+            share(self.effect.total())
+        );
+        let callback = share(self.callback.total());
+        status!(
+            "  Whole callback: {:.3}s ({:.2}%, leaving {:.1}% headroom)",
+            self.callback.total().as_secs_f64(),
+            callback,
+            100.0 - callback
+        );
+    }
+}
+
+// The detector for --clip-detect-abort, if it was asked for.
+fn clip_detector(args: &Args, config: &StreamConfig) -> Option<ClipDetector> {
+    if !args.clip_detect_abort {
+        return None;
+    }
+    let SampleRate(sample_rate) = config.sample_rate;
+    let window = args.clip_abort_window.as_secs_f64() * sample_rate as f64 * config.channels as f64;
+    Some(ClipDetector::new(
+        window as usize,
+        args.clip_abort_threshold / 100.0,
+    ))
+}
+
+// Why --clip-detect-abort stopped the run.
+fn clip_abort_message(args: &Args) -> String {
+    format!(
+        "Stopped because more than {}% of the output clipped within {:.1}s.  Anything \
+         recorded up to that point has been saved",
+        args.clip_abort_threshold,
+        args.clip_abort_window.as_secs_f64()
+    )
+}
+
+// Replaces NaN and infinite samples with silence, so an unstable effect
+// can't blast the speakers.  Returns how many samples were replaced.
+fn sanitize(frame: &mut [f32]) -> usize {
+    let mut replaced = 0;
+    for sample in frame.iter_mut() {
+        if !sample.is_finite() {
+            *sample = 0.0;
+            replaced += 1;
+        }
+    }
+    replaced
+}
+
+// Copies one interleaved input frame into an output frame of a possibly
+// different width.  Output channels beyond the input's wrap around, so a mono
+// input is duplicated across every output channel; extra input channels are
+// dropped.
+fn remix_frame(input: &[f32], output: &mut [f32]) {
+    for (c, sample) in output.iter_mut().enumerate() {
+        *sample = input[c % input.len()];
+    }
+}
+
+// Averages the channels of a frame into a single sample, for --mono.
+fn downmix(frame: &[f32]) -> f32 {
+    frame.iter().sum::<f32>() / frame.len() as f32
+}
+
+// Where the input comes from.
+enum Source {
+    Device(Device),
+    File {
+        path: PathBuf,
+        reader: WavFileReader,
+    },
+    Generator(Signal),
+    Stdin,
+}
+
+type WavFileReader = hound::WavReader<std::io::BufReader<std::fs::File>>;
+
+// Opens a WAV file and checks that wav_samples knows how to decode it, so an
+// odd file is turned away up front with a message saying why rather than
+// failing partway through playback.  The file's sample rate isn't checked:
+// playback resamples to whatever the output device runs at.
+fn open_wav(path: &Path) -> Result<WavFileReader, String> {
+    let reader = hound::WavReader::open(path).map_err(|err| match err {
+        hound::Error::Unsupported => format!(
+            "{} uses a compressed encoding, which isn't supported.  Use PCM or float",
+            path.display()
+        ),
+        err => format!("Couldn't read {}: {}", path.display(), err),
+    })?;
+    let spec = reader.spec();
+    let supported = match spec.sample_format {
+        hound::SampleFormat::Int => matches!(spec.bits_per_sample, 8 | 16 | 24 | 32),
+        hound::SampleFormat::Float => spec.bits_per_sample == 32,
+    };
+    if !supported {
+        let format = match spec.sample_format {
+            hound::SampleFormat::Int => "PCM",
+            hound::SampleFormat::Float => "float",
+        };
+        return Err(format!(
+            "{} is {}-bit {}, which isn't supported.  Use 8, 16, 24 or 32-bit PCM, or 32-bit float",
+            path.display(),
+            spec.bits_per_sample,
+            format
+        ));
+    }
+    if spec.channels == 0 {
+        return Err(format!("{} has no channels", path.display()));
+    }
+    Ok(reader)
+}
+
+// Decodes every sample of a WAV file to f32 in the range -1.0..1.0.  With
+// looping set, the file seeks back to its first sample when it runs out, so
+// the last sample is followed directly by the first with nothing in between.
+fn wav_samples(
+    mut reader: WavFileReader,
+    looping: bool,
+) -> Box<dyn Iterator<Item = Result<f32, hound::Error>> + Send> {
+    let spec = reader.spec();
+    let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+
+    // An empty file would otherwise loop forever without producing anything.
+    let looping = looping && reader.len() > 0;
+    Box::new(std::iter::from_fn(move || loop {
+        let sample = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().next(),
+            hound::SampleFormat::Int => reader
+                .samples::<i32>()
+                .next()
+                .map(|s| s.map(|s| s as f32 * scale)),
+        };
+        match sample {
+            None if looping => {
+                if let Err(err) = reader.seek(0) {
+                    return Some(Err(err.into()));
+                }
+            }
+            sample => return sample,
+        }
+    }))
+}
+
+// Counts samples of a --loop file as they're played, to find where each pass
+// ends and the next begins.  The effect is reset there so nothing from the
+// end of one pass rings on into the start of the next.
+struct LoopPosition {
+    // Samples in one pass of the file, counting every channel.
+    length: usize,
+    played: usize,
+}
+
+impl LoopPosition {
+    // None unless the file loops.
+    fn new(reader: &WavFileReader, looping: bool) -> Option<LoopPosition> {
+        let length = reader.len() as usize;
+        (looping && length > 0).then_some(LoopPosition { length, played: 0 })
+    }
+
+    // Moves on by the next samples played.  If a new pass starts among them,
+    // returns how many samples in it starts.
+    fn advance(&mut self, samples: usize) -> Option<usize> {
+        let offset = (self.length - self.played % self.length) % self.length;
+        let start = self.played + offset;
+        self.played += samples;
+        (offset < samples && start > 0).then_some(offset)
+    }
+}
+
+// Reads interleaved little-endian f32 samples until EOF.  A partial sample
+// left over at the very end is dropped.
+fn raw_samples<R>(mut input: R) -> Box<dyn Iterator<Item = std::io::Result<f32>> + Send>
+where
+    R: std::io::Read + Send + 'static,
+{
+    Box::new(std::iter::from_fn(move || {
+        let mut bytes = [0; 4];
+        match input.read_exact(&mut bytes) {
+            Ok(()) => Some(Ok(f32::from_le_bytes(bytes))),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err)),
+        }
+    }))
+}
+
+// Errors that end file or pipe playback.
+type PlaybackError = Box<dyn Error + Send + Sync>;
+
+// Feeds a file or pipe into the input ringbuffer at the rate the output
+// stream drains it.  Once the samples run out, a full buffer of silence is
+// pushed so that by the time the thread exits everything has been played.
+// Setting shutdown stops playback early.
+fn play_samples<E>(
+    mut samples: Box<dyn Iterator<Item = Result<f32, E>> + Send>,
+    mut producer: Producer<f32>,
+    shutdown: Arc<AtomicBool>,
+) -> JoinHandle<Result<(), PlaybackError>>
+where
+    E: Into<PlaybackError> + 'static,
+{
+    thread::spawn(move || {
+        let mut pending = None;
+        loop {
+            if shutdown.load(Ordering::Acquire) {
+                return Ok(());
+            }
+
+            if pending.is_none() {
+                pending = match samples.next() {
+                    Some(sample) => Some(sample.map_err(Into::into)?),
+                    None => break,
+                };
+            }
+
+            if let Some(sample) = pending {
+                match producer.push(sample) {
+                    Ok(()) => pending = None,
+                    Err(_) => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+        }
+
+        let mut silence = producer.capacity();
+        while silence > 0 && !shutdown.load(Ordering::Acquire) {
+            match producer.push(0.0) {
+                Ok(()) => silence -= 1,
+                Err(_) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+
+        Ok(())
+    })
+}
+
+// Feeds a generated signal into the input ringbuffer at the rate the output
+// stream drains it, until shutdown is set.
+fn play_generator(
+    mut generator: SignalGenerator,
+    mut producer: Producer<f32>,
+    shutdown: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut pending = generator.next_sample();
+        while !shutdown.load(Ordering::Acquire) {
+            match producer.push(pending) {
+                Ok(()) => pending = generator.next_sample(),
+                Err(_) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    })
+}
+
+// With no output device to match, a --stdin-raw dry run assumes this rate
+// unless given --raw-rate.
+const DRY_RUN_RAW_RATE: u32 = 48000;
+
+// Runs --input-file or --stdin-raw through the effect as fast as possible
+// without touching any audio devices, then reports how it went.  Output goes
+// to --record if given, or to stdout as raw little-endian f32 samples
+// otherwise.  With --loop it runs until killed or its output is closed.
+fn dry_run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let (input, channels, sample_rate, mut loop_position) = match &args.input_file {
+        Some(path) => {
+            let reader = open_wav(path)?;
+            let spec = reader.spec();
+            let loop_position = LoopPosition::new(&reader, args.loop_input);
+            let input: Box<dyn Iterator<Item = Result<f32, PlaybackError>>> =
+                Box::new(wav_samples(reader, args.loop_input).map(|s| s.map_err(Into::into)));
+            (input, spec.channels, spec.sample_rate, loop_position)
+        }
+        None if args.stdin_raw => {
+            let input: Box<dyn Iterator<Item = Result<f32, PlaybackError>>> =
+                Box::new(raw_samples(std::io::stdin()).map(|s| s.map_err(Into::into)));
+            (
+                input,
+                args.raw_channels,
+                args.raw_rate.unwrap_or(DRY_RUN_RAW_RATE),
+                None,
+            )
+        }
+        None => return Err("--dry-run needs an --input-file or --stdin-raw".into()),
+    };
+    let config = StreamConfig {
+        channels,
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let chain_config = chain_config(args)?;
+    let channels = config.channels as usize;
+    let (mut frame_filter, _) = build_frame_filter(
+        args,
+        chain_config.as_ref(),
+        None,
+        channels,
+        config.sample_rate,
+    )?;
+
+    let mut wav_writer = match &args.record {
+        Some(path) => Some(WavEncoder::create(path, &config, args)?),
+        None => None,
+    };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+
+    let mut samples = 0usize;
+    let mut peak = 0.0f32;
+    let mut non_finite = 0usize;
+    let mut clip_detector = clip_detector(args, &config);
+    let mut frame = vec![0.0; channels];
+    let mut filled = 0;
+
+    let start = Instant::now();
+    for sample in input {
+        frame[filled] = sample.map_err(|err| err as Box<dyn Error>)?;
+        filled += 1;
+        if filled < channels {
+            continue;
+        }
+        filled = 0;
+
+        if args.mono {
+            let level = downmix(&frame);
+            frame.fill(level);
+        }
+        if let Some(position) = loop_position.as_mut() {
+            if position.advance(channels).is_some() {
+                frame_filter.reset();
+            }
+        }
+        frame_filter.process_frame(&mut frame);
+        if let Some(detector) = clip_detector.as_mut() {
+            if detector.update(&frame) {
+                break;
+            }
+        }
+
+        for sample in &frame {
+            if sample.is_finite() {
+                peak = peak.max(sample.abs());
+            } else {
+                non_finite += 1;
+            }
+
+            // Without --record, stdout stands in for the output device.
+            match wav_writer.as_mut() {
+                Some(writer) => writer.write_sample(*sample)?,
+                None if args.mute_input => stdout.write_all(&0.0f32.to_le_bytes())?,
+                None => stdout.write_all(&sample.to_le_bytes())?,
+            }
+        }
+        samples += channels;
+    }
+    let elapsed = start.elapsed();
+
+    if let Some(writer) = wav_writer {
+        writer.finalize()?;
+    }
+    stdout.flush()?;
+
+    let per_second = samples as f64 / elapsed.as_secs_f64().max(1e-9);
+    let real_time = per_second / (sample_rate as f64 * channels as f64);
+    eprintln!(
+        "Processed {} samples in {:.3}s: {:.0} samples/sec, {:.1}x real time",
+        samples,
+        elapsed.as_secs_f64(),
+        per_second,
+        real_time
+    );
+    eprintln!(
+        "Peak output level {:.4} ({:.1} dBFS)",
+        peak,
+        linear_to_db(peak)
+    );
+    if non_finite > 0 {
+        eprintln!("{} output samples were NaN or infinite!", non_finite);
+    } else {
+        eprintln!("No NaN or infinite samples");
+    }
+
+    if clip_detector.is_some_and(|detector| detector.tripped()) {
+        return Err(clip_abort_message(args).into());
+    }
+    Ok(())
+}
+
+// Measures the whole mono effect path, gains and EQ included, for
+// --measure-response.
+fn print_response(args: &Args) -> Result<(), Box<dyn Error>> {
+    let sample_rate = SampleRate(args.raw_rate.unwrap_or(DRY_RUN_RAW_RATE));
+    let chain_config = chain_config(args)?;
+    let (mut frame_filter, _) =
+        build_frame_filter(args, chain_config.as_ref(), None, 1, sample_rate)?;
+
+    let gains = measure_response(
+        |block| frame_filter.process_block(block, 1),
+        sample_rate,
+        &THIRD_OCTAVES,
+    );
+    println!("frequency_hz,gain_db");
+    for (freq, gain) in THIRD_OCTAVES.iter().zip(gains) {
+        // Frequencies too near Nyquist for this rate are left out.
+        if gain.is_finite() {
+            println!("{},{:.2}", freq, gain);
+        }
+    }
+    Ok(())
+}
+
+// How long to let the streams settle before sending the test impulse.
+const LATENCY_SETTLE: Duration = Duration::from_millis(500);
+
+// How long to wait for the impulse to come back before giving up.
+const LATENCY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Input level that counts as hearing the impulse.
+const LATENCY_THRESHOLD: f32 = 0.3;
+
+// Plays a single impulse and times how long it takes to arrive back at the
+// input.  This covers the device and driver buffering on both sides; the
+// ringbuffer between the callbacks adds buffer_frames on top of that during
+// normal operation.
+fn measure_latency(
+    input_device: &Device,
+    output_device: &Device,
+    input_config: &StreamConfig,
+    input_format: SampleFormat,
+    config: &StreamConfig,
+    output_format: SampleFormat,
+    buffer_frames: usize,
+) -> Result<(), Box<dyn Error>> {
+    let SampleRate(sample_rate) = config.sample_rate;
+    let start = Instant::now();
+
+    // Nanoseconds since start at which the impulse was sent and heard.  Zero
+    // means it hasn't happened yet.
+    let sent = Arc::new(AtomicU64::new(0));
+    let heard = Arc::new(AtomicU64::new(0));
+
+    let output_sent = sent.clone();
+    let output_channels = config.channels as usize;
+    let output_data_fn = move |data: &mut [f32], _cbinfo: &OutputCallbackInfo| {
+        data.fill(0.0);
+        if output_sent.load(Ordering::Acquire) == 0 && start.elapsed() >= LATENCY_SETTLE {
+            // The impulse goes at the very start of the buffer, so this
+            // callback's timestamp is when it was handed to the device.
+            let impulse = output_channels.min(data.len());
+            data[..impulse].fill(0.9);
+            let now = start.elapsed().as_nanos() as u64;
+            output_sent.store(now.max(1), Ordering::Release);
+        }
+    };
+
+    let input_sent = sent.clone();
+    let input_heard = heard.clone();
+    let input_channels = input_config.channels as usize;
+    let input_data_fn = move |data: &[f32], _cbinfo: &InputCallbackInfo| {
+        if input_sent.load(Ordering::Acquire) == 0 || input_heard.load(Ordering::Acquire) != 0 {
+            return;
+        }
+
+        if let Some(index) = data.iter().position(|s| s.abs() > LATENCY_THRESHOLD) {
+            // The callback runs once the whole buffer has been captured, so
+            // back up to when the detected frame came in.
+            let frames_after = (data.len() - index) / input_channels;
+            let backtrack = frames_after as u64 * 1_000_000_000 / sample_rate as u64;
+            let now = start.elapsed().as_nanos() as u64;
+            input_heard.store(now.saturating_sub(backtrack).max(1), Ordering::Release);
+        }
+    };
+
+    let input_stream = open_input_stream(
+        input_device,
+        input_config,
+        input_format,
+        input_data_fn,
+        stream_error_handler(StreamKind::Input, None),
+    )?;
+    let output_stream = open_output_stream(
+        output_device,
+        config,
+        output_format,
+        output_data_fn,
+        stream_error_handler(StreamKind::Output, None),
+    )?;
+    input_stream.play()?;
+    output_stream.play()?;
+
+    let deadline = LATENCY_SETTLE + LATENCY_TIMEOUT;
+    while heard.load(Ordering::Acquire) == 0 && start.elapsed() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    drop(input_stream);
+    drop(output_stream);
+
+    let sent = sent.load(Ordering::Acquire);
+    let heard = heard.load(Ordering::Acquire);
+    if sent == 0 || heard == 0 {
+        println!(
+            "No impulse detected within {:?} at {} Hz.  Is the output looped back into the input?",
+            LATENCY_TIMEOUT, sample_rate
+        );
+        return Ok(());
+    }
+
+    let round_trip = Duration::from_nanos(heard.saturating_sub(sent));
+    let round_trip_frames = (round_trip.as_secs_f64() * sample_rate as f64).round() as usize;
+    let total_frames = round_trip_frames + buffer_frames;
+    let frames_to_ms = |frames: usize| frames as f64 * 1000.0 / sample_rate as f64;
+
+    println!("Sample rate {} Hz", sample_rate);
+    println!(
+        "Device round trip: {:.2} ms ({} samples)",
+        frames_to_ms(round_trip_frames),
+        round_trip_frames
+    );
+    println!(
+        "Including {} frames of buffering: {:.2} ms ({} samples)",
+        buffer_frames,
+        frames_to_ms(total_frames),
+        total_frames
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if args.dry_run {
+        return dry_run(&args);
+    }
+    if args.measure_response {
+        return print_response(&args);
+    }
+
+    STDOUT_IS_DATA.store(args.stdout_raw || args.show_config, Ordering::Relaxed);
+
+    if args.list_hosts {
+        list_hosts();
+        return Ok(());
+    }
+
+    let host = select_host(args.host.as_deref());
+
+    if args.list_json {
+        list_devices_json(&host)?;
+        return Ok(());
+    }
+
+    if args.list {
+        list_input_devices(&host)?;
+        println!();
+        list_output_devices(&host)?;
+        println!();
+        println!("* marks the default device");
+        return Ok(());
+    }
+
+    let source = match (&args.input_file, args.generate) {
+        (Some(path), _) => Source::File {
+            path: path.clone(),
+            reader: open_wav(path)?,
+        },
+        (None, Some(signal)) => Source::Generator(signal),
+        (None, None) if args.stdin_raw => Source::Stdin,
+        (None, None) => Source::Device(select_input_device(
+            &host,
+            args.input_device,
+            args.input_name.as_deref(),
+        )?),
+    };
+    let output_device =
+        select_output_device(&host, args.output_device, args.output_name.as_deref())?;
+
+    match &source {
+        Source::Device(device) => status!("Using {}", device.name()?),
+        Source::File { path, .. } if args.loop_input => {
+            status!("Using {}, looping", path.display())
+        }
+        Source::File { path, .. } => status!("Using {}", path.display()),
+        Source::Generator(signal) => status!("Using a generated {:?} signal", signal),
+        Source::Stdin => status!("Using raw samples from stdin"),
+    }
+    status!("And {}", output_device.name()?);
+
+    let configs = match &source {
+        Source::Device(device) => {
+            let configs = negotiate_stream_configs(device, &output_device)?;
+            status!(
+                "Sample formats {:?} in, {:?} out",
+                configs.input_format,
+                configs.output_format
+            );
+            configs
+        }
+        Source::File { reader, .. } => {
+            let spec = reader.spec();
+            source_stream_configs(
+                &output_device,
+                spec.channels,
+                Some(SampleRate(spec.sample_rate)),
+            )?
+        }
+        Source::Generator(_) => source_stream_configs(&output_device, 1, None)?,
+        Source::Stdin => source_stream_configs(
+            &output_device,
+            args.raw_channels,
+            args.raw_rate.map(SampleRate),
+        )?,
+    };
+    let StreamConfigs {
+        input: input_config,
+        input_format,
+        output: config,
+        output_format,
+    } = configs.clone();
+
+    // Default to 20ms of latency between the input and output callbacks.
+    let SampleRate(sample_rate) = config.sample_rate;
+    let buffer_frames = args.buffer_frames.unwrap_or(sample_rate as usize / 50);
+    if buffer_frames == 0 {
+        return Err("--buffer-frames must be greater than zero".into());
+    }
+    if buffer_frames < sample_rate as usize / 1000 {
+        eprintln!(
+            "Warning: {} frames is less than 1ms of audio; expect dropouts",
+            buffer_frames
+        );
+    }
+
+    let buffer: RingBuffer<f32> = RingBuffer::new(buffer_frames * input_config.channels as usize);
+    let (producer, mut consumer) = buffer.split();
+
+    // Only needed when the devices (or the file) couldn't agree on a rate.
+    let mut resampler = if input_config.sample_rate != config.sample_rate {
+        status!(
+            "Sample rate {} Hz in, {} Hz out; resampling with {:?} interpolation",
+            input_config.sample_rate.0,
+            config.sample_rate.0,
+            args.resample
+        );
+        Some(Resampler::new(
+            args.resample,
+            input_config.channels as usize,
+            input_config.sample_rate,
+            config.sample_rate,
+        ))
+    } else {
+        status!("Sample rate {:?}", config.sample_rate);
+        None
+    };
+    status!(
+        "{} input channels, {} output channels",
+        input_config.channels,
+        config.channels
+    );
+    status!("Buffering {} frames", buffer_frames);
+    status!();
+
+    if args.measure_latency {
+        if resampler.is_some() {
+            return Err("--measure-latency needs both devices at the same sample rate".into());
+        }
+        let Source::Device(input_device) = source else {
+            return Err("--measure-latency needs a live input device".into());
+        };
+        return measure_latency(
+            &input_device,
+            &output_device,
+            &input_config,
+            input_format,
+            &config,
+            output_format,
+            buffer_frames,
+        );
+    }
+
+    if !(0.0..=1.0).contains(&args.mix) {
+        eprintln!("--mix {} is out of range; clamping to 0.0 - 1.0", args.mix);
+    }
+
+    let output_channels = config.channels as usize;
+    let chain_config = chain_config(&args)?;
+    let mut controls = LiveControls::new(&args);
+    let (mut frame_filter, stages) = build_frame_filter(
+        &args,
+        chain_config.as_ref(),
+        Some(&mut controls),
+        output_channels,
+        config.sample_rate,
+    )?;
+
+    if args.show_config {
+        return show_config(
+            &args,
+            stages,
+            &source,
+            &output_device,
+            &configs,
+            buffer_frames,
+        );
+    }
+
+    let stage_timers = controls.timers.clone();
+
+    // Kept alive until main returns, since dropping it stops MIDI input.
+    #[cfg(feature = "midi")]
+    let _midi_connection = if args.midi {
+        midi::connect(vec![
+            midi::CcMapping {
+                controller: args.midi_gain_cc,
+                param: controls.gain.clone(),
+                min: 0.0,
+                max: MIDI_MAX_GAIN,
+            },
+            midi::CcMapping {
+                controller: args.midi_decay_cc,
+                param: controls.decay.clone(),
+                min: 0.0,
+                max: MAX_LIVE_DECAY,
+            },
+        ])
+    } else {
+        None
+    };
+
+    let (recorder, mut record_producer) = match &args.record {
+        Some(path) => {
+            let (recorder, producer) = Recorder::start(path, &config, &args)?;
+            status!("Recording to {}", path.display());
+            (Some(recorder), Some(producer))
+        }
+        None => (None, None),
+    };
+    let (raw_writer, mut raw_producer) = if args.stdout_raw {
+        let (writer, producer) = Recorder::start_stdout(&config);
+        status!("Writing raw samples to stdout");
+        (Some(writer), Some(producer))
+    } else {
+        (None, None)
+    };
+
+    let stats = Arc::new(XrunStats::default());
+    let clip_stats = Arc::new(ClipStats::default());
+
+    // Set by Ctrl-C, by pressing Enter, or once file playback finishes.  The
+    // handler only touches this flag, so it can't contend with the audio
+    // callbacks.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let handler_shutdown = shutdown.clone();
+    ctrlc::set_handler(move || handler_shutdown.store(true, Ordering::Release))?;
+
+    let sanitized = Arc::new(AtomicUsize::new(0));
+    let diagnostics = Arc::new(Diagnostics::default());
+    let diagnostics_display = if args.verbose {
+        Some(DiagnosticsDisplay::start(
+            diagnostics.clone(),
+            stats.clone(),
+            producer.capacity(),
+        ))
+    } else {
+        None
+    };
+
+    // Set once shutdown starts, to fade the output out before the streams
+    // are stopped.
+    let fading_out = Arc::new(AtomicBool::new(false));
+    let fade_time = Duration::from_millis(args.fade_in_ms);
+    let mut ramp = GainRamp::new(args.fade_in_ms as f32, config.sample_rate);
+
+    let output_fading_out = fading_out.clone();
+    let output_stats = stats.clone();
+    let output_clip_stats = clip_stats.clone();
+    let profile = args.profile.then(|| Arc::new(Profile::default()));
+    let output_profile = profile.clone();
+    let mut clip_detector = clip_detector(&args, &config);
+    let clipped_out = Arc::new(AtomicBool::new(false));
+    let output_clipped_out = clipped_out.clone();
+    let output_sanitized = sanitized.clone();
+    let output_diagnostics = diagnostics.clone();
+    let mut last_output_callback = None;
+    let input_channels = input_config.channels as usize;
+    let mut input_frame = vec![0.0; input_channels];
+    let mut input_block = Vec::new();
+    let mut loop_position = match &source {
+        Source::File { reader, .. } => LoopPosition::new(reader, args.loop_input),
+        _ => None,
+    };
+    let mono = args.mono;
+    let mute_input = args.mute_input;
+    let output_data_fn = move |data: &mut [f32], _cbinfo: &OutputCallbackInfo| {
+        let callback_start = output_profile.is_some().then(Instant::now);
+        record_callback_gap(&mut last_output_callback, &output_diagnostics.output_gap_us);
+        output_diagnostics
+            .buffered
+            .store(consumer.len(), Ordering::Relaxed);
+
+        if output_fading_out.load(Ordering::Acquire) {
+            ramp.fade_out();
+        }
+
+        // Anything the input couldn't supply in time is played as silence.
+        // Without a resampler the whole buffer's input is taken in one go;
+        // the resampler pulls it a frame at a time as it needs it.
+        // A --loop file starting over resets the effect at the output frame
+        // where its first sample lands.
+        let mut underrun = false;
+        let mut replaced = 0;
+        let mut reset_at = None;
+        let frames = data.len() / output_channels;
+        match resampler.as_mut() {
+            Some(resampler) => {
+                for (n, frame) in data.chunks_mut(output_channels).enumerate() {
+                    let mut popped = 0;
+                    resampler.next_frame(&mut input_frame, |input_frame| {
+                        let missing = pop_samples(&mut consumer, input_frame);
+                        underrun |= missing > 0;
+                        popped += input_frame.len() - missing;
+                    });
+                    if let Some(position) = loop_position.as_mut() {
+                        if position.advance(popped).is_some() {
+                            reset_at.get_or_insert(n);
+                        }
+                    }
+                    if mono {
+                        frame.fill(downmix(&input_frame));
+                    } else {
+                        remix_frame(&input_frame, frame);
+                    }
+                }
+            }
+            None => {
+                input_block.resize(frames * input_channels, 0.0);
+                let missing = pop_samples(&mut consumer, &mut input_block);
+                underrun = missing > 0;
+                if let Some(position) = loop_position.as_mut() {
+                    reset_at = position
+                        .advance(input_block.len() - missing)
+                        .map(|offset| offset / input_channels);
+                }
+                let input_frames = input_block.chunks(input_channels);
+                for (input_frame, frame) in input_frames.zip(data.chunks_mut(output_channels)) {
+                    if mono {
+                        frame.fill(downmix(input_frame));
+                    } else {
+                        remix_frame(input_frame, frame);
+                    }
+                }
+            }
+        }
+
+        let effect_start = output_profile.is_some().then(Instant::now);
+        match reset_at {
+            Some(frame) => {
+                let (before, after) = data.split_at_mut(frame * output_channels);
+                frame_filter.process_block(before, output_channels);
+                frame_filter.reset();
+                frame_filter.process_block(after, output_channels);
+            }
+            None => frame_filter.process_block(data, output_channels),
+        }
+        if let (Some(profile), Some(start)) = (&output_profile, effect_start) {
+            profile.effect.add(start.elapsed());
+        }
+
+        for frame in data.chunks_mut(output_channels) {
+            replaced += sanitize(frame);
+
+            let gain = ramp.next_gain();
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+        if replaced > 0 {
+            output_sanitized.fetch_add(replaced, Ordering::Relaxed);
+        }
+        if underrun {
+            output_stats.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(record_producer) = record_producer.as_mut() {
+            record_producer.push_slice(data);
+        }
+        if mute_input {
+            data.fill(0.0);
+        }
+        output_clip_stats.update(data);
+        if let Some(detector) = clip_detector.as_mut() {
+            if detector.update(data) {
+                output_clipped_out.store(true, Ordering::Release);
+            }
+        }
+
+        if let Some(raw_producer) = raw_producer.as_mut() {
+            raw_producer.push_slice(data);
+        }
+
+        if let (Some(profile), Some(start)) = (&output_profile, callback_start) {
+            profile.callback.add(start.elapsed());
+            profile.frames.fetch_add(frames, Ordering::Relaxed);
+        }
+    };
+
+    let mut player = None;
+    let mut generator_thread = None;
+    let mut meter_display = None;
+    let mut spectrum_display = None;
+    // Errors from either stream, for the main loop to act on.
+    let (failure_sender, failures) = mpsc::channel();
+    let mut input_stream = match source {
+        Source::Device(device) => {
+            let mut producer = producer;
+            let input_stats = stats.clone();
+            let input_diagnostics = diagnostics.clone();
+            let mut last_input_callback = None;
+            let strict = args.strict;
+
+            let meter = if args.meter {
+                let meter = Arc::new(LevelMeter::new(input_config.channels as usize));
+                let peak_hold = Duration::from_millis(args.peak_hold_ms);
+                meter_display = Some(MeterDisplay::start(meter.clone(), peak_hold));
+                Some(meter)
+            } else {
+                None
+            };
+
+            // The spectrum shows a mono mix of the input.
+            let mut spectrum = if args.spectrum {
+                let (producer, consumer) = RingBuffer::new(4 * args.fft_size).split();
+                spectrum_display = Some(SpectrumDisplay::start(
+                    consumer,
+                    args.fft_size,
+                    input_config.sample_rate.0,
+                ));
+                Some(producer)
+            } else {
+                None
+            };
+
+            let input_data_fn = move |data: &[f32], _cbinfo: &InputCallbackInfo| {
+                record_callback_gap(&mut last_input_callback, &input_diagnostics.input_gap_us);
+                if let Some(meter) = &meter {
+                    meter.update(data);
+                }
+                if let Some(spectrum) = spectrum.as_mut() {
+                    // If the display falls behind, it just misses some input.
+                    for frame in data.chunks(input_channels) {
+                        let _ = spectrum.push(downmix(frame));
+                    }
+                }
+
+                let dropped = push_samples(&mut producer, data);
+                if dropped > 0 {
+                    if strict {
+                        panic!(
+                            "Unable to refill output buffer: dropped {} samples",
+                            dropped
+                        );
+                    }
+                    input_stats.dropped.fetch_add(dropped, Ordering::Relaxed);
+                }
+            };
+            let input_data_fn = Arc::new(Mutex::new(input_data_fn));
+            let input_config = input_config.clone();
+            let failure_sender = failure_sender.clone();
+            let reopen = move || -> Result<Stream, Box<dyn Error>> {
+                let stream = open_input_stream(
+                    &device,
+                    &input_config,
+                    input_format,
+                    shared_input(&input_data_fn),
+                    stream_error_handler(StreamKind::Input, Some(failure_sender.clone())),
+                )?;
+                stream.play()?;
+                Ok(stream)
+            };
+            Some(RecoverableStream::open(
+                StreamKind::Input,
+                Box::new(reopen),
+            )?)
+        }
+        Source::File { reader, .. } => {
+            player = Some(play_samples(
+                wav_samples(reader, args.loop_input),
+                producer,
+                shutdown.clone(),
+            ));
+            None
+        }
+        Source::Stdin => {
+            let samples = raw_samples(std::io::stdin());
+            player = Some(play_samples(samples, producer, shutdown.clone()));
+            None
+        }
+        Source::Generator(signal) => {
+            let generator =
+                SignalGenerator::new(signal, args.tone_freq, args.tone_amp, config.sample_rate);
+            generator_thread = Some(play_generator(generator, producer, shutdown.clone()));
+            None
+        }
+    };
+    if input_stream.is_none() {
+        if args.meter {
+            eprintln!("--meter only measures live input; ignoring it");
+        }
+        if args.spectrum {
+            eprintln!("--spectrum only shows live input; ignoring it");
+        }
+    }
+
+    let output_data_fn = Arc::new(Mutex::new(output_data_fn));
+    let output_config = config.clone();
+    let reopen = move || -> Result<Stream, Box<dyn Error>> {
+        let stream = open_output_stream(
+            &output_device,
+            &output_config,
+            output_format,
+            shared_output(&output_data_fn),
+            stream_error_handler(StreamKind::Output, Some(failure_sender.clone())),
+        )?;
+        stream.play()?;
+        Ok(stream)
+    };
+    let mut output_stream = RecoverableStream::open(StreamKind::Output, Box::new(reopen))?;
+
+    // Timed from here so opening the streams doesn't eat into the run.
+    let deadline = args.duration.map(|duration| Instant::now() + duration);
+
+    if let Some(duration) = args.duration {
+        status!(
+            "Running for {:.1}s; press Ctrl-C to stop early",
+            duration.as_secs_f64()
+        );
+    } else if player.is_none() {
+        status!("Press Enter or Ctrl-C to quit");
+        status!("Type + or - then Enter to change the distortion gain, [ or ] for delay decay");
+        status!("Type b and a stage number to bypass that stage, or bring it back");
+
+        let stdin_shutdown = shutdown.clone();
+        thread::spawn(move || keyboard_control(controls, stdin_shutdown));
+    }
+
+    let backoff = Backoff::new(
+        STREAM_RETRY_INITIAL,
+        STREAM_RETRY_MAX,
+        STREAM_RETRY_ATTEMPTS,
+    );
+    let mut stream_failure = None;
+    let mut warned_clipping = false;
+    while !shutdown.load(Ordering::Acquire)
+        && !clipped_out.load(Ordering::Acquire)
+        && !player.as_ref().is_some_and(|player| player.is_finished())
+        && deadline.is_none_or(|deadline| Instant::now() < deadline)
+    {
+        if !warned_clipping && clip_stats.clipped.load(Ordering::Relaxed) > 0 {
+            eprintln!("Warning: the output is clipping");
+            warned_clipping = true;
+        }
+        if let Ok((kind, err)) = failures.try_recv() {
+            let stream = match kind {
+                StreamKind::Input => input_stream.as_mut(),
+                StreamKind::Output => Some(&mut output_stream),
+            };
+            if let Some(stream) = stream {
+                if let Err(err) = stream.recover(err, &backoff, &shutdown) {
+                    stream_failure = Some(err);
+                    break;
+                }
+            }
+            // A dying stream tends to report the same failure more than
+            // once; the new one starts with a clean slate.
+            while failures.try_recv().is_ok() {}
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    shutdown.store(true, Ordering::Release);
+
+    if let Some(player) = player {
+        match player.join() {
+            Ok(result) => result.map_err(|err| err as Box<dyn Error>)?,
+            Err(_) => return Err("Playback thread panicked".into()),
+        }
+    }
+    if let Some(generator_thread) = generator_thread {
+        if generator_thread.join().is_err() {
+            return Err("Signal generator thread panicked".into());
+        }
+    }
+
+    // Give the fade-out time to reach silence, plus a buffer's worth for the
+    // last of it to be played.
+    if !fade_time.is_zero() {
+        fading_out.store(true, Ordering::Release);
+        thread::sleep(
+            fade_time + Duration::from_secs_f64(buffer_frames as f64 / sample_rate as f64),
+        );
+    }
+
+    // Stop the streams before finalizing so no more samples arrive.
+    if let Some(input_stream) = &input_stream {
+        input_stream.pause();
+    }
+    output_stream.pause();
+    drop(input_stream);
+    drop(output_stream);
+
+    if let Some(meter_display) = meter_display {
+        meter_display.finish();
+    }
+    if let Some(diagnostics_display) = diagnostics_display {
+        diagnostics_display.finish();
+    }
+    if let Some(spectrum_display) = spectrum_display {
+        spectrum_display.finish();
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    if let Some(raw_writer) = raw_writer {
+        raw_writer.finish()?;
+    }
+
+    status!(
+        "{} samples dropped to overruns, {} underruns",
+        stats.dropped.load(Ordering::Relaxed),
+        stats.underruns.load(Ordering::Relaxed)
+    );
+
+    let peak = f32::from_bits(clip_stats.peak.load(Ordering::Relaxed));
+    status!(
+        "Output peak {:.4} ({:.1} dBFS), {} samples clipped",
+        peak,
+        linear_to_db(peak),
+        clip_stats.clipped.load(Ordering::Relaxed)
+    );
+
+    if let Some(profile) = profile {
+        profile.print(&stage_timers, sample_rate);
+    }
+
+    let sanitized = sanitized.load(Ordering::Relaxed);
+    if sanitized > 0 {
+        status!(
+            "Replaced {} NaN or infinite samples with silence; the effect settings are unstable",
+            sanitized
+        );
+    }
+
+    if let Some(err) = stream_failure {
+        return Err(err);
+    }
+    if clipped_out.load(Ordering::Acquire) {
+        return Err(clip_abort_message(&args).into());
+    }
+
+    status!("Goodbye World!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn mono_input_is_duplicated_across_the_output() {
+        let mut frame = [0.0; 2];
+        remix_frame(&[0.5], &mut frame);
+        assert_eq!(frame, [0.5, 0.5]);
+        remix_frame(&[0.1, 0.2, 0.3], &mut frame);
+        assert_eq!(frame, [0.1, 0.2]);
+    }
+
+    #[test]
+    fn oversampled_auto_gain_is_timed_at_the_oversampled_rate() {
+        let args = Args::parse_from(["g2"]);
+        let values = HashMap::from([
+            ("auto_gain".to_string(), "true".to_string()),
+            ("oversample".to_string(), "4".to_string()),
+        ]);
+        let mut params = Params::new(values);
+        let mut built =
+            build_effect(Effect::Distort, &mut params, &args, None, SampleRate(48000)).unwrap();
+
+        let mut distort = DistortFilter::new(args.gain, args.saturation, args.distort_mode);
+        distort.enable_auto_gain(SampleRate(4 * 48000));
+        let mut expected = Oversampler::new(distort, 4, SampleRate(48000));
+        for n in 0..4800 {
+            let x = 0.2 * (n as f32 * 0.03).sin();
+            assert_eq!(built.process(x), expected.process(x), "sample {}", n);
+        }
+    }
+
+    // Whether effect builds from the command line argv and the config or
+    // chain parameters values.
+    fn builds(effect: Effect, argv: &[&str], values: &[(&str, &str)]) -> bool {
+        let args = Args::parse_from(argv);
+        let values = values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let mut params = Params::new(values);
+        build_effect(effect, &mut params, &args, None, SampleRate(48000)).is_ok()
+    }
+
+    #[test]
+    fn filters_reject_a_zero_q_and_cutoffs_past_nyquist() {
+        for effect in [Effect::Lowpass, Effect::Highpass] {
+            assert!(builds(effect, &["g2"], &[]));
+            assert!(!builds(effect, &["g2"], &[("q", "0")]));
+            assert!(!builds(effect, &["g2"], &[("cutoff", "24000")]));
+            assert!(!builds(effect, &["g2", "--q", "0"], &[]));
+            assert!(!builds(effect, &["g2", "--cutoff", "0"], &[]));
+            assert!(!builds(effect, &["g2", "--cutoff", "30000"], &[]));
+        }
+    }
+
+    #[test]
+    fn soft_distortion_needs_a_saturation_above_zero() {
+        let soft = ["g2", "--distort-mode", "soft"];
+        assert!(builds(Effect::Distort, &soft, &[]));
+        assert!(!builds(Effect::Distort, &soft, &[("saturation", "0")]));
+        assert!(!builds(Effect::Multiband, &soft, &[("saturation", "0")]));
+        assert!(!builds(
+            Effect::Distort,
+            &[&soft[..], &["--saturation", "0"]].concat(),
+            &[]
+        ));
+
+        // Hard clipping and folding at zero are just silence.
+        assert!(builds(Effect::Distort, &["g2"], &[("saturation", "0")]));
+        assert!(builds(Effect::Foldback, &["g2"], &[("saturation", "0")]));
+        assert!(!builds(Effect::Distort, &["g2", "--saturation=-0.5"], &[]));
+    }
+
+    #[test]
+    fn config_file_builds_its_effects_in_order() {
+        let config = ChainConfig::parse(
+            "[[effect]]\ntype = \"lowpass\"\ncutoff = 1500.0\nq = 0.9\n\n\
+             [[effect]]\ntype = \"distort\"\ngain = 6.0\nsaturation = 0.4\nmode = \"soft\"\n",
+        )
+        .unwrap();
+        assert!(matches!(config.effects[0].effect, Effect::Lowpass));
+        assert!(matches!(config.effects[1].effect, Effect::Distort));
+
+        let args = Args::parse_from(["g2"]);
+        let (mut chain, _) = config.build(&args, SampleRate(48000)).unwrap();
+        let mut lowpass = LowPassFilter::new(1500.0, 0.9, SampleRate(48000));
+        let mut distort = DistortFilter::new(6.0, 0.4, DistortMode::Soft);
+        for n in 0..480 {
+            let x = 0.5 * (n as f32 * 0.05).sin();
+            let expected = distort.process(lowpass.process(x));
+            assert!((chain.process(x) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn config_errors_give_the_line() {
+        let error = |text: &str| match ChainConfig::parse(text) {
+            Ok(config) => config
+                .build(&Args::parse_from(["g2"]), SampleRate(48000))
+                .err()
+                .unwrap()
+                .to_string(),
+            Err(err) => err.to_string(),
+        };
+
+        assert_eq!(
+            error("[[effect]]\ntype = \"distort\"\n\n[[effect]]\ntype = \"wobble\"\n"),
+            "Config line 4: Unknown effect \"wobble\""
+        );
+        assert!(error("[[effect]]\ntype = \"distort\"\nsaturation = 2.0\n")
+            .starts_with("Config line 1: saturation = 2 is outside"));
+    }
+
+    #[test]
+    fn telephone_preset_cuts_both_ends_of_the_band() {
+        // Quiet enough that the distortion at the end stays linear.
+        let gain_at = |freq_hz: f32| {
+            let mut chain = Preset::Telephone.build(SampleRate(48000));
+            let step = 2.0 * PI * freq_hz / 48000.0;
+            let peak = (0..96000)
+                .map(|i| chain.process(0.01 * (step * i as f32).sin()))
+                .skip(48000)
+                .fold(0.0f32, |peak, s| peak.max(s.abs()));
+            linear_to_db(peak)
+        };
+
+        // The high-pass sits at 300Hz and the low-pass at 3.4kHz.
+        let voice = gain_at(1000.0);
+        assert!(voice - gain_at(50.0) > 24.0);
+        assert!(voice - gain_at(16000.0) > 24.0);
+        assert!((voice - gain_at(500.0)).abs() < 1.0);
+        assert!((voice - gain_at(2000.0)).abs() < 1.0);
+    }
+
+    #[test]
+    fn f32_survives_a_trip_through_i16() {
+        // One step of a 16-bit sample, as a fraction of full scale.
+        let i16_step = 1.0 / 32767.0;
+
+        let input: Vec<f32> = (-1000..=1000).map(|n| n as f32 / 1000.0).collect();
+        let mut native = vec![0i16; input.len()];
+        samples_from_f32(&input, &mut native);
+        let mut output = Vec::new();
+        samples_to_f32(&native, &mut output);
+
+        assert_eq!(output.len(), input.len());
+        for (got, want) in output.iter().zip(&input) {
+            assert!((got - want).abs() <= i16_step, "{} became {}", want, got);
+        }
+        assert_eq!(native[0], i16::MIN);
+        assert_eq!(native[2000], i16::MAX);
+    }
+
+    #[test]
+    fn i16_survives_a_trip_through_f32() {
+        let input: Vec<i16> = (i16::MIN..=i16::MAX).step_by(7).collect();
+        let mut converted = Vec::new();
+        samples_to_f32(&input, &mut converted);
+        assert!(converted.iter().all(|s| (-1.0..=1.0).contains(s)));
+
+        let mut output = vec![0i16; input.len()];
+        samples_from_f32(&converted, &mut output);
+        for (got, want) in output.iter().zip(&input) {
+            assert!(
+                (*got as i32 - *want as i32).abs() <= 1,
+                "{} became {}",
+                want,
+                got
+            );
+        }
+    }
+
+    #[test]
+    fn mono_sums_opposite_channels_to_silence() {
+        assert_eq!(downmix(&[1.0, -1.0]), 0.0);
+        assert_eq!(downmix(&[0.5, 0.25, 0.0, 0.25]), 0.25);
+        assert_eq!(downmix(&[0.5]), 0.5);
+    }
+
+    #[test]
+    fn device_not_found_lists_the_alternatives() {
+        let err = G2Error::DeviceNotFound {
+            substring: "usb".to_string(),
+            available: vec!["Built-in".to_string(), "HDMI".to_string()],
+        };
+        assert_eq!(
+            err.to_string(),
+            "No device matching \"usb\".  Available devices: Built-in, HDMI"
+        );
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn messages_describe_each_failure() {
+        assert_eq!(
+            G2Error::NoDefaultDevice.to_string(),
+            "No default audio device is available"
+        );
+        assert_eq!(
+            G2Error::UnsupportedConfig("No shared sample rate".to_string()).to_string(),
+            "No shared sample rate"
+        );
+        assert_eq!(
+            G2Error::Backend("ALSA went away".to_string()).to_string(),
+            "Audio backend error: ALSA went away"
+        );
+    }
+
+    #[test]
+    fn stream_build_errors_keep_their_cause() {
+        let err = G2Error::from(BuildStreamError::DeviceNotAvailable);
+        let cause = BuildStreamError::DeviceNotAvailable.to_string();
+        assert_eq!(
+            err.to_string(),
+            format!("Couldn't open audio stream: {}", cause)
+        );
+        assert_eq!(err.source().unwrap().to_string(), cause);
+    }
 }
@@ -0,0 +1,71 @@
+// MIDI control of effect parameters.  Knobs send Control Change messages,
+// which are mapped straight onto the same shared parameters the keyboard
+// controls use, so the audio callback never waits on MIDI.
+
+use g2::effects::SharedParam;
+use midir::{MidiInput, MidiInputConnection};
+
+// Ties one MIDI controller number to a parameter.  CC values 0 - 127 are
+// spread evenly from min to max.
+pub struct CcMapping {
+    pub controller: u8,
+    pub param: SharedParam,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl CcMapping {
+    fn apply(&self, value: u8) {
+        let t = value.min(127) as f32 / 127.0;
+        self.param.set(self.min + t * (self.max - self.min));
+    }
+}
+
+// Control Change messages have a status byte of 0xBn, where n is the
+// channel.  Every channel is listened to.
+const CONTROL_CHANGE: u8 = 0xB0;
+
+// Opens the first MIDI input port and starts applying the mappings.  The
+// returned connection stops listening when dropped.  MIDI is a nice to have,
+// so any problem is reported and None is returned rather than failing.
+pub fn connect(mappings: Vec<CcMapping>) -> Option<MidiInputConnection<()>> {
+    let midi_in = match MidiInput::new("g2") {
+        Ok(midi_in) => midi_in,
+        Err(err) => {
+            eprintln!("Couldn't start MIDI: {}; continuing without it", err);
+            return None;
+        }
+    };
+
+    let ports = midi_in.ports();
+    for (i, port) in ports.iter().enumerate() {
+        match midi_in.port_name(port) {
+            Ok(name) => println!("MIDI port {}: {}", i, name),
+            Err(err) => println!("MIDI port {}: unknown ({})", i, err),
+        }
+    }
+    let Some(port) = ports.first() else {
+        eprintln!("No MIDI input ports found; continuing without MIDI");
+        return None;
+    };
+
+    let callback = move |_timestamp: u64, message: &[u8], _: &mut ()| {
+        if let [status, controller, value] = *message {
+            if status & 0xF0 == CONTROL_CHANGE {
+                for mapping in mappings.iter().filter(|m| m.controller == controller) {
+                    mapping.apply(value);
+                }
+            }
+        }
+    };
+    match midi_in.connect(port, "g2-control", callback, ()) {
+        Ok(connection) => {
+            println!("Listening for MIDI on port 0");
+            Some(connection)
+        }
+        Err(err) => {
+            eprintln!("Couldn't open MIDI port: {}; continuing without MIDI", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Sample rate conversion, so devices that can't agree on a rate can still be
+// wired together.
+
+use clap::ValueEnum;
+use cpal::SampleRate;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    // Draws straight lines between input samples.  Cheap, but dulls the top
+    // end and adds some aliasing.
+    Linear,
+
+    // Fits a Catmull-Rom curve through four input samples.  Much cleaner for
+    // very little extra work.
+    Cubic,
+}
+
+// Converts a stream of frames from one rate to another.  Input is pulled on
+// demand, a frame at a time, as each output frame needs it.  Neither mode
+// filters out content above the new Nyquist frequency, so downsampling
+// aliases a little.
+pub struct Resampler {
+    quality: ResampleQuality,
+    channels: usize,
+
+    // Input frames consumed per output frame.
+    ratio: f64,
+
+    // How far the next output frame lies between history frames 1 and 2.
+    position: f64,
+
+    // The last four input frames, oldest first, one after another.
+    history: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(
+        quality: ResampleQuality,
+        channels: usize,
+        from: SampleRate,
+        to: SampleRate,
+    ) -> Resampler {
+        let (SampleRate(from), SampleRate(to)) = (from, to);
+        Resampler {
+            quality,
+            channels,
+            ratio: from as f64 / to as f64,
+            position: 0.0,
+            history: vec![0.0; 4 * channels],
+        }
+    }
+
+    // Fills output with the next frame, calling input to fill in each new
+    // input frame it needs.
+    pub fn next_frame<F>(&mut self, output: &mut [f32], mut input: F)
+    where
+        F: FnMut(&mut [f32]),
+    {
+        let t = self.position as f32;
+        for (c, sample) in output.iter_mut().enumerate() {
+            let h = |frame: usize| self.history[frame * self.channels + c];
+            *sample = match self.quality {
+                ResampleQuality::Linear => h(1) + t * (h(2) - h(1)),
+                ResampleQuality::Cubic => {
+                    let (h0, h1, h2, h3) = (h(0), h(1), h(2), h(3));
+                    h1 + 0.5
+                        * t
+                        * (h2 - h0
+                            + t * (2.0 * h0 - 5.0 * h1 + 4.0 * h2 - h3
+                                + t * (3.0 * (h1 - h2) + h3 - h0)))
+                }
+            };
+        }
+
+        self.position += self.ratio;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.history.rotate_left(self.channels);
+            let newest = self.history.len() - self.channels;
+            input(&mut self.history[newest..]);
+        }
+    }
+}
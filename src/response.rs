@@ -0,0 +1,85 @@
+// Measuring the frequency response of an effect, by playing a sine sweep
+// through it and comparing what comes out with what went in.
+
+use std::f64::consts::PI;
+
+use cpal::SampleRate;
+
+use crate::generator::Sweep;
+
+// The sweep starts an octave below the lowest frequency worth reporting, and
+// carries on to just short of Nyquist, so every measurement window falls
+// wholly inside it.
+const SWEEP_LOW_HZ: f64 = 10.0;
+const SWEEP_SECONDS: f64 = 10.0;
+const SWEEP_AMPLITUDE: f64 = 0.5;
+
+// Silence after the sweep for the effect's tail to ring out into.
+const TAIL_SECONDS: f64 = 0.5;
+
+// How much of the sweep each measurement looks at, in octaves either side
+// of its frequency.
+const WINDOW_OCTAVES: f64 = 1.0 / 6.0;
+
+// How many samples process is handed at a time.
+const BLOCK_FRAMES: usize = 512;
+
+// The standard third-octave band centres from 20Hz to 20kHz.
+pub const THIRD_OCTAVES: [f32; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+
+// Plays an exponential sine sweep through process, a block of mono samples
+// at a time, and returns the gain in dB at each of frequencies.  Each gain
+// comes from correlating the input and output with the sweep's own phase
+// over the stretch where the sweep passes that frequency, so harmonics a
+// nonlinear effect adds don't count towards it.  Frequencies too close to
+// the ends of the sweep to measure give NaN.
+pub fn measure_response<P>(mut process: P, sample_rate: SampleRate, frequencies: &[f32]) -> Vec<f32>
+where
+    P: FnMut(&mut [f32]),
+{
+    let SampleRate(sr) = sample_rate;
+    let sr = sr as f64;
+    let high_hz = 0.95 * sr / 2.0;
+
+    let sweep = Sweep::new(SWEEP_LOW_HZ, high_hz, SWEEP_SECONDS, sample_rate);
+    let input: Vec<f32> = (0..sweep.frames())
+        .map(|n| (SWEEP_AMPLITUDE * sweep.phase(n).sin()) as f32)
+        .chain(std::iter::repeat_n(0.0, (TAIL_SECONDS * sr) as usize))
+        .collect();
+    let mut output = input.clone();
+    for block in output.chunks_mut(BLOCK_FRAMES) {
+        process(block);
+    }
+
+    frequencies
+        .iter()
+        .map(|&freq| {
+            let freq = freq as f64;
+            let low = freq * 2f64.powf(-WINDOW_OCTAVES);
+            let high = freq * 2f64.powf(WINDOW_OCTAVES);
+            if low < SWEEP_LOW_HZ || high > high_hz {
+                return f32::NAN;
+            }
+
+            // The frames over which the sweep runs from low to high, under a
+            // Hann window.
+            let (start, end) = (sweep.frame_at(low), sweep.frame_at(high));
+            let correlate = |signal: &[f32]| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (n, sample) in signal.iter().enumerate().take(end).skip(start) {
+                    let window =
+                        0.5 - 0.5 * (2.0 * PI * (n - start) as f64 / (end - start) as f64).cos();
+                    let sample = window * *sample as f64;
+                    re += sample * sweep.phase(n).cos();
+                    im += sample * sweep.phase(n).sin();
+                }
+                re.hypot(im)
+            };
+            (20.0 * (correlate(&output) / correlate(&input)).log10()) as f32
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+// Deciding whether, and when, to try reopening an audio stream that has
+// failed, kept apart from the streams themselves so it can be checked
+// without any hardware.
+
+use std::time::Duration;
+
+use cpal::StreamError;
+
+// Exponential backoff: the first retry waits initial, each one after that
+// waits twice as long as the last up to max, and after attempts tries in all
+// it gives up.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, attempts: u32) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            attempts,
+        }
+    }
+
+    // How long to wait before retry number attempt, counting from 0, or None
+    // once every attempt has been used.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        let scale = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(scale).min(self.max))
+    }
+}
+
+// Whether reopening the stream could help.  A device that went away may come
+// back, but anything else the backend reports is taken to be permanent.
+pub fn is_recoverable(err: &StreamError) -> bool {
+    matches!(err, StreamError::DeviceNotAvailable)
+}
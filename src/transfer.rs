@@ -0,0 +1,18 @@
+// Moving samples between the audio callbacks and the ringbuffer that joins
+// them, a whole buffer at a time rather than sample by sample.
+
+use ringbuf::{Consumer, Producer};
+
+// Pushes as much of data as the ringbuffer has room for.  Returns how many
+// samples didn't fit and were dropped off the end.
+pub fn push_samples(producer: &mut Producer<f32>, data: &[f32]) -> usize {
+    data.len() - producer.push_slice(data)
+}
+
+// Fills data from the ringbuffer, padding with silence if it runs dry.
+// Returns how many samples were missing.
+pub fn pop_samples(consumer: &mut Consumer<f32>, data: &mut [f32]) -> usize {
+    let count = consumer.pop_slice(data);
+    data[count..].fill(0.0);
+    data.len() - count
+}
@@ -0,0 +1,458 @@
+// Runs the g2 binary itself, in --dry-run mode so no audio devices are needed.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use cpal::SampleRate;
+use g2::effects::{DistortFilter, DistortMode, Filter, HighPassFilter};
+
+// Writes a short mono test tone to a WAV file in the temp directory.
+fn write_tone(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("g2-{}-{}.wav", name, std::process::id()));
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for n in 0..4800 {
+        let phase = 2.0 * std::f32::consts::PI * 440.0 * n as f32 / 48000.0;
+        writer.write_sample(0.5 * phase.sin()).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+// Reads back the tone write_tone() wrote.
+fn read_tone(path: &PathBuf) -> Vec<f32> {
+    hound::WavReader::open(path)
+        .unwrap()
+        .into_samples::<f32>()
+        .map(Result::unwrap)
+        .collect()
+}
+
+// Runs a dry run over input and returns the raw f32 samples it printed.
+fn dry_run(input: &PathBuf, args: &[&str]) -> Vec<f32> {
+    let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .arg("--dry-run")
+        .arg("--input-file")
+        .arg(input)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+        .stdout
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn dry_run_reports_throughput_peak_and_bad_samples() {
+    let input = write_tone("dry-run");
+    let record = std::env::temp_dir().join(format!("g2-dry-run-out-{}.wav", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args(["--dry-run", "--effect", "distort", "--gain", "1"])
+        .args(["--saturation", "0.25", "--input-file"])
+        .arg(&input)
+        .arg("--record")
+        .arg(&record)
+        .output()
+        .unwrap();
+    let recorded = read_tone(&record);
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&record).unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Processed 4800 samples"), "{}", stderr);
+    assert!(stderr.contains("samples/sec"), "{}", stderr);
+    assert!(stderr.contains("Peak output level 0.2500"), "{}", stderr);
+    assert!(stderr.contains("No NaN or infinite samples"), "{}", stderr);
+
+    assert_eq!(recorded.len(), 4800);
+    assert!(recorded.iter().all(|sample| sample.abs() <= 0.25));
+}
+
+#[test]
+fn mute_input_outputs_silence() {
+    let input = write_tone("mute");
+    let normal = dry_run(&input, &["--passthrough"]);
+    let muted = dry_run(&input, &["--passthrough", "--mute-input"]);
+    std::fs::remove_file(&input).unwrap();
+
+    assert_eq!(normal.len(), 4800);
+    assert!(normal.iter().any(|sample| *sample != 0.0));
+    assert_eq!(muted.len(), 4800);
+    assert!(muted.iter().all(|sample| *sample == 0.0));
+}
+
+#[test]
+fn stdin_raw_passes_samples_through() {
+    let input: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.01).sin() * 0.5).collect();
+    let mut bytes: Vec<u8> = input.iter().flat_map(|s| s.to_le_bytes()).collect();
+    // A stray partial sample at the end of the stream is dropped.
+    bytes.extend_from_slice(&[0, 0]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args([
+            "--dry-run",
+            "--stdin-raw",
+            "--raw-channels",
+            "2",
+            "--passthrough",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&bytes).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let samples: Vec<f32> = output
+        .stdout
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    assert_eq!(samples, input);
+}
+
+#[test]
+fn chain_builds_each_stage_with_its_parameters() {
+    let input = write_tone("chain");
+    let output = dry_run(
+        &input,
+        &[
+            "--chain",
+            "highpass:cutoff=2000,q=0.5 distort:gain=8,saturation=0.3",
+        ],
+    );
+    let tone = read_tone(&input);
+    std::fs::remove_file(&input).unwrap();
+
+    let mut highpass = HighPassFilter::new(2000.0, 0.5, SampleRate(48000));
+    let mut distort = DistortFilter::new(8.0, 0.3, DistortMode::Hard);
+    let expected: Vec<f32> = tone
+        .iter()
+        .map(|sample| distort.process(highpass.process(*sample)))
+        .collect();
+
+    assert_eq!(output.len(), expected.len());
+    for (got, want) in output.iter().zip(&expected) {
+        assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+    }
+}
+
+#[test]
+fn chain_errors_name_the_bad_stage() {
+    let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args(["--dry-run", "--stdin-raw", "--chain", "reverb distort:gain"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"distort:gain\""), "{}", stderr);
+}
+
+#[test]
+fn ping_pong_rejects_bad_frames_and_decay() {
+    for (args, complaint) in [
+        (["--delay-frames", "0", "--decay", "0.5"], "--delay-frames"),
+        (["--delay-frames", "100", "--decay", "1.0"], "decay 1"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+            .args(["--dry-run", "--stdin-raw", "--effect", "pingpong"])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(complaint), "{}", stderr);
+    }
+}
+
+#[test]
+fn parallel_sums_its_branches() {
+    let input = write_tone("parallel");
+    let output = dry_run(
+        &input,
+        &["--parallel", "dry | level=-6 distort:gain=8,saturation=0.3"],
+    );
+    let tone = read_tone(&input);
+    std::fs::remove_file(&input).unwrap();
+
+    let level = 10f32.powf(-6.0 / 20.0);
+    let mut distort = DistortFilter::new(8.0, 0.3, DistortMode::Hard);
+    assert_eq!(output.len(), tone.len());
+    for (got, sample) in output.iter().zip(&tone) {
+        let want = sample + level * distort.process(*sample);
+        assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+    }
+}
+
+// Writes a stereo ramp in the given format and returns the path along with
+// the samples as g2 should decode them.
+fn write_format(name: &str, bits: u16, format: hound::SampleFormat) -> (PathBuf, Vec<f32>) {
+    let path = std::env::temp_dir().join(format!("g2-{}-{}.wav", name, std::process::id()));
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: bits,
+        sample_format: format,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    let full_scale = (1i64 << (bits - 1)) as f32;
+    let mut expected = Vec::new();
+    for n in 0..2000 {
+        let value = (n as f32 / 1000.0 - 1.0) * 0.9;
+        match format {
+            hound::SampleFormat::Float => {
+                writer.write_sample(value).unwrap();
+                expected.push(value);
+            }
+            hound::SampleFormat::Int => {
+                let quantized = (value * full_scale) as i32;
+                writer.write_sample(quantized).unwrap();
+                expected.push(quantized as f32 / full_scale);
+            }
+        }
+    }
+    writer.finalize().unwrap();
+    (path, expected)
+}
+
+#[test]
+fn supported_wav_formats_decode_to_the_same_levels() {
+    let formats = [
+        ("pcm16", 16, hound::SampleFormat::Int),
+        ("pcm24", 24, hound::SampleFormat::Int),
+        ("float32", 32, hound::SampleFormat::Float),
+    ];
+    for (name, bits, format) in formats {
+        let (input, expected) = write_format(name, bits, format);
+        let output = dry_run(&input, &["--passthrough"]);
+        std::fs::remove_file(&input).unwrap();
+
+        assert_eq!(output.len(), expected.len(), "{}", name);
+        for (got, want) in output.iter().zip(&expected) {
+            assert!((got - want).abs() < 1e-6, "{}: {} != {}", name, got, want);
+        }
+    }
+}
+
+// Writes a WAV header by hand, for formats hound won't write itself, followed
+// by one frame of silence.
+fn write_header(name: &str, format_tag: u16, bits: u16) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("g2-{}-{}.wav", name, std::process::id()));
+    let block_align = bits / 8;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + block_align as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&format_tag.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&48000u32.to_le_bytes());
+    wav.extend_from_slice(&(48000 * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&bits.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(block_align as u32).to_le_bytes());
+    wav.resize(wav.len() + block_align as usize, 0);
+    std::fs::write(&path, wav).unwrap();
+    path
+}
+
+#[test]
+fn unsupported_wav_formats_are_rejected() {
+    let cases = [
+        ("pcm64", 1, 64, "64-bit PCM"),
+        ("adpcm", 2, 8, "compressed"),
+    ];
+    for (name, format_tag, bits, message) in cases {
+        let path = write_header(name, format_tag, bits);
+        let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+            .args(["--dry-run", "--passthrough", "--input-file"])
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!output.status.success(), "{}", name);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn loop_repeats_the_file_without_a_gap() {
+    let input = write_tone("loop");
+    let tone = read_tone(&input);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args(["--dry-run", "--passthrough", "--loop", "--input-file"])
+        .arg(&input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut bytes = vec![0; tone.len() * 2 * 4];
+    child.stdout.take().unwrap().read_exact(&mut bytes).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&input).unwrap();
+
+    let output: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    let twice: Vec<f32> = tone.iter().chain(&tone).copied().collect();
+    assert_eq!(output, twice);
+}
+
+#[test]
+fn loop_resets_the_effect_each_pass() {
+    let input = write_tone("loop-reset");
+    let tone = read_tone(&input);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args(["--dry-run", "--effect", "delay", "--delay-frames", "1000"])
+        .args(["--loop", "--input-file"])
+        .arg(&input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut bytes = vec![0; tone.len() * 2 * 4];
+    child.stdout.take().unwrap().read_exact(&mut bytes).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&input).unwrap();
+
+    // Without the reset the second pass would start with the first pass's
+    // echoes still sounding.
+    let output: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    let (first, second) = output.split_at(tone.len());
+    assert_eq!(first, second);
+}
+
+#[test]
+fn clip_detect_abort_stops_on_sustained_clipping() {
+    let input = write_tone("clip");
+    let run = |gain: &str| {
+        Command::new(env!("CARGO_BIN_EXE_g2"))
+            .args(["--dry-run", "--effect", "distort", "--saturation", "1"])
+            .args(["--gain", gain, "--clip-detect-abort"])
+            // The tone is only a tenth of a second long.
+            .args(["--clip-abort-window", "0.05", "--input-file"])
+            .arg(&input)
+            .output()
+            .unwrap()
+    };
+    let clean = run("1");
+    let clipped = run("4");
+    std::fs::remove_file(&input).unwrap();
+
+    assert!(clean.status.success());
+    assert!(!clipped.status.success());
+    let stderr = String::from_utf8_lossy(&clipped.stderr);
+    assert!(stderr.contains("clipped"), "{}", stderr);
+}
+
+#[test]
+fn list_json_prints_an_array() {
+    let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .arg("--list-json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // Each device is one object; with no devices at all it's just [].
+    for device in json.as_array().unwrap() {
+        assert!(device["index"].is_u64(), "{}", device);
+        assert!(device["direction"].is_string(), "{}", device);
+        assert!(device["errors"].is_array(), "{}", device);
+    }
+}
+
+#[test]
+fn seeded_dither_records_the_same_file_every_time() {
+    let input = write_tone("dither");
+    let record = |name: &str, extra: &[&str]| {
+        let path = std::env::temp_dir().join(format!("g2-{}-{}.wav", name, std::process::id()));
+        let mut args = vec!["--passthrough", "--record-bits", "16", "--record"];
+        args.push(path.to_str().unwrap());
+        args.extend_from_slice(extra);
+        dry_run(&input, &args);
+        let samples: Vec<i16> = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        samples
+    };
+    let plain = record("dither-plain", &[]);
+    let first = record("dither-first", &["--dither", "--seed", "5"]);
+    let second = record("dither-second", &["--dither", "--seed", "5"]);
+    let tone = read_tone(&input);
+    std::fs::remove_file(&input).unwrap();
+
+    assert_eq!(first, second);
+    assert_ne!(first, plain);
+    for ((plain, dithered), sample) in plain.iter().zip(&first).zip(&tone) {
+        assert_eq!(*plain, (sample * 32768.0).round() as i16);
+        assert!((plain - dithered).abs() <= 1);
+    }
+}
+
+#[test]
+fn measure_response_prints_the_low_pass_curve() {
+    let output = Command::new(env!("CARGO_BIN_EXE_g2"))
+        .args([
+            "--measure-response",
+            "--effect",
+            "lowpass",
+            "--cutoff",
+            "1000",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("frequency_hz,gain_db"));
+    let rows: Vec<(f32, f32)> = lines
+        .map(|line| {
+            let (freq, gain) = line.split_once(',').unwrap();
+            (freq.parse().unwrap(), gain.parse().unwrap())
+        })
+        .collect();
+    assert_eq!(rows.len(), 31);
+    let gain_at = |freq: f32| rows.iter().find(|row| row.0 == freq).unwrap().1;
+    assert!(gain_at(100.0).abs() < 0.1);
+    assert!((gain_at(1000.0) + 3.0).abs() < 0.1);
+    assert!(gain_at(10000.0) < -35.0);
+}
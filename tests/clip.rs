@@ -0,0 +1,36 @@
+use g2::clip::ClipDetector;
+
+// A buffer of quiet samples with every nth one pushed past full scale.
+fn clipped_every(n: usize, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| if i % n == 0 { -1.2 } else { 0.3 })
+        .collect()
+}
+
+#[test]
+fn sustained_clipping_trips_the_detector() {
+    // 5% of samples clipping, against a 1% threshold.
+    let mut detector = ClipDetector::new(1000, 0.01);
+    assert!(detector.update(&clipped_every(20, 1000)));
+    assert!(detector.update(&[0.0; 1000]));
+}
+
+#[test]
+fn clipping_under_the_threshold_is_let_through() {
+    // 0.5% of samples clipping, against a 1% threshold.
+    let mut detector = ClipDetector::new(1000, 0.01);
+    assert!(!detector.update(&clipped_every(200, 10000)));
+}
+
+#[test]
+fn a_burst_spread_over_two_windows_is_judged_per_window() {
+    let mut detector = ClipDetector::new(1000, 0.01);
+    let mut data = vec![0.0; 2000];
+    data[990..1010].fill(1.0);
+    assert!(!detector.update(&data));
+
+    // Half a window isn't judged until the window fills.
+    let mut detector = ClipDetector::new(1000, 0.01);
+    assert!(!detector.update(&clipped_every(2, 500)));
+    assert!(detector.update(&[0.0; 500]));
+}
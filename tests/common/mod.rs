@@ -0,0 +1,26 @@
+// Helpers shared by the integration tests.
+
+use g2::effects::Filter;
+
+// Runs a single 1.0 followed by silence through filter, returning the first
+// n output samples.
+pub fn impulse_response(mut filter: Box<dyn Filter>, n: usize) -> Vec<f32> {
+    (0..n)
+        .map(|i| filter.process(if i == 0 { 1.0 } else { 0.0 }))
+        .collect()
+}
+
+// Runs a constant 1.0 through filter, returning the first n output samples.
+pub fn step_response(mut filter: Box<dyn Filter>, n: usize) -> Vec<f32> {
+    (0..n).map(|_| filter.process(1.0)).collect()
+}
+
+// Runs a unit sine at freq_hz through filter, returning the peak output once
+// the filter has had a second to settle.
+pub fn sine_gain(mut filter: Box<dyn Filter>, freq_hz: f32, sample_rate: u32) -> f32 {
+    let step = 2.0 * std::f32::consts::PI * freq_hz / sample_rate as f32;
+    (0..2 * sample_rate)
+        .map(|i| filter.process((step * i as f32).sin()))
+        .skip(sample_rate as usize)
+        .fold(0.0f32, |peak, s| peak.max(s.abs()))
+}
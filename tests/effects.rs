@@ -0,0 +1,1379 @@
+use cpal::SampleRate;
+use g2::effects::{
+    db_to_linear, linear_to_db, AllpassFilter, AutoPanner, AutoWah, BitCrusher, ChannelDelay,
+    Chorus, Compressor, Crossfade, DeEsser, DelayFilter, DistortFilter, DistortMode, Dither,
+    EnvelopeFollower, Filter, FilterChain, FlangeFilter, FrameFilter, Gain, GainRamp, InvertPhase,
+    LfoShape, Limiter, LowPassFilter, Multiband, NoiseGate, Overdrive, Oversampler, ParallelChain,
+    PerChannel, PingPongDelay, PitchShifter, RingModulator, SharedParam, Sidechain,
+    SidechainCompressor, StageTimer, StereoWidth, SwapChannels, TapeSaturation, Tremolo, Vibrato,
+    WetDry,
+};
+
+#[test]
+fn hard_distortion_clamps_to_saturation() {
+    let mut distort = DistortFilter::new(4.0, 0.5, DistortMode::Hard);
+    assert_eq!(distort.process(0.1), 0.4);
+    assert_eq!(distort.process(0.9), 0.5);
+    assert_eq!(distort.process(-0.9), -0.5);
+}
+
+#[test]
+fn foldback_reflects_off_saturation_instead_of_clamping() {
+    let mut folder = DistortFilter::new(1.0, 1.0, DistortMode::Foldback);
+    assert_eq!(folder.process(0.5), 0.5);
+    assert_eq!(folder.process(1.5), 0.5);
+    assert_eq!(folder.process(-1.5), -0.5);
+    // Past the other side it folds back again.
+    assert_eq!(folder.process(2.5), -0.5);
+    assert_eq!(folder.process(4.25), 0.25);
+
+    // fold_amount drives it further round.
+    let mut driven = DistortFilter::new(3.0, 1.0, DistortMode::Foldback);
+    assert_eq!(driven.process(0.5), 0.5);
+}
+
+#[test]
+fn soft_distortion_never_reaches_saturation() {
+    let mut distort = DistortFilter::new(100.0, 0.7, DistortMode::Soft);
+    let loud = distort.process(1.0);
+    assert!(loud > 0.69 && loud < 0.7);
+}
+
+#[test]
+fn soft_distortion_keeps_the_gain_on_quiet_signals() {
+    let mut soft = DistortFilter::new(3.0, 0.7, DistortMode::Soft);
+    let mut hard = DistortFilter::new(3.0, 0.7, DistortMode::Hard);
+    let quiet = soft.process(0.001);
+    assert!((quiet - 0.003).abs() < 1e-5, "{}", quiet);
+    assert!((quiet - hard.process(0.001)).abs() < 1e-5);
+}
+
+#[test]
+fn each_filter_shapes_an_impulse() {
+    let respond = |filter: &mut dyn Filter| -> Vec<f32> {
+        impulse(5).iter().map(|x| filter.process(*x)).collect()
+    };
+
+    let mut distort = DistortFilter::new(2.0, 0.5, DistortMode::Hard);
+    assert_eq!(respond(&mut distort), [0.5, 0.0, 0.0, 0.0, 0.0]);
+
+    let mut delay = DelayFilter::new(2, 0.5, 0.0).unwrap();
+    assert_eq!(respond(&mut delay), [1.0, 0.0, 0.5, 0.0, 0.25]);
+
+    // With no sweep the flange sits at a one-frame delay, so each sample
+    // feeds straight back into the next.
+    let mut flange = FlangeFilter::new(10, SampleRate(48000), 1.0, 0.0, 0.5, LfoShape::Sine);
+    assert_eq!(respond(&mut flange), [1.0, 0.5, 0.25, 0.125, 0.0625]);
+}
+
+#[test]
+fn delay_echoes_after_delay_frames() {
+    let mut delay = DelayFilter::new(100, 0.5, 0.0).unwrap();
+    let output: Vec<f32> = (0..201)
+        .map(|i| delay.process(if i == 0 { 1.0 } else { 0.0 }))
+        .collect();
+
+    assert_eq!(output[0], 1.0);
+    assert!(output[1..100].iter().all(|s| *s == 0.0));
+    assert_eq!(output[100], 0.5);
+    assert_eq!(output[200], 0.25);
+}
+
+#[test]
+fn delay_rejects_runaway_decay() {
+    assert!(DelayFilter::new(100, 1.0, 0.0).is_err());
+    assert!(DelayFilter::new(100, -0.1, 0.0).is_err());
+}
+
+#[test]
+fn dry_mix_passes_input_through() {
+    let distort = DistortFilter::new(10.0, 0.1, DistortMode::Hard);
+    let mut wet_dry = WetDry::new(distort, 0.0);
+    assert_eq!(wet_dry.process(0.5), 0.5);
+}
+
+#[test]
+fn mix_blends_dry_and_wet_and_is_clamped() {
+    let distort = || DistortFilter::new(4.0, 1.0, DistortMode::Hard);
+    let mix = |mix: f32| WetDry::new(distort(), mix).process(0.1);
+
+    assert_eq!(mix(0.0), 0.1);
+    assert!((mix(1.0) - 0.4).abs() < 1e-6);
+    assert!((mix(0.25) - (0.75 * 0.1 + 0.25 * 0.4)).abs() < 1e-6);
+    assert_eq!(mix(-1.0), mix(0.0));
+    assert_eq!(mix(2.0), mix(1.0));
+}
+
+#[test]
+fn chain_runs_filters_in_order() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(DistortFilter::new(2.0, 1.0, DistortMode::Hard)));
+    chain.push(Box::new(DistortFilter::new(1.0, 0.5, DistortMode::Hard)));
+    assert_eq!(chain.process(0.2), 0.4);
+    assert_eq!(chain.process(0.4), 0.5);
+}
+
+#[test]
+fn identity_then_double_doubles_the_input() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(DistortFilter::new(1.0, 10.0, DistortMode::Hard)));
+    chain.push(Box::new(DistortFilter::new(2.0, 10.0, DistortMode::Hard)));
+    for x in [-0.5, -0.1, 0.0, 0.25, 1.0] {
+        assert!((chain.process(x) - 2.0 * x).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn swapping_stages_changes_the_output() {
+    let run = |delay_first: bool| -> Vec<f32> {
+        let delay = Box::new(DelayFilter::new(2, 0.5, 0.0).unwrap());
+        let distort = Box::new(DistortFilter::new(2.0, 0.5, DistortMode::Hard));
+        let mut chain = FilterChain::new();
+        if delay_first {
+            chain.push(delay);
+            chain.push(distort);
+        } else {
+            chain.push(distort);
+            chain.push(delay);
+        }
+        impulse(5).iter().map(|x| chain.process(*x)).collect()
+    };
+
+    // Clipping after the delay flattens the echoes up to the ceiling;
+    // clipping first leaves them decaying from the clipped impulse.
+    assert_eq!(run(true), [0.5, 0.0, 0.5, 0.0, 0.5]);
+    assert_eq!(run(false), [0.5, 0.0, 0.25, 0.0, 0.125]);
+}
+
+#[test]
+fn solo_runs_only_the_chosen_stage() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(DistortFilter::new(2.0, 1.0, DistortMode::Hard)));
+    chain.push(Box::new(DistortFilter::new(3.0, 1.0, DistortMode::Hard)));
+
+    chain.solo(Some(1));
+    assert!((chain.process(0.1) - 0.3).abs() < 1e-6);
+    chain.solo(Some(0));
+    assert!((chain.process(0.1) - 0.2).abs() < 1e-6);
+    chain.solo(None);
+    assert!((chain.process(0.1) - 0.6).abs() < 1e-6);
+}
+
+#[test]
+fn bypassed_stages_pass_their_input_through() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(Gain::new(-6.0)));
+    chain.push(Box::new(DistortFilter::new(2.0, 1.0, DistortMode::Hard)));
+    assert!((chain.process(0.2) - 0.2 * 0.501 * 2.0).abs() < 1e-3);
+
+    chain.set_enabled(0, false);
+    assert!((chain.process(0.2) - 0.4).abs() < 1e-6);
+    chain.set_enabled(0, true);
+    assert!((chain.process(0.2) - 0.2 * 0.501 * 2.0).abs() < 1e-3);
+}
+
+#[test]
+fn is_running_follows_the_switches_and_the_solo() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(Gain::new(0.0)));
+    chain.push(Box::new(Gain::new(0.0)));
+    assert!(chain.is_running(0) && chain.is_running(1));
+    assert!(!chain.is_running(2));
+
+    chain.set_enabled(0, false);
+    assert!(!chain.is_running(0) && chain.is_running(1));
+    chain.set_enabled(0, true);
+    chain.solo(Some(0));
+    assert!(chain.is_running(0) && !chain.is_running(1));
+}
+
+#[test]
+fn shared_switches_bypass_every_channel() {
+    let build = || {
+        let mut chain = FilterChain::new();
+        chain.push(Box::new(Gain::new(6.0)));
+        chain
+    };
+    let (mut left, mut right) = (build(), build());
+    let switches = left.switches();
+    right.share_switches(&switches);
+
+    switches[0].set(false);
+    assert_eq!(left.process(0.25), 0.25);
+    assert_eq!(right.process(0.25), 0.25);
+}
+
+#[test]
+fn per_channel_filters_keep_channels_apart() {
+    let delays = (0..2)
+        .map(|_| DelayFilter::new(1, 0.5, 0.0).unwrap())
+        .collect();
+    let mut per_channel = PerChannel::new(delays);
+
+    let mut frame = [1.0, 0.0];
+    per_channel.process_frame(&mut frame);
+    frame = [0.0, 0.0];
+    per_channel.process_frame(&mut frame);
+    assert_eq!(frame, [0.5, 0.0]);
+}
+
+#[test]
+fn each_channel_gets_its_own_delay() {
+    let mut per_channel = PerChannel::new(vec![
+        DelayFilter::new(1, 0.5, 0.0).unwrap(),
+        DelayFilter::new(2, 0.5, 0.0).unwrap(),
+    ]);
+
+    let mut block = impulse(4)
+        .iter()
+        .flat_map(|x| [*x, *x])
+        .collect::<Vec<f32>>();
+    for frame in block.chunks_mut(2) {
+        per_channel.process_frame(frame);
+    }
+    assert_eq!(block, [1.0, 1.0, 0.5, 0.0, 0.25, 0.5, 0.125, 0.0]);
+}
+
+#[test]
+fn per_channel_adapter_keeps_the_frame_width() {
+    let gains = [1.0, 2.0, 0.5]
+        .iter()
+        .map(|gain| DistortFilter::new(*gain, 10.0, DistortMode::Hard))
+        .collect();
+    let mut per_channel = PerChannel::new(gains);
+
+    let mut frame = [0.5, 0.5, 0.5];
+    per_channel.process_frame(&mut frame);
+    assert_eq!(frame, [0.5, 1.0, 0.25]);
+
+    // Channels past the filters are left alone.
+    let mut wide = [0.5; 4];
+    per_channel.process_frame(&mut wide);
+    assert_eq!(wide, [0.5, 1.0, 0.25, 0.5]);
+}
+
+#[test]
+fn ping_pong_bounces_between_channels() {
+    let mut ping_pong = PingPongDelay::new(10, 0.5).unwrap();
+    let mut left_hits = Vec::new();
+    let mut right_hits = Vec::new();
+    for i in 0..40 {
+        let mut frame = if i == 0 { [1.0, 0.0] } else { [0.0, 0.0] };
+        ping_pong.process_frame(&mut frame);
+        if i > 0 && frame[0] != 0.0 {
+            left_hits.push(i);
+        }
+        if frame[1] != 0.0 {
+            right_hits.push(i);
+        }
+    }
+
+    assert_eq!(&right_hits[..2], &[10, 30]);
+    assert_eq!(&left_hits[..1], &[20]);
+}
+
+#[test]
+fn ping_pong_first_echo_lands_on_the_other_side() {
+    for (start, echo) in [(0, 1), (1, 0)] {
+        let mut ping_pong = PingPongDelay::new(5, 0.5).unwrap();
+        let mut frames = Vec::new();
+        for i in 0..6 {
+            let mut frame = [0.0, 0.0];
+            if i == 0 {
+                frame[start] = 1.0;
+            }
+            ping_pong.process_frame(&mut frame);
+            frames.push(frame);
+        }
+
+        assert_eq!(frames[5][echo], 0.5);
+        assert_eq!(frames[5][start], 0.0);
+        assert!(frames[1..5].iter().all(|frame| *frame == [0.0, 0.0]));
+    }
+}
+
+#[test]
+fn ping_pong_rejects_runaway_decay() {
+    assert!(PingPongDelay::new(10, 1.0).is_err());
+    assert!(PingPongDelay::new(10, -0.1).is_err());
+}
+
+#[test]
+fn zero_width_collapses_to_mid() {
+    let mut width = StereoWidth::new(0.0);
+    let mut frame = [0.75, 0.25];
+    width.process_frame(&mut frame);
+    assert_eq!(frame, [0.5, 0.5]);
+}
+
+#[test]
+fn unit_width_is_identity() {
+    let mut width = StereoWidth::new(1.0);
+    let mut frame = [0.75, -0.25];
+    width.process_frame(&mut frame);
+    assert_eq!(frame, [0.75, -0.25]);
+
+    let mut mono = [0.3];
+    StereoWidth::new(2.0).process_frame(&mut mono);
+    assert_eq!(mono, [0.3]);
+}
+
+#[test]
+fn swap_channels_swaps_left_and_right() {
+    let mut frame = [0.25, -0.5];
+    SwapChannels.process_frame(&mut frame);
+    assert_eq!(frame, [-0.5, 0.25]);
+
+    let mut mono = [0.25];
+    SwapChannels.process_frame(&mut mono);
+    assert_eq!(mono, [0.25]);
+}
+
+#[test]
+fn channel_delay_moves_only_its_own_channel() {
+    // An impulse on both channels of a stereo signal, in the first frame.
+    let mut frames = vec![0.0; 2 * 8];
+    frames[0] = 1.0;
+    frames[1] = 1.0;
+    ChannelDelay::new(&[0, 3]).process_block(&mut frames, 2);
+
+    let channel = |c: usize| -> Vec<f32> { frames.iter().skip(c).step_by(2).copied().collect() };
+    assert_eq!(channel(0), [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(channel(1), [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn auto_panner_keeps_constant_power_across_the_sweep() {
+    // One cycle a second at 1kHz: centre at frame 0, hard right a quarter of
+    // the way through, back to centre at half way, hard left at three
+    // quarters.
+    let mut panner = AutoPanner::new(1.0, 1.0, SampleRate(1000));
+    let gains: Vec<[f32; 2]> = (0..1000)
+        .map(|_| {
+            let mut frame = [1.0, 1.0];
+            panner.process_frame(&mut frame);
+            frame
+        })
+        .collect();
+
+    let half_power = 0.5f32.sqrt();
+    for frame in [0, 500] {
+        assert!(
+            (gains[frame][0] - half_power).abs() < 1e-3,
+            "{:?}",
+            gains[frame]
+        );
+        assert!(
+            (gains[frame][1] - half_power).abs() < 1e-3,
+            "{:?}",
+            gains[frame]
+        );
+    }
+    assert!(gains[250][0].abs() < 1e-3 && (gains[250][1] - 1.0).abs() < 1e-3);
+    assert!((gains[750][0] - 1.0).abs() < 1e-3 && gains[750][1].abs() < 1e-3);
+    for [left, right] in gains {
+        assert!((left * left + right * right - 1.0).abs() < 1e-3);
+    }
+
+    let mut mono = [0.25];
+    panner.process_frame(&mut mono);
+    assert_eq!(mono, [0.25]);
+}
+
+#[test]
+fn filters_build_at_any_sample_rate() {
+    for rate in [8000, 44100, 192000] {
+        let mut lowpass = g2::effects::LowPassFilter::new(1000.0, 0.707, SampleRate(rate));
+        assert!(lowpass.process(1.0).is_finite());
+    }
+}
+
+#[test]
+fn shared_gain_changes_while_running() {
+    let gain = SharedParam::new(2.0);
+    let mut distort = DistortFilter::shared(gain.clone(), 1.0, DistortMode::Hard);
+    assert_eq!(distort.process(0.25), 0.5);
+
+    gain.set(4.0);
+    assert_eq!(distort.process(0.25), 1.0);
+}
+
+#[test]
+fn limiter_holds_peaks_at_the_ceiling() {
+    let mut limiter = Limiter::new(0.5, SampleRate(48000));
+    let lookahead = limiter.lookahead_frames();
+    let input: Vec<f32> = (0..2000)
+        .map(|i| if (500..1500).contains(&i) { 2.0 } else { 0.25 })
+        .collect();
+    let output: Vec<f32> = input.iter().map(|s| limiter.process(*s)).collect();
+
+    assert!(output.iter().all(|s| s.abs() <= 0.5 + 1e-6));
+    assert!(output[..lookahead].iter().all(|s| *s == 0.0));
+    assert!((output[lookahead + 1000] - 0.5).abs() < 1e-6);
+    assert!((output[lookahead + 100] - 0.25).abs() < 1e-6);
+}
+
+// Finds the strongest frequency in samples, searching between low and high
+// in 1 Hz steps.
+fn dominant_frequency(samples: &[f32], sample_rate: f32, low: u32, high: u32) -> f32 {
+    let power = |freq: f32| {
+        let w = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, s)| {
+                (re + s * (w * n as f32).cos(), im + s * (w * n as f32).sin())
+            });
+        re * re + im * im
+    };
+    (low..=high)
+        .map(|f| f as f32)
+        .max_by(|a, b| power(*a).total_cmp(&power(*b)))
+        .unwrap()
+}
+
+#[test]
+fn pitch_shifter_moves_a_sine_by_the_right_ratio() {
+    let sample_rate = 48000.0;
+    for semitones in [-5.0f32, 7.0] {
+        let mut shifter = PitchShifter::new(semitones, SampleRate(48000));
+        let output: Vec<f32> = (0..24000)
+            .map(|n| {
+                let t = n as f32 / sample_rate;
+                shifter.process((2.0 * std::f32::consts::PI * 440.0 * t).sin())
+            })
+            .collect();
+
+        let expected = 440.0 * 2.0f32.powf(semitones / 12.0);
+        let found = dominant_frequency(&output[4800..], sample_rate, 200, 900);
+        assert!(
+            (found - expected).abs() < expected * 0.03,
+            "{} semitones: expected {} Hz, found {} Hz",
+            semitones,
+            expected,
+            found
+        );
+    }
+}
+
+#[test]
+fn allpass_keeps_amplitude_but_shifts_phase() {
+    let mut allpass = AllpassFilter::new(37, 0.6);
+    let input: Vec<f32> = (0..20000)
+        .map(|n| (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin())
+        .collect();
+    let output: Vec<f32> = input.iter().map(|s| allpass.process(*s)).collect();
+
+    // Skip past the start, where the feedback is still building up.
+    let settled = 10000..;
+    let peak = output[settled.clone()]
+        .iter()
+        .fold(0.0f32, |a, b| a.max(b.abs()));
+    assert!((peak - 1.0).abs() < 0.01, "peak {}", peak);
+
+    let difference = input[settled.clone()]
+        .iter()
+        .zip(&output[settled])
+        .fold(0.0f32, |a, (i, o)| a.max((i - o).abs()));
+    assert!(difference > 0.1);
+}
+
+#[test]
+fn gain_ramp_fades_in_and_out() {
+    // 10ms at 48kHz is 480 frames.
+    let mut ramp = GainRamp::new(10.0, SampleRate(48000));
+    let fade_in: Vec<f32> = (0..480).map(|_| ramp.next_gain()).collect();
+    assert!(fade_in.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(fade_in[0] > 0.0 && fade_in[0] < 0.01);
+    assert!((fade_in[479] - 1.0).abs() < 1e-4);
+    assert_eq!(ramp.next_gain(), 1.0);
+
+    ramp.fade_out();
+    let fade_out: Vec<f32> = (0..480).map(|_| ramp.next_gain()).collect();
+    assert!(fade_out.windows(2).all(|pair| pair[0] > pair[1]));
+    assert!(fade_out[479].abs() < 1e-4);
+    assert_eq!(ramp.next_gain(), 0.0);
+}
+
+#[test]
+fn zero_length_gain_ramp_starts_at_unity() {
+    let mut ramp = GainRamp::new(0.0, SampleRate(48000));
+    assert_eq!(ramp.next_gain(), 1.0);
+}
+
+#[test]
+fn envelope_follower_uses_its_time_constants() {
+    // At 48kHz, 10ms is 480 samples and 50ms is 2400.
+    let mut follower = EnvelopeFollower::new(10.0, 50.0, SampleRate(48000));
+    let one_over_e = (-1.0f32).exp();
+
+    let rise: Vec<f32> = (0..4800).map(|_| follower.process(-0.5)).collect();
+    assert!(rise.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(
+        (rise[479] - 0.5 * (1.0 - one_over_e)).abs() < 1e-3,
+        "{}",
+        rise[479]
+    );
+    assert!((rise[4799] - 0.5).abs() < 1e-3);
+
+    let peak = rise[4799];
+    let fall: Vec<f32> = (0..2400).map(|_| follower.process(0.0)).collect();
+    assert!(fall.windows(2).all(|pair| pair[0] > pair[1]));
+    assert!(
+        (fall[2399] - peak * one_over_e).abs() < 1e-3,
+        "{}",
+        fall[2399]
+    );
+}
+
+#[test]
+fn deesser_turns_down_sibilance_but_not_low_tones() {
+    let sine = |freq: f32| {
+        (0..9600).map(move |n| 0.5 * (2.0 * std::f32::consts::PI * freq * n as f32 / 48000.0).sin())
+    };
+    let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+
+    let mut deesser = DeEsser::new(6000.0, 0.05, 4.0, SampleRate(48000));
+    let hiss: Vec<f32> = sine(6000.0).map(|s| deesser.process(s)).collect();
+    assert!(
+        peak(&hiss[4800..]) < 0.25,
+        "hiss peak {}",
+        peak(&hiss[4800..])
+    );
+
+    let mut deesser = DeEsser::new(6000.0, 0.05, 4.0, SampleRate(48000));
+    let tone: Vec<f32> = sine(200.0).map(|s| deesser.process(s)).collect();
+    assert!(
+        peak(&tone[4800..]) > 0.49,
+        "tone peak {}",
+        peak(&tone[4800..])
+    );
+}
+
+#[test]
+fn distortion_auto_gain_keeps_the_input_level() {
+    let rms = |samples: &[f32]| {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    };
+    for amplitude in [0.05, 0.5] {
+        let tone: Vec<f32> = (0..96000)
+            .map(|n| amplitude * (2.0 * std::f32::consts::PI * 220.0 * n as f32 / 48000.0).sin())
+            .collect();
+
+        let mut plain = DistortFilter::new(20.0, 0.8, DistortMode::Soft);
+        let mut levelled = DistortFilter::new(20.0, 0.8, DistortMode::Soft);
+        levelled.enable_auto_gain(SampleRate(48000));
+        let plain: Vec<f32> = tone.iter().map(|s| plain.process(*s)).collect();
+        let levelled: Vec<f32> = tone.iter().map(|s| levelled.process(*s)).collect();
+
+        // Skip the first second while the level estimates settle.
+        let input = rms(&tone[48000..]);
+        assert!(rms(&plain[48000..]) > 1.2 * input);
+        let output = rms(&levelled[48000..]);
+        assert!(
+            (output / input - 1.0).abs() < 0.1,
+            "{} in, {} out",
+            input,
+            output
+        );
+    }
+}
+
+#[test]
+fn crossfade_blends_linearly_between_filters() {
+    // 10ms at 1kHz is ten samples.
+    let mut crossfade = Crossfade::new(Box::new(Gain::new(0.0)), 10.0, SampleRate(1000));
+    assert_eq!(crossfade.process(1.0), 1.0);
+
+    crossfade.switch_to(Box::new(Gain::new(-120.0)));
+    assert!(crossfade.is_switching());
+    let faded: Vec<f32> = (0..12).map(|_| crossfade.process(1.0)).collect();
+    for (n, sample) in faded.iter().take(10).enumerate() {
+        let expected = 1.0 - (n + 1) as f32 / 10.0;
+        assert!((sample - expected).abs() < 1e-5, "{}: {}", n, sample);
+    }
+    assert!(!crossfade.is_switching());
+    assert!(faded[10].abs() < 1e-5);
+}
+
+#[test]
+fn crossfade_switch_has_no_jump() {
+    let sine = |n: usize| 0.8 * (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 48000.0).sin();
+    let clean = || DistortFilter::new(1.0, 1.0, DistortMode::Hard);
+    let dirty = || DistortFilter::new(8.0, 0.3, DistortMode::Hard);
+    let mut crossfade = Crossfade::new(Box::new(clean()), 5.0, SampleRate(48000));
+    let (mut old, mut new) = (clean(), dirty());
+
+    // The switch lands where the two filters disagree by almost 0.5, which a
+    // hard cut would jump straight across.
+    let switch = 27;
+    for n in 0..480 {
+        if n == switch {
+            crossfade.switch_to(Box::new(dirty()));
+        }
+        let (sample, old, new) = (
+            crossfade.process(sine(n)),
+            old.process(sine(n)),
+            new.process(sine(n)),
+        );
+        if n < switch {
+            assert_eq!(sample, old);
+        } else if n == switch {
+            assert!((old - new).abs() > 0.4);
+            assert!(
+                (sample - old).abs() < 0.01,
+                "{} after the switch",
+                sample - old
+            );
+        } else if n >= switch + 240 {
+            assert_eq!(sample, new);
+        }
+    }
+}
+
+#[test]
+fn chain_fades_stages_when_toggled() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(Gain::new(-120.0)));
+    chain.set_fade(4.0, SampleRate(1000));
+
+    chain.set_enabled(0, false);
+    let faded: Vec<f32> = (0..5).map(|_| chain.process(1.0)).collect();
+    for (n, sample) in faded.iter().enumerate() {
+        let expected = ((n + 1) as f32 / 4.0).min(1.0);
+        assert!((sample - expected).abs() < 1e-5, "{}: {}", n, sample);
+    }
+}
+
+#[test]
+fn tape_transfer_curve_rises_and_compresses() {
+    // Holding the input steady lets the hysteresis, tone filter and flutter
+    // all settle, leaving just the static curve.
+    let settled = |input: f32| {
+        let mut tape = TapeSaturation::new(3.0, 0.5, 0.3, SampleRate(48000));
+        (0..4800).map(|_| tape.process(input)).last().unwrap()
+    };
+    let inputs: Vec<f32> = (-40..=40).map(|n| n as f32 / 10.0).collect();
+    let outputs: Vec<f32> = inputs.iter().map(|x| settled(*x)).collect();
+
+    for pair in outputs.windows(2) {
+        assert!(pair[1] > pair[0], "{} then {}", pair[0], pair[1]);
+    }
+    assert!(outputs.iter().all(|y| y.abs() < 1.0));
+
+    // Quiet inputs get the full drive; loud ones much less.
+    assert!((settled(1e-4) / 1e-4 - 3.0).abs() < 0.01);
+    assert!(settled(4.0) / 4.0 < 0.25);
+}
+
+#[test]
+fn overdrive_curve_is_monotonic_bounded_and_soft() {
+    let mut overdrive = Overdrive::new(2.0, 0.8);
+    let inputs: Vec<f32> = (-300..=300).map(|n| n as f32 / 100.0).collect();
+    let outputs: Vec<f32> = inputs.iter().map(|x| overdrive.process(*x)).collect();
+
+    for pair in outputs.windows(2) {
+        assert!(pair[1] >= pair[0], "{} then {}", pair[0], pair[1]);
+    }
+    assert!(outputs.iter().all(|y| y.abs() <= 0.8 * 1.5));
+
+    // Quiet inputs see the same slope either side of zero; loud ones get
+    // far less of it.
+    let mut slope = |x: f32| overdrive.process(x) / x;
+    let quiet = slope(1e-3);
+    assert!((slope(-1e-3) - quiet).abs() < quiet * 0.01);
+    assert!(slope(0.01) > quiet * 0.95);
+    assert!(slope(1.0) < quiet * 0.5);
+}
+
+#[test]
+fn gate_hysteresis_rides_over_a_hovering_level() {
+    // Steps between 0.012 and 0.018 every 40ms: the level crosses 0.015 on
+    // every step but never leaves the band between 0.01 and 0.02.
+    let hover = |n: usize| {
+        if (n / 1920).is_multiple_of(2) {
+            0.012
+        } else {
+            0.018
+        }
+    };
+    let flips = |mut gate: NoiseGate| {
+        // Open the gate first with something loud.
+        for _ in 0..480 {
+            gate.process(0.05);
+        }
+        let mut flips = 0;
+        let mut was_open = gate.is_open();
+        for n in 0..96000 {
+            gate.process(hover(n));
+            if gate.is_open() != was_open {
+                flips += 1;
+                was_open = gate.is_open();
+            }
+        }
+        (flips, was_open)
+    };
+
+    let single = NoiseGate::new(0.015, 0.015, 1.0, 50.0, SampleRate(48000));
+    assert!(flips(single).0 > 40);
+
+    let hysteresis = NoiseGate::new(0.02, 0.01, 1.0, 50.0, SampleRate(48000));
+    assert_eq!(flips(hysteresis), (0, true));
+}
+
+#[test]
+fn gate_silences_quiet_signals_and_passes_loud_ones() {
+    let run = |amplitude: f32| {
+        let mut gate = NoiseGate::new(0.02, 0.01, 1.0, 50.0, SampleRate(48000));
+        let input: Vec<f32> = (0..48000)
+            .map(|n| amplitude * (n as f32 * 0.05).sin())
+            .collect();
+        let output: Vec<f32> = input.iter().map(|x| gate.process(*x)).collect();
+        (input, output)
+    };
+
+    let (_, quiet) = run(0.005);
+    assert!(quiet.iter().all(|s| *s == 0.0));
+
+    // Past the attack the loud signal comes through untouched.
+    let (input, loud) = run(0.1);
+    for (x, y) in input[4800..].iter().zip(&loud[4800..]) {
+        assert!((x - y).abs() < 1e-6, "{} became {}", x, y);
+    }
+}
+
+#[test]
+fn expander_turns_quiet_signals_down_by_the_ratio() {
+    // A steady level 12dB under the threshold, long enough to settle.
+    let settle = |ratio: Option<f32>| {
+        let mut gate = NoiseGate::new(0.02, 0.01, 1.0, 50.0, SampleRate(48000));
+        if let Some(ratio) = ratio {
+            gate.enable_expansion(ratio);
+        }
+        (0..48000).map(|_| gate.process(0.005)).last().unwrap() / 0.005
+    };
+
+    assert_eq!(settle(None), 0.0);
+    // Each dB under the threshold costs ratio - 1 dB of gain.
+    assert!(
+        (settle(Some(2.0)) - 0.25).abs() < 1e-3,
+        "{}",
+        settle(Some(2.0))
+    );
+    assert!(
+        (settle(Some(3.0)) - 0.0625).abs() < 1e-3,
+        "{}",
+        settle(Some(3.0))
+    );
+    assert!((settle(Some(1.0)) - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn loud_key_ducks_a_steady_signal() {
+    let compressor = || Compressor::new(-20.0, 4.0, 1.0, 50.0, 0.0, SampleRate(48000));
+
+    // The key is silent for the first 100ms, then loud.
+    let key = |n: usize| if n < 4800 { 0.0 } else { 0.5 };
+    let mut keyed = compressor();
+    let main: Vec<f32> = (0..9600)
+        .map(|n| keyed.process_keyed(0.05, key(n)))
+        .collect();
+    assert!((main[4799] - 0.05).abs() < 1e-6);
+    // 0.5 is 14dB over the threshold, so the gain comes down by 10.5dB.
+    assert!((main[9599] - 0.05 * db_to_linear(-10.5)).abs() < 1e-3);
+
+    // The same through the frame filter, keyed from channel 0.
+    let mut ducker = SidechainCompressor::new(compressor(), Sidechain::Channel(0));
+    let mut frame = [0.0, 0.0];
+    for n in 0..9600 {
+        frame = [key(n), 0.05];
+        ducker.process_frame(&mut frame);
+    }
+    assert_eq!(frame[0], 0.5);
+    assert!((frame[1] - main[9599]).abs() < 1e-6);
+
+    // And from a recording, which goes quiet once it runs out.
+    let mut ducker = SidechainCompressor::new(compressor(), Sidechain::Samples(vec![0.5; 4800]));
+    let mut frame = [0.0];
+    for n in 0..9600 {
+        frame = [0.05];
+        ducker.process_frame(&mut frame);
+        if n == 4799 {
+            assert!(frame[0] < 0.02);
+        }
+    }
+    assert!((frame[0] - 0.05).abs() < 1e-3);
+}
+
+#[test]
+fn compressor_reduces_by_the_ratio_over_the_threshold() {
+    let settle = |input: f32| {
+        let mut compressor = Compressor::new(-20.0, 4.0, 1.0, 50.0, 0.0, SampleRate(48000));
+        (0..48000)
+            .map(|_| compressor.process(input))
+            .last()
+            .unwrap()
+    };
+
+    // 12dB over the threshold comes out 3dB over it.
+    let output_db = linear_to_db(settle(db_to_linear(-8.0)));
+    assert!((output_db + 17.0).abs() < 0.1, "{}", output_db);
+
+    // Under the threshold nothing changes.
+    let mut compressor = Compressor::new(-20.0, 4.0, 1.0, 50.0, 0.0, SampleRate(48000));
+    for n in 0..48000 {
+        let x = db_to_linear(-30.0) * (n as f32 * 0.05).sin();
+        assert!((compressor.process(x) - x).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn block_processing_matches_sample_processing() {
+    let input: Vec<f32> = (0..2000).map(|n| (n as f32 * 0.05).sin() * 0.9).collect();
+    let build = || {
+        let mut chain = FilterChain::new();
+        chain.push(Box::new(DistortFilter::new(3.0, 0.6, DistortMode::Soft)));
+        chain.push(Box::new(DelayFilter::new(37, 0.5, 0.2).unwrap()));
+        chain.push(Box::new(DistortFilter::new(2.0, 0.8, DistortMode::Hard)));
+        chain.set_fade(2.0, SampleRate(48000));
+        let switches = chain.switches();
+        let filter = PerChannel::new(vec![
+            WetDry::new(chain, 0.7),
+            WetDry::new(FilterChain::new(), 0.7),
+        ]);
+        (filter, switches)
+    };
+
+    let (mut by_frame, frame_switches) = build();
+    let (mut by_block, block_switches) = build();
+    let mut expected = input.clone();
+    let mut output = input.clone();
+    let blocks = expected.chunks_mut(200).zip(output.chunks_mut(200));
+    for (n, (frames, block)) in blocks.enumerate() {
+        // Bypass a stage partway through, so a block catches it mid-fade.
+        if n == 3 {
+            frame_switches[1].set(false);
+            block_switches[1].set(false);
+        }
+        for frame in frames.chunks_mut(2) {
+            by_frame.process_frame(frame);
+        }
+        by_block.process_block(block, 2);
+    }
+    for (got, want) in output.iter().zip(&expected) {
+        assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+    }
+}
+
+#[test]
+fn distort_block_matches_process_bit_for_bit() {
+    // Odd length, so the SIMD path has a remainder to hand back, with a NaN
+    // and both zeros where the SIMD path will see them.
+    let mut input: Vec<f32> = (0..1027).map(|n| (n as f32 * 0.37).sin() * 1.5).collect();
+    input[1..4].copy_from_slice(&[f32::NAN, 0.0, -0.0]);
+    for mode in [DistortMode::Hard, DistortMode::Soft, DistortMode::Foldback] {
+        for saturation in [0.7, 0.0] {
+            let mut scalar = DistortFilter::new(3.0, saturation, mode);
+            let expected: Vec<u32> = input.iter().map(|s| scalar.process(*s).to_bits()).collect();
+
+            let mut block = input.clone();
+            DistortFilter::new(3.0, saturation, mode).process_block(&mut block);
+            let got: Vec<u32> = block.iter().map(|s| s.to_bits()).collect();
+            let first_mismatch = got.iter().zip(&expected).position(|(a, b)| a != b);
+            assert_eq!(first_mismatch, None, "{:?} at {}", mode, saturation);
+        }
+    }
+}
+
+#[test]
+fn chain_timers_charge_each_stage() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(Gain::new(0.0)));
+    chain.push(Box::new(PitchShifter::new(5.0, SampleRate(48000))));
+    let timers = [StageTimer::new(), StageTimer::new()];
+    chain.set_timers(&timers);
+
+    let mut block = vec![0.1; 48000];
+    chain.process_block(&mut block);
+    assert!(timers[0].total() > std::time::Duration::ZERO);
+    assert!(timers[1].total() > timers[0].total());
+
+    // Once bypassed, a stage costs nothing.
+    chain.set_enabled(1, false);
+    chain.process_block(&mut block);
+    let before = timers[1].total();
+    chain.process_block(&mut block);
+    assert_eq!(timers[1].total(), before);
+}
+
+#[test]
+fn invert_phase_negates_every_sample() {
+    let input = [0.0, 0.25, -0.5, 1.0, -1.0, 3.5, f32::MIN_POSITIVE];
+    let mut invert = InvertPhase;
+    for sample in input {
+        assert_eq!(invert.process(sample), -sample);
+    }
+
+    let mut block = input;
+    invert.process_block(&mut block);
+    assert!(block.iter().zip(&input).all(|(o, i)| *o == -*i));
+}
+
+#[test]
+fn parallel_identity_branches_at_half_gain_reproduce_the_input() {
+    let input: Vec<f32> = (0..256).map(|n| (n as f32 * 0.1).sin()).collect();
+    let mut parallel = ParallelChain::new();
+    parallel.push(Box::new(FilterChain::new()), 0.5);
+    parallel.push(Box::new(Gain::new(0.0)), 0.5);
+
+    let output: Vec<f32> = input.iter().map(|x| parallel.process(*x)).collect();
+    assert_eq!(output, input);
+
+    let mut block = input.clone();
+    parallel.process_block(&mut block);
+    assert_eq!(block, input);
+}
+
+#[test]
+fn parallel_delay_and_dry_sum_to_dry_plus_echo() {
+    // The delay's own output carries the dry impulse as well as its echoes,
+    // so the direct sound arrives twice over.
+    let mut parallel = ParallelChain::new();
+    parallel.push(Box::new(FilterChain::new()), 1.0);
+    parallel.push(Box::new(DelayFilter::new(10, 0.5, 0.0).unwrap()), 1.0);
+
+    let mut block = vec![0.0; 40];
+    block[0] = 1.0;
+    parallel.process_block(&mut block);
+    for (n, sample) in block.iter().enumerate() {
+        let expected = match n {
+            0 => 2.0,
+            10 => 0.5,
+            20 => 0.25,
+            30 => 0.125,
+            _ => 0.0,
+        };
+        assert_eq!(*sample, expected, "sample {}", n);
+    }
+}
+
+#[test]
+fn multiband_crossovers_sum_flat() {
+    let sample_rate = SampleRate(48000);
+    let bands = || -> [Box<dyn Filter>; 3] {
+        [
+            Box::new(FilterChain::new()),
+            Box::new(FilterChain::new()),
+            Box::new(FilterChain::new()),
+        ]
+    };
+
+    // Tones at and between the crossovers, where any dip or bump in the sum
+    // would show.
+    for freq in [50.0, 200.0, 600.0, 2000.0, 8000.0] {
+        let mut multiband = Multiband::new(200.0, 2000.0, sample_rate, bands());
+        let input: Vec<f32> = (0..48000)
+            .map(|n| (2.0 * std::f32::consts::PI * freq * n as f32 / 48000.0).sin())
+            .collect();
+        let output: Vec<f32> = input.iter().map(|x| multiband.process(*x)).collect();
+
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        let ratio = rms(&output[24000..]) / rms(&input[24000..]);
+        assert!((ratio - 1.0).abs() < 0.01, "{} Hz: {}", freq, ratio);
+    }
+}
+
+// Passes samples straight through while claiming to be late, so any delay
+// in the output comes from whatever is compensating for it.
+struct ClaimsLatency(usize);
+
+impl Filter for ClaimsLatency {
+    fn process(&mut self, sample: f32) -> f32 {
+        sample
+    }
+
+    fn latency_frames(&self) -> usize {
+        self.0
+    }
+}
+
+fn impulse(len: usize) -> Vec<f32> {
+    let mut block = vec![0.0; len];
+    block[0] = 1.0;
+    block
+}
+
+#[test]
+fn wet_dry_delays_the_dry_path_by_the_wet_latency() {
+    // All dry, so the output is only the compensated dry path.
+    let mut wet_dry = WetDry::new(ClaimsLatency(7), 0.0);
+    assert_eq!(wet_dry.latency_frames(), 7);
+    let output: Vec<f32> = impulse(20).iter().map(|x| wet_dry.process(*x)).collect();
+    let mut expected = vec![0.0; 20];
+    expected[7] = 1.0;
+    assert_eq!(output, expected);
+
+    let mut wet_dry = WetDry::new(ClaimsLatency(7), 0.0);
+    let mut block = impulse(20);
+    wet_dry.process_block(&mut block);
+    assert_eq!(block, expected);
+}
+
+#[test]
+fn parallel_chain_lines_branches_up_with_the_slowest() {
+    let limiter = Limiter::new(1.0, SampleRate(48000));
+    let lookahead = limiter.lookahead_frames();
+
+    let mut parallel = ParallelChain::new();
+    parallel.push(Box::new(FilterChain::new()), 0.5);
+    parallel.push(Box::new(limiter), 0.5);
+    assert_eq!(parallel.latency_frames(), lookahead);
+
+    let mut block = impulse(4 * lookahead);
+    parallel.process_block(&mut block);
+    for (n, sample) in block.iter().enumerate() {
+        let expected = if n == lookahead { 1.0 } else { 0.0 };
+        assert!((sample - expected).abs() < 1e-6, "sample {}: {}", n, sample);
+    }
+}
+
+#[test]
+fn multiband_lines_bands_up_with_the_slowest() {
+    let sample_rate = SampleRate(48000);
+    let limiter = Limiter::new(10.0, sample_rate);
+    let lookahead = limiter.lookahead_frames();
+    let mut late_mid = Multiband::new(
+        200.0,
+        2000.0,
+        sample_rate,
+        [
+            Box::new(FilterChain::new()),
+            Box::new(limiter),
+            Box::new(FilterChain::new()),
+        ],
+    );
+    assert_eq!(late_mid.latency_frames(), lookahead);
+
+    // The limiter never touches a signal this quiet, so the whole output
+    // should be the plain split delayed by its lookahead.
+    let mut plain = Multiband::new(
+        200.0,
+        2000.0,
+        sample_rate,
+        [
+            Box::new(FilterChain::new()),
+            Box::new(FilterChain::new()),
+            Box::new(FilterChain::new()),
+        ],
+    );
+    let expected: Vec<f32> = impulse(2000).iter().map(|x| plain.process(*x)).collect();
+    let output: Vec<f32> = impulse(2000 + lookahead)
+        .iter()
+        .map(|x| late_mid.process(*x))
+        .collect();
+    assert!(output[..lookahead].iter().all(|s| *s == 0.0));
+    for (n, (got, want)) in output[lookahead..].iter().zip(&expected).enumerate() {
+        assert!(
+            (got - want).abs() < 1e-6,
+            "sample {}: {} != {}",
+            n,
+            got,
+            want
+        );
+    }
+}
+
+#[test]
+fn reset_delay_forgets_its_echoes() {
+    let mut delay = DelayFilter::new(100, 0.9, 0.3).unwrap();
+    delay.process(1.0);
+    for _ in 0..150 {
+        delay.process(0.0);
+    }
+
+    delay.reset();
+    assert!((0..1000).all(|_| delay.process(0.0) == 0.0));
+}
+
+fn test_wah() -> AutoWah {
+    AutoWah::new(4.0, 300.0, 3000.0, 4.0, 5.0, 50.0, SampleRate(48000))
+}
+
+#[test]
+fn reset_biquad_filters_fall_silent() {
+    let filters: Vec<Box<dyn Filter>> = vec![
+        Box::new(test_wah()),
+        Box::new(LowPassFilter::new(1000.0, 4.0, SampleRate(48000))),
+    ];
+    for mut filter in filters {
+        for n in 0..4800 {
+            filter.process((n as f32 * 0.7).sin());
+        }
+
+        // Straight after a loud tone the biquad would still ring.
+        filter.reset();
+        assert!((0..1000).all(|_| filter.process(0.0) == 0.0));
+    }
+}
+
+#[test]
+fn reset_filters_start_over() {
+    let build = || -> Vec<Box<dyn Filter>> {
+        vec![
+            Box::new(Tremolo::new(7.0, 1.0, SampleRate(48000))),
+            Box::new(RingModulator::new(500.0, 1.0, SampleRate(48000))),
+            Box::new(Chorus::new(3, 2.0, 5.0, 1.0, SampleRate(48000))),
+            Box::new(BitCrusher::new(4, 7)),
+            Box::new(DeEsser::new(6000.0, 0.1, 4.0, SampleRate(48000))),
+            Box::new(test_wah()),
+        ]
+    };
+    for (mut used, mut fresh) in build().into_iter().zip(build()) {
+        for n in 0..1001 {
+            used.process((n as f32 * 0.7).sin());
+        }
+        used.reset();
+        for n in 0..4800 {
+            let x = (n as f32 * 0.05).sin();
+            assert_eq!(used.process(x), fresh.process(x), "frame {}", n);
+        }
+    }
+}
+
+#[test]
+fn chain_resets_a_stage_when_it_is_switched_back_on() {
+    let mut chain = FilterChain::new();
+    chain.push(Box::new(DelayFilter::new(10, 0.9, 0.0).unwrap()));
+    chain.process(1.0);
+    for _ in 0..4 {
+        chain.process(0.0);
+    }
+
+    // Echoes still in the line when the stage was bypassed are gone when
+    // it comes back.
+    chain.set_enabled(0, false);
+    chain.process(0.0);
+    chain.set_enabled(0, true);
+    assert!((0..100).all(|_| chain.process(0.0) == 0.0));
+}
+
+#[test]
+fn soft_knee_bends_the_compression_curve_smoothly() {
+    let sample_rate = SampleRate(48000);
+    let mut compressor = Compressor::new(-20.0, 4.0, 5.0, 50.0, 0.0, sample_rate);
+    let hard = Compressor::new(-20.0, 4.0, 5.0, 50.0, 0.0, sample_rate);
+    compressor.set_knee(12.0);
+
+    let step = 0.01;
+    let levels: Vec<f32> = (0..=4000).map(|n| -40.0 + n as f32 * step).collect();
+    let curve: Vec<f32> = levels
+        .iter()
+        .map(|level| compressor.gain_reduction_db(*level))
+        .collect();
+
+    // Outside the knee it's the hard-knee curve.
+    for (level, reduction) in levels.iter().zip(&curve) {
+        if (level + 20.0).abs() >= 6.0 {
+            let expected = hard.gain_reduction_db(*level);
+            assert!((reduction - expected).abs() < 1e-4, "{} dB", level);
+        }
+    }
+    assert!((compressor.gain_reduction_db(-20.0) - 0.75 * 12.0 / 8.0).abs() < 1e-4);
+
+    // No jumps, and the slope only ever eases from 0 up to 1 - 1/ratio,
+    // never turning a corner.
+    let slopes: Vec<f32> = curve.windows(2).map(|w| (w[1] - w[0]) / step).collect();
+    assert!(slopes
+        .iter()
+        .all(|slope| (-1e-3..=0.75 + 1e-3).contains(slope)));
+    for pair in slopes.windows(2) {
+        assert!((pair[1] - pair[0]).abs() < 0.01, "{:?}", pair);
+    }
+}
+
+#[test]
+fn dither_is_reproducible_and_unbiased() {
+    let run = |seed, sample| {
+        let mut dither = Dither::new(seed);
+        (0..100_000)
+            .map(|_| dither.quantize(sample, 0.25))
+            .collect::<Vec<f32>>()
+    };
+    assert_eq!(run(7, 0.3), run(7, 0.3));
+    assert_ne!(run(7, 0.3), run(8, 0.3));
+
+    let mean = |samples: Vec<f32>| samples.iter().sum::<f32>() / samples.len() as f32;
+
+    // Every output is a whole step, and a sample already on a step averages
+    // out to itself, just as it would round without dither.
+    let on_step = run(7, 0.5);
+    assert!(on_step.iter().all(|x| (x / 0.25).fract() == 0.0));
+    assert!((mean(on_step) - 0.5).abs() < 0.002);
+
+    // One between steps averages out to where it really was, rather than to
+    // the nearest step.
+    assert!((mean(run(7, 0.3)) - 0.3).abs() < 0.002);
+}
+
+#[test]
+fn oversampling_cuts_the_aliases_of_hard_clipping() {
+    // Clipping a 5kHz sine makes odd harmonics, which past 24kHz fold back
+    // onto whole kHz that aren't 5kHz or 15kHz.  The last 100ms, by then
+    // settled, holds a whole number of cycles of all of them.
+    let sr = 48000;
+    let frames = 4800;
+    let clipped = |factor: usize| {
+        let distort = DistortFilter::new(10.0, 0.5, DistortMode::Hard);
+        let mut oversampler = Oversampler::new(distort, factor, SampleRate(sr));
+        let output: Vec<f32> = (0..2 * frames)
+            .map(|n| {
+                let phase = 2.0 * std::f32::consts::PI * 5000.0 * n as f32 / sr as f32;
+                oversampler.process(0.5 * phase.sin())
+            })
+            .collect();
+        output[frames..].to_vec()
+    };
+    let power_at = |signal: &[f32], freq: f32| {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, sample) in signal.iter().enumerate() {
+            let phase = 2.0 * std::f32::consts::PI * freq * n as f32 / sr as f32;
+            re += sample * phase.cos();
+            im += sample * phase.sin();
+        }
+        (re * re + im * im) / (signal.len() * signal.len()) as f32
+    };
+    let aliases = |signal: &[f32]| -> f32 {
+        (1..24)
+            .filter(|khz| khz % 5 != 0)
+            .map(|khz| power_at(signal, khz as f32 * 1000.0))
+            .sum()
+    };
+
+    let plain = clipped(1);
+    let oversampled = clipped(4);
+    // Both still clip the fundamental about as hard.
+    let fundamental = power_at(&oversampled, 5000.0) / power_at(&plain, 5000.0);
+    assert!((fundamental - 1.0).abs() < 0.1, "{}", fundamental);
+    let reduction_db = 10.0 * (aliases(&plain) / aliases(&oversampled)).log10();
+    assert!(reduction_db > 20.0, "{} dB", reduction_db);
+}
+
+#[test]
+fn oversampler_latency_is_where_an_impulse_peaks() {
+    let peak_frame = |mut filter: Box<dyn Filter>| {
+        let output: Vec<f32> = impulse(1000).iter().map(|x| filter.process(*x)).collect();
+        let peak = output.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+        output.iter().position(|s| s.abs() == peak).unwrap()
+    };
+
+    for factor in [2, 4] {
+        let plain = Oversampler::new(Gain::new(0.0), factor, SampleRate(48000));
+        let latency = plain.latency_frames();
+        assert!(latency > 0);
+        assert_eq!(peak_frame(Box::new(plain)), latency, "{}x", factor);
+
+        // A lookahead inside counts at the oversampled rate.
+        let limiter = Limiter::new(10.0, SampleRate(48000 * factor as u32));
+        let lookahead = limiter.lookahead_frames() / factor;
+        let limited = Oversampler::new(limiter, factor, SampleRate(48000));
+        assert_eq!(limited.latency_frames(), latency + lookahead);
+        assert_eq!(
+            peak_frame(Box::new(limited)),
+            latency + lookahead,
+            "{}x",
+            factor
+        );
+    }
+}
+
+#[test]
+fn one_bit_crushes_to_two_levels() {
+    let mut crusher = BitCrusher::new(1, 1);
+    let output: Vec<f32> = (-100..=100)
+        .map(|n| crusher.process(n as f32 / 100.0))
+        .collect();
+    assert!(output.iter().all(|s| *s == -1.0 || *s == 1.0));
+    assert!(output.contains(&-1.0) && output.contains(&1.0));
+}
+
+#[test]
+fn downsampling_holds_each_value_for_n_samples() {
+    let mut crusher = BitCrusher::new(16, 3);
+    let input: Vec<f32> = (0..30).map(|n| n as f32 / 40.0).collect();
+    let output: Vec<f32> = input.iter().map(|x| crusher.process(*x)).collect();
+
+    // Each run holds the first sample of its block of three.
+    for (run, held) in output.chunks(3).enumerate() {
+        assert!(held.iter().all(|s| *s == held[0]), "{:?}", held);
+        assert!((held[0] - input[run * 3]).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn tremolo_depth_and_rate() {
+    let mut still = Tremolo::new(5.0, 0.0, SampleRate(48000));
+    for n in 0..4800 {
+        let x = (n as f32 * 0.05).sin();
+        assert_eq!(still.process(x), x);
+    }
+
+    // At 10Hz the gain on a steady input repeats every 4800 frames, and
+    // swings across the whole depth within each period.
+    let mut tremolo = Tremolo::new(10.0, 0.5, SampleRate(48000));
+    let gain: Vec<f32> = (0..9600).map(|_| tremolo.process(1.0)).collect();
+    for n in 0..4800 {
+        assert!((gain[n] - gain[n + 4800]).abs() < 1e-3, "frame {}", n);
+    }
+    let lowest = gain[..4800].iter().fold(f32::MAX, |a, b| a.min(*b));
+    let highest = gain[..4800].iter().fold(f32::MIN, |a, b| a.max(*b));
+    assert!((lowest - 0.5).abs() < 1e-3 && (highest - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn vibrato_interpolates_between_samples() {
+    // With the LFO stopped at its midpoint a one-frame depth reads half a
+    // frame back, halfway between each sample and the one before it.
+    let mut vibrato = Vibrato::new(0.0, 1.0, SampleRate(1000));
+    let output: Vec<f32> = (0..10).map(|n| vibrato.process(n as f32)).collect();
+    for (n, sample) in output.iter().enumerate().skip(1) {
+        assert!((sample - (n as f32 - 0.5)).abs() < 1e-6, "{:?}", output);
+    }
+}
+
+#[test]
+fn gain_follows_its_decibels() {
+    let gain = |db: f32| Gain::new(db).process(0.5) / 0.5;
+    assert_eq!(gain(0.0), 1.0);
+    assert!((gain(6.0) - 2.0).abs() < 0.01, "{}", gain(6.0));
+    assert!((gain(-6.0) - 0.5).abs() < 0.01, "{}", gain(-6.0));
+    assert!(gain(-120.0) < 1e-5);
+    assert_eq!(gain(f32::NEG_INFINITY), 0.0);
+}
+
+#[test]
+fn auto_wah_opens_on_a_burst_and_settles_after() {
+    let mut wah = test_wah();
+    let tone = |n: usize, amplitude: f32| amplitude * (n as f32 * 0.05).sin();
+
+    for n in 0..4800 {
+        wah.process(tone(n, 0.001));
+    }
+    let resting = wah.center_hz();
+    assert!(resting < 310.0, "{}", resting);
+
+    for n in 0..4800 {
+        wah.process(tone(n, 0.5));
+    }
+    assert!(wah.center_hz() > 1000.0, "{}", wah.center_hz());
+
+    for n in 0..48000 {
+        wah.process(tone(n, 0.001));
+    }
+    assert!(
+        (wah.center_hz() - resting).abs() < 5.0,
+        "{}",
+        wah.center_hz()
+    );
+}
@@ -0,0 +1,33 @@
+use cpal::SampleRate;
+use g2::generator::{Signal, SignalGenerator};
+
+#[test]
+fn sine_has_the_requested_frequency_and_amplitude() {
+    let mut generator = SignalGenerator::new(Signal::Sine, 1000.0, 0.25, SampleRate(48000));
+    let samples: Vec<f32> = (0..48000).map(|_| generator.next_sample()).collect();
+
+    let peak = samples.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+    assert!((peak - 0.25).abs() < 1e-3, "peak {}", peak);
+
+    // One rising zero crossing per cycle.  The sine starts at zero, so the
+    // crossings land every 48 samples from then on.
+    let rising: Vec<usize> = samples
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+        .map(|(n, _)| n + 1)
+        .collect();
+    assert_eq!(rising.len(), 999);
+    let period = (rising[998] - rising[0]) as f32 / 998.0;
+    assert!((period - 48.0).abs() < 0.01, "period {}", period);
+}
+
+#[test]
+fn noise_stays_within_its_amplitude() {
+    for signal in [Signal::White, Signal::Pink, Signal::Sweep] {
+        let mut generator = SignalGenerator::new(signal, 440.0, 0.5, SampleRate(44100));
+        let samples: Vec<f32> = (0..44100).map(|_| generator.next_sample()).collect();
+        let peak = samples.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+        assert!(peak <= 0.5 && peak > 0.1, "{:?} peak {}", signal, peak);
+    }
+}
@@ -0,0 +1,60 @@
+use cpal::SampleRate;
+use g2::generator::{Signal, SignalGenerator};
+use g2::resample::{ResampleQuality, Resampler};
+
+// Finds the frequency of a sine from the spacing of its rising zero
+// crossings.
+fn sine_frequency(samples: &[f32], sample_rate: f32) -> f32 {
+    let rising: Vec<usize> = samples
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+        .map(|(n, _)| n)
+        .collect();
+    let cycles = rising.len() - 1;
+    let period = (rising[cycles] - rising[0]) as f32 / cycles as f32;
+    sample_rate / period
+}
+
+#[test]
+fn resampling_keeps_a_tone_at_its_frequency() {
+    for quality in [ResampleQuality::Linear, ResampleQuality::Cubic] {
+        let mut generator = SignalGenerator::new(Signal::Sine, 1000.0, 0.5, SampleRate(44100));
+        let mut resampler = Resampler::new(quality, 1, SampleRate(44100), SampleRate(48000));
+
+        let mut output = vec![0.0; 48000];
+        for frame in output.chunks_mut(1) {
+            resampler.next_frame(frame, |input| input[0] = generator.next_sample());
+        }
+
+        let frequency = sine_frequency(&output, 48000.0);
+        assert!(
+            (frequency - 1000.0).abs() < 1.0,
+            "{:?} {}",
+            quality,
+            frequency
+        );
+        let peak = output.iter().fold(0.0f32, |a, b| a.max(b.abs()));
+        assert!((peak - 0.5).abs() < 0.02, "{:?} peak {}", quality, peak);
+    }
+}
+
+#[test]
+fn resampling_consumes_input_at_the_input_rate() {
+    let mut resampler = Resampler::new(
+        ResampleQuality::Cubic,
+        2,
+        SampleRate(48000),
+        SampleRate(44100),
+    );
+    let mut frames_read = 0;
+    let mut frame = [0.0; 2];
+    for _ in 0..44100 {
+        resampler.next_frame(&mut frame, |input| {
+            input.fill(1.0);
+            frames_read += 1;
+        });
+    }
+    assert!((47999..=48000).contains(&frames_read), "{}", frames_read);
+    assert_eq!(frame, [1.0, 1.0]);
+}
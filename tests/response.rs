@@ -0,0 +1,69 @@
+use std::f32::consts::PI;
+
+use cpal::SampleRate;
+use g2::effects::{Filter, LowPassFilter};
+use g2::response::{measure_response, THIRD_OCTAVES};
+
+// The simplest low-pass there is, y += a * (x - y), whose response is known
+// exactly.
+struct OnePole {
+    coefficient: f32,
+    state: f32,
+}
+
+impl Filter for OnePole {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.state += self.coefficient * (sample - self.state);
+        self.state
+    }
+}
+
+#[test]
+fn measures_a_one_pole_low_pass() {
+    let sample_rate = 48000.0;
+    let coefficient = 1.0 - (-2.0 * PI * 1000.0 / sample_rate).exp();
+    let mut filter = OnePole {
+        coefficient,
+        state: 0.0,
+    };
+    let measured = measure_response(
+        |block| filter.process_block(block),
+        SampleRate(48000),
+        &THIRD_OCTAVES,
+    );
+
+    for (freq, gain) in THIRD_OCTAVES.iter().zip(measured) {
+        // |a / (1 - (1 - a)e^-jw)|
+        let w = 2.0 * PI * freq / sample_rate;
+        let pole = 1.0 - coefficient;
+        let denominator = (1.0 - pole * w.cos()).hypot(pole * w.sin());
+        let expected = 20.0 * (coefficient / denominator).log10();
+        assert!(
+            (gain - expected).abs() < 0.1,
+            "{} Hz: {} != {}",
+            freq,
+            gain,
+            expected
+        );
+    }
+}
+
+#[test]
+fn low_pass_is_three_db_down_at_its_cutoff() {
+    let mut filter = LowPassFilter::new(1000.0, std::f32::consts::FRAC_1_SQRT_2, SampleRate(48000));
+    let measured = measure_response(
+        |block| filter.process_block(block),
+        SampleRate(48000),
+        &[100.0, 1000.0],
+    );
+    assert!(measured[0].abs() < 0.1, "{}", measured[0]);
+    assert!((measured[1] + 3.01).abs() < 0.1, "{}", measured[1]);
+}
+
+#[test]
+fn frequencies_outside_the_sweep_are_nan() {
+    let measured = measure_response(|_| {}, SampleRate(8000), &[5.0, 1000.0, 3900.0]);
+    assert!(measured[0].is_nan());
+    assert!(measured[1].abs() < 1e-3);
+    assert!(measured[2].is_nan());
+}
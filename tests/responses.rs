@@ -0,0 +1,292 @@
+mod common;
+
+use common::{impulse_response, sine_gain, step_response};
+use cpal::SampleRate;
+use g2::effects::{
+    db_to_linear, Chorus, ConvolutionReverb, DelayFilter, DistortFilter, DistortMode, Filter,
+    FlangeFilter, HighPassFilter, LfoShape, LowPassFilter, PeakingEq, Phaser, ReverbFilter,
+    RingModulator,
+};
+
+#[test]
+fn distortion_step_saturates() {
+    for mode in [DistortMode::Hard, DistortMode::Soft] {
+        let output = step_response(Box::new(DistortFilter::new(50.0, 0.6, mode)), 10);
+        assert!(output.iter().all(|s| *s <= 0.6 && *s > 0.59), "{:?}", mode);
+    }
+}
+
+#[test]
+fn quiet_distortion_is_linear() {
+    let output = impulse_response(Box::new(DistortFilter::new(2.0, 0.9, DistortMode::Hard)), 5);
+    assert_eq!(output, [0.9, 0.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn delay_impulse_echoes_and_decays() {
+    let delay = DelayFilter::new(250, 0.6, 0.0).unwrap();
+    let output = impulse_response(Box::new(delay), 1000);
+
+    for (i, sample) in output.iter().enumerate() {
+        let expected = if i % 250 == 0 {
+            0.6f32.powi(i as i32 / 250)
+        } else {
+            0.0
+        };
+        assert!((sample - expected).abs() < 1e-6, "sample {}", i);
+    }
+}
+
+#[test]
+fn heavy_feedback_still_dies_out() {
+    let delay = DelayFilter::new(100, 0.99, 0.0).unwrap();
+    let output = impulse_response(Box::new(delay), 100_000);
+
+    let echoes: Vec<f32> = output.iter().step_by(100).copied().collect();
+    for pair in echoes.windows(2) {
+        assert!(pair[1] < pair[0], "{} then {}", pair[0], pair[1]);
+    }
+    assert!(output[99_000..].iter().all(|s| s.abs() < 1e-4));
+}
+
+#[test]
+fn delay_step_settles_at_feedback_gain() {
+    let delay = DelayFilter::new(10, 0.5, 0.0).unwrap();
+    let output = step_response(Box::new(delay), 1000);
+    assert!((output[999] - 2.0).abs() < 1e-3);
+}
+
+#[test]
+fn damped_delay_echoes_get_duller() {
+    let delay = DelayFilter::new(100, 0.9, 0.5).unwrap();
+    let output = impulse_response(Box::new(delay), 400);
+
+    // Damping spreads each echo over several samples and lowers its peak.
+    let peak = |echo: usize| {
+        output[echo * 100..(echo + 1) * 100]
+            .iter()
+            .fold(0.0f32, |a, b| a.max(*b))
+    };
+    assert!(peak(1) < 0.9);
+    assert!(peak(2) < peak(1) * 0.9);
+}
+
+#[test]
+fn flange_echo_starts_at_full_sweep() {
+    // At t = 0 the sweep sits at its longest delay, 2 * amplitude + 1.
+    let flange = FlangeFilter::new(100, SampleRate(48000), 10.0, 10.0, 0.5, LfoShape::Sine);
+    let output = impulse_response(Box::new(flange), 60);
+
+    assert_eq!(output[0], 1.0);
+    let (echo, level) = output
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(
+            (0, 0.0f32),
+            |best, (i, s)| if *s > best.1 { (i, *s) } else { best },
+        );
+    assert_eq!(echo, 21);
+    assert!((level - 0.5).abs() < 0.01);
+}
+
+#[test]
+fn flange_half_frame_offset_averages_its_neighbours() {
+    // A quarter-frame amplitude starts the sweep at 1.5 frames, and an LFO
+    // this slow barely moves it.  Each echo is then the mean of the last two
+    // outputs.
+    let flange = FlangeFilter::new(10, SampleRate(48000), 1e6, 0.25, 1.0, LfoShape::Sine);
+    assert!((flange.current_delay() - 1.5).abs() < 1e-6);
+
+    let output = impulse_response(Box::new(flange), 4);
+    for (sample, expected) in output.iter().zip([1.0, 0.5, 0.75, 0.625]) {
+        assert!((sample - expected).abs() < 1e-5, "{:?}", output);
+    }
+}
+
+#[test]
+fn flange_step_stays_bounded() {
+    let flange = FlangeFilter::new(500, SampleRate(48000), 0.01, 100.0, 0.8, LfoShape::Sine);
+    let output = step_response(Box::new(flange), 20000);
+    assert!(output.iter().all(|s| s.is_finite() && *s <= 5.0 + 1e-3));
+}
+
+#[test]
+fn lfo_shapes_over_one_period() {
+    let phases: Vec<f32> = (0..=64)
+        .map(|i| 2.0 * std::f32::consts::PI * i as f32 / 64.0)
+        .collect();
+
+    for phase in &phases {
+        assert!((LfoShape::Sine.at(*phase) - phase.cos()).abs() < 1e-6);
+    }
+
+    // The triangle falls by the same amount every step for the first half of
+    // the period, then rises the same way.
+    let triangle: Vec<f32> = phases.iter().map(|p| LfoShape::Triangle.at(*p)).collect();
+    assert!((triangle[0] - 1.0).abs() < 1e-6);
+    assert!((triangle[32] + 1.0).abs() < 1e-5);
+    for step in triangle[..33].windows(2) {
+        assert!((step[1] - step[0] + 1.0 / 16.0).abs() < 1e-5);
+    }
+    for step in triangle[32..64].windows(2) {
+        assert!((step[1] - step[0] - 1.0 / 16.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn flange_keeps_sweeping_after_hours() {
+    // One LFO period is 4 * frequency * sample_rate frames; this is 960.
+    let mut flange = FlangeFilter::new(100, SampleRate(48000), 0.005, 20.0, 0.5, LfoShape::Sine);
+
+    // Well past 2^24 samples, where an unwrapped f32 sample counter stops
+    // advancing.
+    for _ in 0..20_000_000 {
+        flange.process(0.0);
+    }
+
+    let delays: Vec<f32> = (0..960)
+        .map(|_| {
+            let delay = flange.current_delay();
+            flange.process(0.0);
+            delay
+        })
+        .collect();
+    let lowest = delays.iter().fold(f32::MAX, |a, b| a.min(*b));
+    let highest = delays.iter().fold(f32::MIN, |a, b| a.max(*b));
+    assert!((lowest - 1.0).abs() < 0.01, "lowest {}", lowest);
+    assert!((highest - 41.0).abs() < 0.01, "highest {}", highest);
+}
+
+#[test]
+fn convolution_impulse_response_is_the_ir() {
+    let ir = vec![0.5, -0.25, 0.125, 0.0, 0.75];
+    let output = impulse_response(Box::new(ConvolutionReverb::new(ir.clone())), 12);
+    assert_eq!(output[..5], ir[..]);
+    assert!(output[5..].iter().all(|s| *s == 0.0), "{:?}", output);
+}
+
+#[test]
+fn convolution_step_sums_the_ir() {
+    let output = step_response(Box::new(ConvolutionReverb::new(vec![0.5, 0.25, 0.125])), 6);
+    assert_eq!(output, [0.5, 0.75, 0.875, 0.875, 0.875, 0.875]);
+}
+
+#[test]
+fn low_pass_passes_dc_and_cuts_near_nyquist() {
+    let lowpass = LowPassFilter::new(2000.0, 0.707, SampleRate(48000));
+    let output = step_response(Box::new(lowpass), 4800);
+    assert!((output[4799] - 1.0).abs() < 1e-4, "{}", output[4799]);
+
+    let lowpass = LowPassFilter::new(2000.0, 0.707, SampleRate(48000));
+    let gain = sine_gain(Box::new(lowpass), 20000.0, 48000);
+    assert!(gain < 0.02, "{}", gain);
+}
+
+#[test]
+fn high_pass_blocks_dc_and_passes_high_tones() {
+    let highpass = HighPassFilter::new(100.0, 0.707, SampleRate(48000));
+    let output = step_response(Box::new(highpass), 48000);
+    assert!(output[0] > 0.9);
+    assert!(output[47999].abs() < 1e-4, "{}", output[47999]);
+
+    let highpass = HighPassFilter::new(100.0, 0.707, SampleRate(48000));
+    let gain = sine_gain(Box::new(highpass), 5000.0, 48000);
+    assert!((gain - 1.0).abs() < 0.01, "{}", gain);
+}
+
+#[test]
+fn reverb_tail_rings_for_the_reverb_time() {
+    // A room size of 0.5 feeds each comb back at 0.84, so the shortest comb
+    // takes ln(1000) / -ln(0.84) trips round to fall by 60dB.
+    let (room_size, sample_rate) = (0.5, 48000);
+    let feedback: f32 = 0.84;
+    let trip = 1422.0 / 44100.0;
+    let reverb_time = 1000f32.ln() / -feedback.ln() * trip;
+
+    let reverb = ReverbFilter::new(room_size, 0.0, 1.0, SampleRate(sample_rate));
+    let frames = (reverb_time * sample_rate as f32) as usize;
+    let output = impulse_response(Box::new(reverb), frames);
+
+    let peak = |from: usize| {
+        output[from..from + sample_rate as usize / 20]
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()))
+    };
+    let start = peak(0);
+    let end = peak(frames - sample_rate as usize / 20);
+    assert!(end > start * 1e-3, "{} faded to {}", start, end);
+}
+
+#[test]
+fn more_phaser_stages_cut_more_notches() {
+    // Counts the dips in the magnitude response with the sweep held still.
+    let notches = |stages: usize| {
+        let phaser = Phaser::new(stages, 0.0, 0.5, 0.0, SampleRate(48000));
+        let ir = impulse_response(Box::new(phaser), 2048);
+        let gains: Vec<f32> = (1..1000)
+            .map(|bin| {
+                let w = std::f32::consts::PI * bin as f32 / 1000.0;
+                let (re, im) = ir.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, h)| {
+                    (re + h * (w * n as f32).cos(), im - h * (w * n as f32).sin())
+                });
+                (re * re + im * im).sqrt()
+            })
+            .collect();
+        gains
+            .windows(3)
+            .filter(|w| w[1] < w[0] && w[1] < w[2] && w[1] < 0.1)
+            .count()
+    };
+
+    // Each stage turns the phase by up to half a turn, and the mix cancels
+    // wherever the total is an odd number of half turns.
+    for stages in [2, 4, 8] {
+        assert_eq!(notches(stages), stages / 2, "{} stages", stages);
+    }
+}
+
+#[test]
+fn chorus_impulse_comes_back_once_per_voice() {
+    // With the LFOs stopped each voice sits at its own fixed delay.
+    let chorus = Chorus::new(3, 0.0, 5.0, 1.0, SampleRate(48000));
+    let output = impulse_response(Box::new(chorus), 2000);
+
+    // Interpolation spreads each copy over a pair of neighbouring frames.
+    let copies = output
+        .windows(2)
+        .filter(|pair| pair[0] == 0.0 && pair[1] != 0.0)
+        .count();
+    assert_eq!(copies, 3, "{:?}", output);
+    let total: f32 = output.iter().sum();
+    assert!((total - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn ring_modulated_dc_is_the_carrier() {
+    // 500Hz at 48kHz repeats every 96 frames.
+    let ring = RingModulator::new(500.0, 1.0, SampleRate(48000));
+    let output = step_response(Box::new(ring), 960);
+    for (n, sample) in output.iter().enumerate() {
+        let carrier = (2.0 * std::f32::consts::PI * 500.0 * n as f32 / 48000.0).sin();
+        assert!((sample - carrier).abs() < 1e-3, "frame {}", n);
+    }
+    for n in 0..864 {
+        assert!((output[n] - output[n + 96]).abs() < 1e-3, "frame {}", n);
+    }
+}
+
+#[test]
+fn peaking_eq_boosts_only_around_its_centre() {
+    let mut flat = PeakingEq::new(1000.0, 1.0, 0.0, SampleRate(48000));
+    for n in 0..4800 {
+        let x = (n as f32 * 0.05).sin();
+        assert!((flat.process(x) - x).abs() < 1e-5);
+    }
+
+    let boost = || Box::new(PeakingEq::new(1000.0, 1.0, 12.0, SampleRate(48000)));
+    let centre = sine_gain(boost(), 1000.0, 48000);
+    let distant = sine_gain(boost(), 15000.0, 48000);
+    assert!((centre - db_to_linear(12.0)).abs() < 0.01, "{}", centre);
+    assert!((distant - 1.0).abs() < 0.02, "{}", distant);
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use cpal::{BackendSpecificError, StreamError};
+use g2::retry::{is_recoverable, Backoff};
+
+#[test]
+fn backoff_doubles_up_to_the_cap_then_gives_up() {
+    let backoff = Backoff::new(Duration::from_millis(250), Duration::from_secs(1), 5);
+    let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+    assert_eq!(
+        delays,
+        vec![
+            Some(Duration::from_millis(250)),
+            Some(Duration::from_millis(500)),
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(1)),
+            None,
+        ]
+    );
+
+    // Far enough along that doubling would overflow, it stays at the cap.
+    let patient = Backoff::new(Duration::from_millis(250), Duration::from_secs(4), u32::MAX);
+    assert_eq!(patient.delay(100), Some(Duration::from_secs(4)));
+}
+
+#[test]
+fn only_a_missing_device_is_worth_retrying() {
+    assert!(is_recoverable(&StreamError::DeviceNotAvailable));
+    let backend = StreamError::BackendSpecific {
+        err: BackendSpecificError {
+            description: "broken pipe".to_string(),
+        },
+    };
+    assert!(!is_recoverable(&backend));
+}
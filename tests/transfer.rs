@@ -0,0 +1,28 @@
+use g2::transfer::{pop_samples, push_samples};
+use ringbuf::RingBuffer;
+
+#[test]
+fn bulk_transfer_moves_every_sample_that_fits() {
+    let (mut producer, mut consumer) = RingBuffer::<f32>::new(8).split();
+    let data: Vec<f32> = (0..5).map(|i| i as f32).collect();
+    assert_eq!(push_samples(&mut producer, &data), 0);
+    assert_eq!(consumer.len(), 5);
+
+    let mut out = [9.0; 5];
+    assert_eq!(pop_samples(&mut consumer, &mut out), 0);
+    assert_eq!(out.to_vec(), data);
+    assert!(consumer.is_empty());
+}
+
+#[test]
+fn a_full_buffer_counts_the_shortfall() {
+    let (mut producer, mut consumer) = RingBuffer::<f32>::new(4).split();
+    let data = [0.5; 10];
+    assert_eq!(push_samples(&mut producer, &data), 6);
+    assert_eq!(consumer.len(), 4);
+
+    // Reading more than is buffered pads with silence.
+    let mut out = [9.0; 6];
+    assert_eq!(pop_samples(&mut consumer, &mut out), 2);
+    assert_eq!(out, [0.5, 0.5, 0.5, 0.5, 0.0, 0.0]);
+}